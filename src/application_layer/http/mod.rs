@@ -1,9 +1,6 @@
 extern crate milstian_http;
 
-pub mod response
-{
-    pub use milstian_http::response::*;
-}
+pub mod response;
 
 pub mod request
 {
@@ -0,0 +1,488 @@
+//! # Handles everything related to HTTP responses.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::str;
+
+use response::tcp::Upgrade;
+
+/// Size of chunks when a body is copied to a writer
+pub const CHUNK_SIZE: usize = 8192;
+
+/// # A response body
+/// Bodies that are not buffered in memory are copied to the client in bounded chunks.
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// A file and the number of bytes to send from its current position
+    File(File, u64),
+    /// Any source and its length if it is known up front
+    Stream(Box<dyn Read + Send>, Option<u64>),
+}
+
+impl Body {
+    /// # Create a body from the remainder of a file
+    pub fn from_file(file: File) -> io::Result<Body> {
+        let length = file.metadata()?.len();
+        Ok(Body::File(file, length))
+    }
+
+    /// # Get length of body if it is known
+    /// ```rust
+    /// use milstian_internet_framework::application_layer::http::response::Body;
+    /// use std::io::Cursor;
+    /// assert_eq!(Body::from(b"abc".to_vec()).len(), Some(3));
+    /// assert_eq!(Body::Stream(Box::new(Cursor::new(b"abc".to_vec())), None).len(), None);
+    /// ```
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File(_, length) => Some(*length),
+            Body::Stream(_, length) => *length,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// # Get the bytes of a body that is buffered in memory
+    /// Files and streams are `None` until they are buffered, reading the body never fails here.
+    /// ```rust
+    /// use milstian_internet_framework::application_layer::http::response::Body;
+    /// use std::io::Cursor;
+    /// assert_eq!(Body::from(b"abc".to_vec()).as_bytes(), Some(&b"abc"[..]));
+    /// assert_eq!(Body::Stream(Box::new(Cursor::new(b"abc".to_vec())), None).as_bytes(), None);
+    /// ```
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            Body::File(_, _) | Body::Stream(_, _) => None,
+        }
+    }
+
+    /// # Read a streamed body into memory
+    pub fn buffer(&mut self) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        match self {
+            Body::Bytes(_) => return Ok(()),
+            Body::File(file, length) => {
+                file.take(*length).read_to_end(&mut bytes)?;
+            }
+            Body::Stream(stream, _) => {
+                stream.read_to_end(&mut bytes)?;
+            }
+        }
+        *self = Body::Bytes(bytes);
+        Ok(())
+    }
+
+    /// # Copy body to a writer in bounded chunks, returns number of copied bytes
+    pub fn copy_to<W: Write>(&mut self, writer: &mut W) -> io::Result<u64> {
+        match self {
+            Body::Bytes(bytes) => {
+                for chunk in bytes.chunks(CHUNK_SIZE) {
+                    writer.write_all(chunk)?;
+                }
+                Ok(bytes.len() as u64)
+            }
+            Body::File(file, length) => Body::copy_stream(&mut file.take(*length), writer),
+            Body::Stream(stream, _) => Body::copy_stream(stream, writer),
+        }
+    }
+
+    /// # Turn body into a reader that can be consumed in parts
+    /// ```rust
+    /// use milstian_internet_framework::application_layer::http::response::Body;
+    /// use std::io::Read;
+    /// let mut bytes = Vec::new();
+    /// Body::from(b"abc".to_vec()).into_reader().read_to_end(&mut bytes).unwrap();
+    /// assert_eq!(bytes, b"abc".to_vec());
+    /// ```
+    pub fn into_reader(self) -> Box<dyn Read + Send> {
+        match self {
+            Body::Bytes(bytes) => Box::new(io::Cursor::new(bytes)),
+            Body::File(file, length) => Box::new(file.take(length)),
+            Body::Stream(stream, _) => stream,
+        }
+    }
+
+    fn copy_stream<R: Read + ?Sized, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<u64> {
+        let mut buffer = [0; CHUNK_SIZE];
+        let mut copied: u64 = 0;
+        loop {
+            let read_size = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(copied),
+                Ok(read_size) => read_size,
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+            writer.write_all(&buffer[..read_size])?;
+            copied += read_size as u64;
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => write!(f, "Bytes({:?})", bytes),
+            Body::File(file, length) => write!(f, "File({:?}, {})", file, length),
+            Body::Stream(_, length) => write!(f, "Stream({:?})", length),
+        }
+    }
+}
+
+/// # A response message
+#[derive(Debug)]
+pub struct Message {
+    pub protocol: String,
+    pub status: String,
+    pub headers: HashMap<String, String>,
+    pub body: Body,
+    /// What takes over the connection after the head is written, instead of the body
+    pub upgrade: Option<Box<dyn Upgrade>>,
+}
+
+impl Message {
+    /// # Create a new HTTP Message
+    pub fn new<B: Into<Body>>(
+        protocol: String,
+        status: String,
+        headers: HashMap<String, String>,
+        body: B,
+    ) -> Message {
+        Message {
+            protocol,
+            status,
+            headers,
+            body: body.into(),
+            upgrade: None,
+        }
+    }
+
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    /// # Find a header value by case-insensitive name
+    /// ```rust
+    /// use milstian_internet_framework::application_layer::http::response::Message;
+    /// use std::collections::HashMap;
+    /// let mut headers = HashMap::new();
+    /// headers.insert("Content-Type".to_string(), "text/html".to_string());
+    /// let message = Message::new("HTTP/1.1".to_string(), "200 OK".to_string(), headers, Vec::new());
+    /// assert_eq!(message.get_header("content-type"), Some(&"text/html".to_string()));
+    /// assert_eq!(message.get_header("Content-Length"), None);
+    /// ```
+    pub fn get_header(&self, name: &str) -> Option<&String> {
+        for (key, value) in self.headers.iter() {
+            if key.eq_ignore_ascii_case(name) {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// # Send the body with chunked transfer-coding
+    /// Used when the length of the body is not known when the header is sent.
+    pub fn set_chunked(&mut self) {
+        self.headers.retain(|key, _| {
+            !key.eq_ignore_ascii_case("Content-Length")
+                && !key.eq_ignore_ascii_case("Transfer-Encoding")
+        });
+        self.headers
+            .insert("Transfer-Encoding".to_string(), "chunked".to_string());
+    }
+
+    /// # Whether the body is sent with chunked transfer-coding
+    /// ```rust
+    /// use milstian_internet_framework::application_layer::http::response::Message;
+    /// use std::collections::HashMap;
+    /// let mut message = Message::new("HTTP/1.1".to_string(), "200 OK".to_string(), HashMap::new(), Vec::new());
+    /// assert!(!message.is_chunked());
+    /// message.set_chunked();
+    /// assert!(message.is_chunked());
+    /// ```
+    pub fn is_chunked(&self) -> bool {
+        match self.get_header("Transfer-Encoding") {
+            Some(transfer_encoding) => transfer_encoding
+                .rsplit(',')
+                .next()
+                .map(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    /// # Get the HTTP header as a new string
+    /// ```rust
+    /// use milstian_internet_framework::application_layer::http::response::Message;
+    /// use std::collections::HashMap;
+    /// assert_eq!(
+    ///     Message::new(
+    ///         "HTTP/1.0".to_string(),
+    ///         "200 OK".to_string(),
+    ///         HashMap::new(),
+    ///         b"<html><body>Nothing here</body></html>".to_vec()
+    ///     ).header_to_string(),
+    ///     "HTTP/1.0 200 OK\r\n\r\n".to_string()
+    /// );
+    /// ```
+    pub fn header_to_string(&self) -> String {
+        let mut response = format!("{} {}\r\n", &self.protocol, &self.status);
+
+        if !self.headers.is_empty() {
+            let mut headers: Vec<(&String, &String)> = self.headers.iter().collect();
+            headers.sort();
+            for (key, value) in headers {
+                response.push_str(&format!("{}: {}\r\n", &key, &value));
+            }
+        }
+        response.push_str("\r\n");
+
+        response
+    }
+
+    /// # Convert message into bytes
+    /// Streamed bodies are read into memory first so the message can be converted again,
+    /// which is why the message is mutable. Fails without bytes when the body can not be read.
+    /// ```rust
+    /// use milstian_internet_framework::application_layer::http::response::Message;
+    /// use std::collections::HashMap;
+    /// assert_eq!(
+    ///     Message::new(
+    ///         "HTTP/1.0".to_string(),
+    ///         "200 OK".to_string(),
+    ///         HashMap::new(),
+    ///         b"<html><body>Nothing here</body></html>".to_vec()
+    ///     ).to_bytes().unwrap(),
+    ///     b"HTTP/1.0 200 OK\r\n\r\n<html><body>Nothing here</body></html>".to_vec()
+    /// );
+    /// ```
+    /// ## Chunked
+    /// ```rust
+    /// use milstian_internet_framework::application_layer::http::response::Message;
+    /// use std::collections::HashMap;
+    /// let mut message = Message::new(
+    ///     "HTTP/1.1".to_string(),
+    ///     "200 OK".to_string(),
+    ///     HashMap::new(),
+    ///     b"<html><body>Nothing here</body></html>".to_vec()
+    /// );
+    /// message.set_chunked();
+    /// assert_eq!(
+    ///     message.to_bytes().unwrap(),
+    ///     b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n26\r\n<html><body>Nothing here</body></html>\r\n0\r\n\r\n".to_vec()
+    /// );
+    /// ```
+    pub fn to_bytes(&mut self) -> io::Result<Vec<u8>> {
+        // A head is only built for a body that could be read
+        self.body.buffer()?;
+        let mut response = self.header_to_string().into_bytes();
+        self.write_body_to(&mut response)?;
+        Ok(response)
+    }
+
+    /// # Write the body to a writer, with chunked transfer-coding if specified
+    pub fn write_body_to<W: Write>(&mut self, writer: &mut W) -> io::Result<u64> {
+        if self.is_chunked() {
+            let mut chunked = ChunkedWriter::new(writer);
+            let copied = self.body.copy_to(&mut chunked)?;
+            chunked.finish()?;
+            return Ok(copied);
+        }
+        self.body.copy_to(writer)
+    }
+}
+
+/// # Writes data with chunked transfer-coding
+/// Every write becomes one chunk, `finish` writes the last chunk.
+/// ```rust
+/// use milstian_internet_framework::application_layer::http::response::ChunkedWriter;
+/// use std::io::Write;
+/// let mut writer = ChunkedWriter::new(Vec::new());
+/// writer.write_all(b"Hello world!").unwrap();
+/// writer.finish().unwrap();
+/// assert_eq!(writer.into_inner(), b"c\r\nHello world!\r\n0\r\n\r\n".to_vec());
+/// ```
+pub struct ChunkedWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(writer: W) -> ChunkedWriter<W> {
+        ChunkedWriter { writer }
+    }
+
+    /// Write the last chunk that terminates the body
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.write_all(b"0\r\n\r\n")?;
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // An empty chunk would terminate the body
+        if data.is_empty() {
+            return Ok(0);
+        }
+        self.writer
+            .write_all(format!("{:x}\r\n", data.len()).as_bytes())?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(b"\r\n")?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// # Convert response message into a string
+/// ```rust
+/// use milstian_internet_framework::application_layer::http::response::Message;
+/// use std::collections::HashMap;
+/// assert_eq!(
+///     Message::new(
+///         "HTTP/1.0".to_string(),
+///         "200 OK".to_string(),
+///         HashMap::new(),
+///         b"<html><body>Nothing here</body></html>".to_vec()
+///     ).to_string(),
+///     "HTTP/1.0 200 OK\r\n\r\n<html><body>Nothing here</body></html>".to_string()
+/// );
+/// ```
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.header_to_string())?;
+        if let Body::Bytes(ref bytes) = self.body {
+            if let Ok(body_string) = str::from_utf8(bytes) {
+                write!(f, "{}", body_string)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    /// A stream that returns its data and then fails
+    struct Failing {
+        data: Cursor<Vec<u8>>,
+    }
+
+    impl Read for Failing {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let size = self.data.read(buffer)?;
+            if size > 0 {
+                return Ok(size);
+            }
+            Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "Source failed",
+            ))
+        }
+    }
+
+    fn get_message<B: Into<Body>>(body: B) -> Message {
+        Message::new(
+            "HTTP/1.1".to_string(),
+            "200 OK".to_string(),
+            HashMap::new(),
+            body,
+        )
+    }
+
+    #[test]
+    fn test_file_body() {
+        let path = env::temp_dir().join(format!("milstian-body-{}", ::std::process::id()));
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        fs::write(&path, &data).unwrap();
+
+        // Only the remainder of the file from its current position is sent
+        let mut file = File::open(&path).unwrap();
+        file.seek(SeekFrom::Start(10)).unwrap();
+        let mut body = Body::File(file, CHUNK_SIZE as u64 * 2);
+        assert_eq!(body.len(), Some(CHUNK_SIZE as u64 * 2));
+        let mut copied = Vec::new();
+        assert_eq!(body.copy_to(&mut copied).unwrap(), CHUNK_SIZE as u64 * 2);
+        assert_eq!(copied, data[10..].to_vec());
+
+        let mut message = get_message(Body::from_file(File::open(&path).unwrap()).unwrap());
+        let mut expected = b"HTTP/1.1 200 OK\r\n\r\n".to_vec();
+        expected.extend_from_slice(&data);
+        assert_eq!(message.to_bytes().unwrap(), expected);
+        // The body was buffered so the message converts the same again
+        assert_eq!(message.to_bytes().unwrap(), expected);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_stream_body() {
+        let data = vec![b'a'; CHUNK_SIZE + 1];
+        let mut chunked = format!("{:x}\r\n", CHUNK_SIZE).into_bytes();
+        chunked.extend_from_slice(&data[..CHUNK_SIZE]);
+        chunked.extend_from_slice(b"\r\n1\r\na\r\n0\r\n\r\n");
+
+        // Streams are copied in bounded chunks
+        let mut message = get_message(Body::Stream(Box::new(Cursor::new(data.clone())), None));
+        assert_eq!(message.body.len(), None);
+        message.set_chunked();
+        let mut written = Vec::new();
+        assert_eq!(
+            message.write_body_to(&mut written).unwrap(),
+            CHUNK_SIZE as u64 + 1
+        );
+        assert_eq!(written, chunked);
+
+        let mut message = get_message(Body::Stream(Box::new(Cursor::new(data)), None));
+        message.set_chunked();
+        let mut expected = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        expected.extend_from_slice(&chunked);
+        assert_eq!(message.to_bytes().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_failing_stream() {
+        let failing = || Failing {
+            data: Cursor::new(b"abc".to_vec()),
+        };
+
+        // No head is built for a body that can not be read
+        let mut message = get_message(Body::Stream(Box::new(failing()), Some(3)));
+        message
+            .headers
+            .insert("Content-Length".to_string(), "3".to_string());
+        assert_eq!(
+            message.to_bytes().unwrap_err().kind(),
+            io::ErrorKind::ConnectionReset
+        );
+
+        // The last chunk is not written after a failed read
+        let mut message = get_message(Body::Stream(Box::new(failing()), None));
+        message.set_chunked();
+        let mut written = Vec::new();
+        assert!(message.write_body_to(&mut written).is_err());
+        assert_eq!(written, b"3\r\nabc\r\n".to_vec());
+    }
+}
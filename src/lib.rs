@@ -21,23 +21,39 @@
 //! extern crate milstian_internet_framework;
 //! ```
 
+extern crate libc;
 extern crate milstian_feedback;
 extern crate milstian_http;
+extern crate mio;
+#[cfg(test)]
+extern crate rcgen;
+extern crate rustls;
+extern crate rustls_pemfile;
+extern crate signal_hook;
 
 pub mod application_layer;
+pub mod metrics;
 pub mod mime;
 pub mod response;
+pub mod state;
 mod thread;
 pub mod transport_layer;
 
 extern crate chrono;
 
+use std::any::Any;
 use std::env;
 use std::fs;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
 
+use metrics::Metrics;
 use milstian_feedback::Feedback;
-use response::tcp::http::{error, file_not_found, filesystem, ResponderInterface};
+use response::tcp::http::{
+    error, file_not_found, filesystem, MiddlewareInterface, ResponderInterface,
+};
+use state::State;
 
 #[derive(Clone, Debug)]
 /// # Holds application configuration, can be created in different ways.
@@ -55,8 +71,112 @@ pub struct Config {
     pub filesystem_root: String,
     pub server_limit: usize,
     pub server_host: String,
+    /// Seconds a client may take to send the message body of a request, 0 disables it
+    pub server_body_timeout: u64,
+    /// Seconds a client may take to send the request line and header fields, 0 disables it
+    pub server_header_timeout: u64,
+    /// Maximum number of requests served over one persistent connection
+    pub server_keep_alive_max: usize,
+    /// Seconds a persistent connection may be idle between requests
+    pub server_keep_alive_timeout: u64,
+    /// What to do with new connections when the job queue is full
+    pub server_overload_policy: OverloadPolicy,
+    /// Whether connections start with a PROXY protocol header that tells the client address
+    pub server_proxy_protocol: bool,
     pub server_port: u32,
+    /// Maximum number of connections waiting for a worker
+    pub server_queue_limit: usize,
+    /// Seconds a request may take from its first byte to the end of its response, 0 disables it.
+    /// Only reading and writing the socket is bounded, a responder may run for longer.
+    pub server_request_timeout: u64,
+    /// Seconds in-flight requests may take to finish after shutdown was requested
+    pub server_shutdown_timeout: u64,
+    /// How connections are read and written
+    pub server_transport: Transport,
+    /// Addresses of reverse proxies whose forwarding header fields are trusted
+    pub server_trusted_proxies: Vec<IpAddr>,
+    /// Seconds a single write of a response may block, 0 disables it
+    pub server_write_timeout: u64,
     pub tcp_limit: usize,
+    /// Application protocols offered with ALPN in order of preference, `h2` enables HTTP/2
+    pub tls_alpn_protocols: Vec<String>,
+    /// Certificates for HTTPS, the first one is used when no other one matches the SNI name
+    pub tls_certificates: Vec<TlsCertificate>,
+    /// Strict-Transport-Security sent on responses to requests that arrived over HTTPS
+    pub tls_hsts: Option<Hsts>,
+    /// Port HTTPS is served on, used when redirecting from HTTP
+    pub tls_port: u32,
+    /// Group ID that owns the Unix domain socket
+    pub unix_socket_group: Option<u32>,
+    /// File permission bits of the Unix domain socket like `0o660`
+    pub unix_socket_mode: Option<u32>,
+    /// Path of the Unix domain socket to listen on instead of a TCP port
+    pub unix_socket_path: Option<PathBuf>,
+    /// User ID that owns the Unix domain socket
+    pub unix_socket_user: Option<u32>,
+}
+
+/// What the server does with new connections while all workers are busy and the job queue is full
+#[derive(Clone, Debug, PartialEq)]
+pub enum OverloadPolicy {
+    /// Stop accepting connections until there is room in the queue
+    Block,
+    /// Respond 503 Service Unavailable with a Retry-After of this many seconds, TLS
+    /// connections are closed instead
+    ServiceUnavailable(u64),
+    /// Close the connection without a response
+    Close,
+}
+
+/// A PEM certificate chain and private key served to clients that ask for a name with SNI
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsCertificate {
+    /// Host name like `example.com` or a wildcard like `*.example.com`
+    pub server_name: String,
+    pub certificate_file: String,
+    pub key_file: String,
+}
+
+/// The HTTP Strict Transport Security policy of a server
+/// ```rust
+/// use milstian_internet_framework::Hsts;
+/// let hsts = Hsts {
+///     max_age: 31536000,
+///     include_subdomains: true,
+///     preload: false,
+/// };
+/// assert_eq!(hsts.to_header_value(), "max-age=31536000; includeSubDomains");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hsts {
+    /// Seconds clients should only use HTTPS for the host
+    pub max_age: u64,
+    pub include_subdomains: bool,
+    pub preload: bool,
+}
+
+impl Hsts {
+    pub fn to_header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age);
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+/// How the server multiplexes connections
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transport {
+    /// Every connection occupies a worker for as long as it stays open
+    Blocking,
+    /// This many event loops read and write all connections with non-blocking sockets,
+    /// workers are only occupied by framed requests, only HTTP/1.x without WebSocket or
+    /// server-sent events is supported
+    Evented(usize),
 }
 
 impl Config {
@@ -105,8 +225,6 @@ impl Config {
             Err(_) => return Err("Failed to parse TCP limit!".to_string()),
         };
         Ok(Config {
-            feedback_error_file: Option::None,
-            feedback_info_file: Option::None,
             filesystem_directory_index,
             file_not_found_file,
             filesystem_root,
@@ -114,9 +232,48 @@ impl Config {
             server_host,
             server_port,
             tcp_limit,
+            ..Config::default()
         })
     }
 
+    /// Whether forwarding header fields from an address can be trusted
+    pub fn is_trusted_proxy(&self, address: &IpAddr) -> bool {
+        self.server_trusted_proxies.contains(address)
+    }
+
+    /// Whether forwarding header fields from a peer can be trusted, peers of a Unix domain
+    /// socket are always trusted since the file permissions of the socket decide who connects.
+    /// Clients resolved from forwarding header fields are not trusted.
+    /// ```rust
+    /// use milstian_internet_framework::transport_layer::Address;
+    /// use milstian_internet_framework::Config;
+    /// let mut config = Config::from_env_args(vec![
+    ///     "".to_string(),
+    ///     "127.0.0.1".to_string(),
+    ///     "0".to_string(),
+    ///     "4".to_string(),
+    ///     "index.htm".to_string(),
+    ///     "./html/".to_string(),
+    ///     "404.htm".to_string(),
+    ///     "1024".to_string(),
+    /// ]).unwrap();
+    /// let proxy = Address::Tcp("10.0.0.1:8080".parse().unwrap());
+    /// assert!(!config.is_trusted_peer(&proxy));
+    /// config.server_trusted_proxies.push("10.0.0.1".parse().unwrap());
+    /// assert!(config.is_trusted_peer(&proxy));
+    /// assert!(config.is_trusted_peer(&Address::Unix(None)));
+    /// assert!(!config.is_trusted_peer(&Address::Forwarded("10.0.0.1".parse().unwrap())));
+    /// ```
+    pub fn is_trusted_peer(&self, address: &transport_layer::Address) -> bool {
+        match address {
+            transport_layer::Address::Forwarded(_) => false,
+            _ => match address.ip() {
+                Some(ip) => self.is_trusted_proxy(&ip),
+                None => true,
+            },
+        }
+    }
+
     /// This method collects arguments from environment and passes them on to method from_env_args
     /// # Example
     /// ```rust
@@ -129,26 +286,90 @@ impl Config {
     }
 }
 
+/// The settings of `from_env_args` for the fields it does not take as argument, so a config
+/// can be written as a struct literal that only sets some fields. The filesystem responders
+/// serve nothing until `filesystem_root` is set to a path from `get_canonical_root`.
+/// ```rust
+/// use milstian_internet_framework::Config;
+/// let config = Config {
+///     server_port: 8080,
+///     ..Config::default()
+/// };
+/// assert_eq!(config.server_limit, 4);
+/// ```
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            feedback_error_file: Option::None,
+            feedback_info_file: Option::None,
+            filesystem_directory_index: "index.htm".to_string(),
+            file_not_found_file: "404.htm".to_string(),
+            filesystem_root: ".".to_string(),
+            server_limit: 4,
+            server_host: "localhost".to_string(),
+            server_body_timeout: 60,
+            server_header_timeout: 10,
+            server_keep_alive_max: 100,
+            server_keep_alive_timeout: 5,
+            server_overload_policy: OverloadPolicy::Block,
+            server_proxy_protocol: false,
+            server_port: 8888,
+            server_queue_limit: 128,
+            server_request_timeout: 0,
+            server_shutdown_timeout: 30,
+            server_transport: Transport::Blocking,
+            server_trusted_proxies: Vec::new(),
+            server_write_timeout: 30,
+            tcp_limit: 1024,
+            tls_alpn_protocols: vec!["http/1.1".to_string()],
+            tls_certificates: Vec::new(),
+            tls_hsts: None,
+            tls_port: 443,
+            unix_socket_group: None,
+            unix_socket_mode: None,
+            unix_socket_path: None,
+            unix_socket_user: None,
+        }
+    }
+}
+
+/// Config of the tests, serves ./html/ on any free port of the loopback address
+#[cfg(test)]
+pub(crate) fn test_config() -> Config {
+    Config {
+        filesystem_root: Config::get_canonical_root(&"./html/".to_string()).unwrap(),
+        server_host: "127.0.0.1".to_string(),
+        server_port: 0,
+        ..Config::default()
+    }
+}
+
 /// # Main entry point for a new application.
 /// Could in the future support multiple transport layers and application layers.
 /// ## TCP HTTP static application:
 /// ```rust,should_panic
 /// use milstian_internet_framework::{Application, Config};
 /// let config = Config::from_env().expect("Failed to get configuration from environment");
-/// Application::new(config).tcp_http_with_legacy_responders();
+/// Application::new(config).tcp_http_with_legacy_responders().unwrap();
 /// ```
 #[derive(Clone, Debug)]
 pub struct Application {
-    config: Config,
+    config: Arc<Config>,
     feedback: Feedback,
+    metrics: Arc<Metrics>,
+    middleware: Arc<Vec<Arc<dyn MiddlewareInterface>>>,
+    state: Arc<State>,
 }
 
 impl Application {
     pub fn new(config: Config) -> Application {
         let feedback = Feedback::new(config.feedback_error_file.clone(), config.feedback_info_file.clone());
         Application {
-            config,
+            config: Arc::new(config),
             feedback,
+            metrics: Arc::new(Metrics::new()),
+            middleware: Arc::new(Vec::new()),
+            state: Arc::new(State::new()),
         }
     }
 
@@ -160,6 +381,154 @@ impl Application {
         &self.feedback
     }
 
+    pub fn get_metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn get_middleware(&self) -> &[Arc<dyn MiddlewareInterface>] {
+        &self.middleware
+    }
+
+    /// Get the shared state of a type
+    pub fn get_state<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.state.get()
+    }
+
+    /// A copy of the application with another configuration, the feedback and metrics are
+    /// shared with this application
+    pub fn with_config(&self, config: Config) -> Application {
+        let mut application = self.clone();
+        application.config = Arc::new(config);
+        application
+    }
+
+    /// A copy of the application with shared state that every responder and middleware can
+    /// get by its type, replaces the state of the same type
+    /// # Example
+    /// ```rust
+    /// extern crate milstian_internet_framework;
+    /// use milstian_internet_framework::{Application, Config};
+    /// struct Pool {
+    ///     size: usize,
+    /// }
+    /// fn main() {
+    ///     let config = Config::from_env_args(vec![
+    ///         "".to_string(),
+    ///         "127.0.0.1".to_string(),
+    ///         "0".to_string(),
+    ///         "4".to_string(),
+    ///         "index.htm".to_string(),
+    ///         "./html/".to_string(),
+    ///         "404.htm".to_string(),
+    ///         "1024".to_string(),
+    ///     ]).unwrap();
+    ///     let application = Application::new(config).with_state(Pool { size: 4 });
+    ///     assert_eq!(application.get_state::<Pool>().unwrap().size, 4);
+    /// }
+    /// ```
+    pub fn with_state<T: Any + Send + Sync>(&self, value: T) -> Application {
+        let mut state = (*self.state).clone();
+        state.insert(value);
+        let mut application = self.clone();
+        application.state = Arc::new(state);
+        application
+    }
+
+    /// A copy of the application with middleware that runs around the responders of every
+    /// request, middleware runs in the order it was added
+    /// # Example
+    /// ```rust
+    /// extern crate milstian_internet_framework;
+    /// use std::sync::Arc;
+    /// use milstian_internet_framework::application_layer::http::{request, response};
+    /// use milstian_internet_framework::response::tcp::http::{Context, MiddlewareInterface};
+    /// use milstian_internet_framework::transport_layer::Address;
+    /// use milstian_internet_framework::{Application, Config};
+    /// struct Server {}
+    /// impl MiddlewareInterface for Server {
+    ///     fn after(
+    ///         &self,
+    ///         _request_message: &request::Message,
+    ///         response_message: &mut response::Message,
+    ///         _context: &mut Context,
+    ///         _application: &Application,
+    ///         _socket: &Address,
+    ///     ) {
+    ///         response_message
+    ///             .headers
+    ///             .insert("Server".to_string(), "Milstian".to_string());
+    ///     }
+    /// }
+    /// fn main() {
+    ///     let config = Config::from_env_args(vec![
+    ///         "".to_string(),
+    ///         "127.0.0.1".to_string(),
+    ///         "0".to_string(),
+    ///         "4".to_string(),
+    ///         "index.htm".to_string(),
+    ///         "./html/".to_string(),
+    ///         "404.htm".to_string(),
+    ///         "1024".to_string(),
+    ///     ]).unwrap();
+    ///     let application = Application::new(config).with_middleware(Arc::new(Server {}));
+    ///     assert_eq!(application.get_middleware().len(), 1);
+    /// }
+    /// ```
+    pub fn with_middleware(&self, middleware: Arc<dyn MiddlewareInterface>) -> Application {
+        let mut list = self.middleware.to_vec();
+        list.push(middleware);
+        let mut application = self.clone();
+        application.middleware = Arc::new(list);
+        application
+    }
+
+    /// Create a new HTTP application that serves several listeners with one worker pool,
+    /// like IPv4 and IPv6 on the same port or HTTP next to HTTPS
+    /// # Example
+    /// ```rust,should_panic
+    /// extern crate milstian_internet_framework;
+    /// use milstian_internet_framework::{Application, Config};
+    /// use milstian_internet_framework::response::tcp::http::{error, filesystem, https_redirect};
+    /// use milstian_internet_framework::transport_layer::{Endpoint, Listener};
+    /// fn main() {
+    ///     let config = Config::from_env().expect("Failed to get configuration from environment");
+    ///     Application::new(config)
+    ///         .http(vec![
+    ///             Listener::new(
+    ///                 Endpoint::Tcp("0.0.0.0:80".to_string()),
+    ///                 vec![
+    ///                     Box::new(https_redirect::Responder::new()),
+    ///                     Box::new(error::Responder::new()),
+    ///                 ],
+    ///             ),
+    ///             Listener::new(
+    ///                 Endpoint::Tls("0.0.0.0:443".to_string()),
+    ///                 vec![
+    ///                     Box::new(filesystem::Responder::new()),
+    ///                     Box::new(error::Responder::new()),
+    ///                 ],
+    ///             ),
+    ///         ])
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub fn http(
+        &self,
+        listeners: Vec<transport_layer::Listener>,
+    ) -> Result<(), transport_layer::Error> {
+        let server = transport_layer::Server::bind(&self, listeners)?;
+        server.shutdown_on_signals()?;
+        server.run()
+    }
+
+    /// Bind several listeners that share one worker pool without running them
+    pub fn http_bind(
+        &self,
+        listeners: Vec<transport_layer::Listener>,
+    ) -> Result<transport_layer::Server, transport_layer::Error> {
+        transport_layer::Server::bind(&self, listeners)
+    }
+
     /// Create a new TCP HTTP application
     /// # Example
     /// ```rust,should_panic
@@ -167,19 +536,108 @@ impl Application {
     /// use milstian_internet_framework::{Application, Config};
     /// use milstian_internet_framework::response::tcp::http::{error, file_not_found, filesystem, ResponderInterface};
     /// fn main() {
-    ///     let responders: Vec<Box<ResponderInterface + Send>> = vec![
+    ///     let responders: Vec<Box<dyn ResponderInterface>> = vec![
     ///         Box::new(filesystem::Responder::new()),
     ///         Box::new(error::Responder::new()),
     ///     ];
     ///     let config = Config::from_env().expect("Failed to get configuration from environment");
-    ///     Application::new(config).tcp_http(responders);
+    ///     Application::new(config).tcp_http(responders).unwrap();
     /// }
     /// ```
     // TODO Use example that doesn't panic
-    pub fn tcp_http(&self, responders: Vec<Box<ResponderInterface + Send>>) {
+    pub fn tcp_http(
+        &self,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<(), transport_layer::Error> {
         transport_layer::TCP::http(&self, responders)
     }
 
+    /// Bind a new TCP HTTP server without running it, the server handle exposes the bound
+    /// address and can shut the server down
+    /// # Example
+    /// ```rust
+    /// extern crate milstian_internet_framework;
+    /// use milstian_internet_framework::{Application, Config};
+    /// use milstian_internet_framework::response::tcp::http::{error, ResponderInterface};
+    /// fn main() {
+    ///     let config = Config::from_env_args(vec![
+    ///         "".to_string(),
+    ///         "127.0.0.1".to_string(),
+    ///         "0".to_string(),
+    ///         "4".to_string(),
+    ///         "index.htm".to_string(),
+    ///         "./html/".to_string(),
+    ///         "404.htm".to_string(),
+    ///         "1024".to_string(),
+    ///     ]).unwrap();
+    ///     let responders: Vec<Box<dyn ResponderInterface>> =
+    ///         vec![Box::new(error::Responder::new())];
+    ///     let server = Application::new(config).tcp_http_bind(responders).unwrap();
+    ///     assert_ne!(server.get_local_addr().unwrap().port(), 0);
+    /// }
+    /// ```
+    pub fn tcp_http_bind(
+        &self,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<transport_layer::Server, transport_layer::Error> {
+        transport_layer::TCP::bind(&self, responders)
+    }
+
+    /// Create a new TCP HTTP application that redirects every request to HTTPS
+    pub fn tcp_https_redirect(&self) -> Result<(), transport_layer::Error> {
+        transport_layer::TCP::https_redirect(&self)
+    }
+
+    /// Create a new HTTPS application with the certificates of the configuration, SIGHUP
+    /// reloads the certificates
+    /// # Example
+    /// ```rust,should_panic
+    /// extern crate milstian_internet_framework;
+    /// use milstian_internet_framework::{Application, Config};
+    /// use milstian_internet_framework::response::tcp::http::{error, filesystem, ResponderInterface};
+    /// fn main() {
+    ///     let responders: Vec<Box<dyn ResponderInterface>> = vec![
+    ///         Box::new(filesystem::Responder::new()),
+    ///         Box::new(error::Responder::new()),
+    ///     ];
+    ///     let config = Config::from_env().expect("Failed to get configuration from environment");
+    ///     Application::new(config).tls_http(responders).unwrap();
+    /// }
+    /// ```
+    pub fn tls_http(
+        &self,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<(), transport_layer::Error> {
+        transport_layer::TLS::http(&self, responders)
+    }
+
+    /// Bind a new HTTPS server without running it, the server handle can also reload the
+    /// certificates
+    pub fn tls_http_bind(
+        &self,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<transport_layer::Server, transport_layer::Error> {
+        transport_layer::TLS::bind(&self, responders)
+    }
+
+    /// Create a new HTTP application on the Unix domain socket at `unix_socket_path`, for
+    /// reverse proxies on the same host
+    pub fn unix_http(
+        &self,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<(), transport_layer::Error> {
+        transport_layer::Unix::http(&self, responders)
+    }
+
+    /// Bind a new HTTP server to the Unix domain socket at `unix_socket_path` without
+    /// running it
+    pub fn unix_http_bind(
+        &self,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<transport_layer::Server, transport_layer::Error> {
+        transport_layer::Unix::bind(&self, responders)
+    }
+
     /// Create a new TCP HTTP application with the legacy responders
     /// # Example
     /// ```rust,should_panic
@@ -187,12 +645,12 @@ impl Application {
     /// use milstian_internet_framework::{Application, Config};
     /// fn main() {
     ///     let config = Config::from_env().expect("Failed to get configuration from environment");
-    ///     Application::new(config).tcp_http_with_legacy_responders();
+    ///     Application::new(config).tcp_http_with_legacy_responders().unwrap();
     /// }
     /// ```
     // TODO Use example that doesn't panic
-    pub fn tcp_http_with_legacy_responders(&self) {
-        let responders: Vec<Box<ResponderInterface + Send>> = vec![
+    pub fn tcp_http_with_legacy_responders(&self) -> Result<(), transport_layer::Error> {
+        let responders: Vec<Box<dyn ResponderInterface>> = vec![
             Box::new(filesystem::Responder::new()),
             Box::new(file_not_found::Responder::new()),
             Box::new(error::Responder::new()),
@@ -205,15 +663,15 @@ impl Application {
     /// use milstian_internet_framework::{Application, Config};
     /// fn main() {
     ///     let config = Config::from_env().expect("Failed to get configuration from environment");
-    ///     Application::new(config).tcp_http_with_legacy_responders();
+    ///     Application::new(config).tcp_http_with_legacy_responders().unwrap();
     /// }
     /// ```
     // TODO Use example that doesn't panic
     pub fn tcp_http_with_legacy_and_custom_responders(
         &self,
-        custom: Box<ResponderInterface + Send>,
-    ) {
-        let responders: Vec<Box<ResponderInterface + Send>> = vec![
+        custom: Box<dyn ResponderInterface>,
+    ) -> Result<(), transport_layer::Error> {
+        let responders: Vec<Box<dyn ResponderInterface>> = vec![
             custom,
             Box::new(filesystem::Responder::new()),
             Box::new(file_not_found::Responder::new()),
@@ -0,0 +1,58 @@
+//! # Counters for monitoring
+//! Shared by all clones of an application.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The kinds of time-outs that are counted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timeout {
+    /// Request line and header fields were not received in time
+    Header,
+    /// Message body was not received in time
+    Body,
+    /// Response could not be written in time
+    Write,
+    /// The whole request was not served in time
+    Request,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    header_timeouts: AtomicUsize,
+    body_timeouts: AtomicUsize,
+    write_timeouts: AtomicUsize,
+    request_timeouts: AtomicUsize,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    fn get_timeout_counter(&self, timeout: Timeout) -> &AtomicUsize {
+        match timeout {
+            Timeout::Header => &self.header_timeouts,
+            Timeout::Body => &self.body_timeouts,
+            Timeout::Write => &self.write_timeouts,
+            Timeout::Request => &self.request_timeouts,
+        }
+    }
+
+    /// Count a time-out of a kind
+    pub fn add_timeout(&self, timeout: Timeout) {
+        self.get_timeout_counter(timeout)
+            .fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Number of time-outs of a kind so far
+    /// ```rust
+    /// use milstian_internet_framework::metrics::{Metrics, Timeout};
+    /// let metrics = Metrics::new();
+    /// metrics.add_timeout(Timeout::Header);
+    /// assert_eq!(metrics.get_timeouts(Timeout::Header), 1);
+    /// assert_eq!(metrics.get_timeouts(Timeout::Write), 0);
+    /// ```
+    pub fn get_timeouts(&self, timeout: Timeout) -> usize {
+        self.get_timeout_counter(timeout).load(Ordering::SeqCst)
+    }
+}
@@ -5,12 +5,11 @@ use application_layer::http::request;
 use application_layer::http::response;
 
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use transport_layer::Address;
 use Application;
 
-use response::tcp::http::ResponderInterface;
+use response::tcp::http::{Context, HandlerInterface, ResponderInterface};
 
-#[derive(Clone)]
 pub struct Responder {}
 
 impl Responder {
@@ -21,21 +20,22 @@ impl Responder {
 
 impl ResponderInterface for Responder {
     fn matches(
-        &mut self,
+        &self,
         _request_message: &request::Message,
         _application: &Application,
-        _socket: &SocketAddr,
-        _overflow_bytes: &u64,
-    ) -> bool {
-        true
+        _socket: &Address,
+    ) -> Option<Box<dyn HandlerInterface>> {
+        Some(Box::new(Responder {}))
     }
+}
 
+impl HandlerInterface for Responder {
     fn respond(
-        &self,
+        self: Box<Self>,
         request_message: &request::Message,
+        _context: &Context,
         _application: &Application,
-        _socket: &SocketAddr,
-        _overflow_bytes: &u64,
+        _socket: &Address,
     ) -> Result<response::Message, String> {
         let status_code = "500 Internal Server Error";
         let protocol = request::Message::get_protocol_text(&request_message.request_line.protocol);
@@ -57,80 +57,47 @@ mod tests {
     use super::*;
 
     use std::collections::HashMap;
-    use std::net::{IpAddr, Ipv4Addr};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
     use application_layer::http::response;
 
-    use Config;
+    use test_config;
 
     #[test]
     fn test_matches() {
-        let config = Config {
-            feedback_error_file: Option::None,
-            feedback_info_file: Option::None,
-            filesystem_directory_index: "index.htm".to_string(),
-            file_not_found_file: "404.htm".to_string(),
-            filesystem_root: Config::get_canonical_root(&"./html/".to_string()).unwrap(),
-            server_host: "localhost".to_string(),
-            server_limit: 4,
-            server_port: 4040,
-            tcp_limit: 1024,
-        };
+        let config = test_config();
         let application = Application::new(config);
-        let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
-        let mut responder = Responder::new();
-        assert!(
-            responder.matches(
-                &request::Message::from_tcp_stream(b"GET /index2.htm HTTP/1.0")
-                    .expect("Expecting index2.htm response"),
-                &application,
-                &socket,
-                &0
-            )
-        );
-        assert!(
-            responder.matches(
-                &request::Message::from_tcp_stream(b"GET /index3.htm HTTP/1.0")
-                    .expect("Expecting index3.htm response"),
-                &application,
-                &socket,
-                &0
-            )
-        );
-        assert!(
-            responder.matches(
-                &request::Message::from_tcp_stream(b"GET /index.htm HTTP/1.1")
-                    .expect("Expecting index.htm response"),
-                &application,
-                &socket,
-                &0
-            )
-        );
+        let socket = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        ));
+        let responder = Responder::new();
+        let request = request::Message::from_tcp_stream(b"GET /index2.htm HTTP/1.0")
+            .expect("Expecting index2.htm response");
+        assert!(responder.matches(&request, &application, &socket).is_some());
+        let request = request::Message::from_tcp_stream(b"GET /index3.htm HTTP/1.0")
+            .expect("Expecting index3.htm response");
+        assert!(responder.matches(&request, &application, &socket).is_some());
+        let request = request::Message::from_tcp_stream(b"GET /index.htm HTTP/1.1")
+            .expect("Expecting index.htm response");
+        assert!(responder.matches(&request, &application, &socket).is_some());
     }
 
     #[test]
     fn test_respond() {
-        let config = Config {
-            feedback_error_file: Option::None,
-            feedback_info_file: Option::None,
-            filesystem_directory_index: "index.htm".to_string(),
-            file_not_found_file: "404.htm".to_string(),
-            filesystem_root: Config::get_canonical_root(&"./html/".to_string()).unwrap(),
-            server_host: "localhost".to_string(),
-            server_limit: 4,
-            server_port: 4040,
-            tcp_limit: 1024,
-        };
+        let config = test_config();
         let application = Application::new(config);
-        let mut responder = Responder::new();
-        let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let responder = Responder::new();
+        let socket = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        ));
 
         // Build response body
         let response_body = String::new();
         let request =
             request::Message::from_tcp_stream(b"GET /index2.htm HTTP/1.1\r\n\r\n").unwrap();
-        let matches = responder.matches(&request, &application, &socket, &0);
-        assert!(matches);
+        let handler = responder.matches(&request, &application, &socket).unwrap();
 
         let headers: HashMap<String, String> = HashMap::new();
 
@@ -139,12 +106,13 @@ mod tests {
             "500 Internal Server Error".to_string(),
             headers,
             response_body.into_bytes(),
-        ).to_bytes();
+        ).to_bytes().unwrap();
 
-        let given_response = responder
-            .respond(&request, &application, &socket, &0)
+        let given_response = handler
+            .respond(&request, &Context::new(), &application, &socket)
             .unwrap()
-            .to_bytes();
+            .to_bytes()
+            .unwrap();
         assert_eq!(expected_response, given_response);
     }
 }
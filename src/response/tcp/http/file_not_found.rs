@@ -1,35 +1,36 @@
 //! # TCP HTTP File not found Response
 //! Used for displaying that a resource was not found on the server.
 
-use std::net::SocketAddr;
 use std::path::Path;
 
 use application_layer::http::request;
 use application_layer::http::response;
 
 use response::tcp::http::filesystem;
-use response::tcp::http::ResponderInterface;
+use response::tcp::http::{Context, HandlerInterface, ResponderInterface};
+use transport_layer::Address;
 use Application;
 
-#[derive(Clone)]
-pub struct Responder {
-    pub filename: Option<String>,
-}
+pub struct Responder {}
 
 impl Responder {
     pub fn new() -> Responder {
-        Responder { filename: None }
+        Responder {}
     }
 }
 
+/// Responds with the file not found file
+struct Handler {
+    filename: String,
+}
+
 impl ResponderInterface for Responder {
     fn matches(
-        &mut self,
+        &self,
         _request_message: &request::Message,
         application: &Application,
-        _socket: &SocketAddr,
-        _overflow_bytes: &u64,
-    ) -> bool {
+        _socket: &Address,
+    ) -> Option<Box<dyn HandlerInterface>> {
         let filename = format!(
             "{}/{}",
             application.get_config().filesystem_root,
@@ -45,25 +46,25 @@ impl ResponderInterface for Responder {
         } else {
             eprintln!("File not found file does not exists {}", &filename);
         }
-        self.filename = Some(filename);
-        return exists && !is_dir;
+        if exists && !is_dir {
+            return Some(Box::new(Handler { filename }));
+        }
+        None
     }
+}
 
+impl HandlerInterface for Handler {
     fn respond(
-        &self,
+        self: Box<Self>,
         request_message: &request::Message,
+        _context: &Context,
         application: &Application,
-        _socket: &SocketAddr,
-        _overflow_bytes: &u64,
+        _socket: &Address,
     ) -> Result<response::Message, String> {
-        if let Some(filename) = &self.filename {
-            let mut response =
-                filesystem::Responder::get_response(filename, &request_message, &application)?;
-            response.status = "404 File Not Found".to_string();
-            return Ok(response);
-        } else {
-            return Err("Error: File Not Found Filename missing".to_string());
-        }
+        let mut response =
+            filesystem::Responder::get_response(&self.filename, &request_message, &application)?;
+        response.set_status("404 File Not Found".to_string());
+        Ok(response)
     }
 }
 
@@ -75,86 +76,49 @@ mod tests {
     use std::fs;
     use std::fs::File;
     use std::io::prelude::*;
-    use std::net::{IpAddr, Ipv4Addr};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
     use std::time::Duration;
 
     use application_layer::http::response;
     use mime;
 
-    use Config;
+    use {test_config, Config};
 
     #[test]
     fn matches() {
-        let config = Config {
-            feedback_error_file: Option::None,
-            feedback_info_file: Option::None,
-            filesystem_directory_index: "index.htm".to_string(),
-            file_not_found_file: "404.htm".to_string(),
-            filesystem_root: Config::get_canonical_root(&"./html/".to_string()).unwrap(),
-            server_host: "localhost".to_string(),
-            server_limit: 4,
-            server_port: 4040,
-            tcp_limit: 1024,
-        };
+        let config = test_config();
         let application = Application::new(config);
-        let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
-        let mut responder = Responder::new();
-        assert!(responder.matches(
-            &request::Message::from_tcp_stream(b"GET /index2.htm HTTP/1.0").unwrap(),
-            &application,
-            &socket,
-            &0
-        ));
-        assert!(responder.matches(
-            &request::Message::from_tcp_stream(b"GET /index3.htm HTTP/1.0").unwrap(),
-            &application,
-            &socket,
-            &0
-        ));
-        assert!(responder.matches(
-            &request::Message::from_tcp_stream(b"GET /index.htm HTTP/1.1").unwrap(),
-            &application,
-            &socket,
-            &0
+        let socket = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
         ));
+        let responder = Responder::new();
+        let request = request::Message::from_tcp_stream(b"GET /index2.htm HTTP/1.0").unwrap();
+        assert!(responder.matches(&request, &application, &socket).is_some());
+        let request = request::Message::from_tcp_stream(b"GET /index3.htm HTTP/1.0").unwrap();
+        assert!(responder.matches(&request, &application, &socket).is_some());
+        let request = request::Message::from_tcp_stream(b"GET /index.htm HTTP/1.1").unwrap();
+        assert!(responder.matches(&request, &application, &socket).is_some());
 
         let config = Config {
-            feedback_error_file: Option::None,
-            feedback_info_file: Option::None,
-            filesystem_directory_index: "index.htm".to_string(),
             file_not_found_file: "404_file.htm".to_string(),
-            filesystem_root: Config::get_canonical_root(&"./html/".to_string()).unwrap(),
-            server_host: "localhost".to_string(),
-            server_limit: 4,
-            server_port: 4040,
-            tcp_limit: 1024,
+            ..test_config()
         };
         let application = Application::new(config);
-        let mut responder = Responder::new();
-        assert!(!responder.matches(
-            &request::Message::from_tcp_stream(b"GET /index2.htm HTTP/1.0").unwrap(),
-            &application,
-            &socket,
-            &0
-        ));
+        let responder = Responder::new();
+        let request = request::Message::from_tcp_stream(b"GET /index2.htm HTTP/1.0").unwrap();
+        assert!(responder.matches(&request, &application, &socket).is_none());
     }
 
     #[test]
     fn respond() {
-        let config = Config {
-            feedback_error_file: Option::None,
-            feedback_info_file: Option::None,
-            filesystem_directory_index: "index.htm".to_string(),
-            file_not_found_file: "404.htm".to_string(),
-            filesystem_root: Config::get_canonical_root(&"./html/".to_string()).unwrap(),
-            server_host: "localhost".to_string(),
-            server_limit: 4,
-            server_port: 4040,
-            tcp_limit: 1024,
-        };
+        let config = test_config();
         let application = Application::new(config);
-        let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
-        let mut responder = Responder::new();
+        let socket = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        ));
+        let responder = Responder::new();
 
         let filename = "html/404.htm";
 
@@ -168,8 +132,7 @@ mod tests {
         let request =
             request::Message::from_tcp_stream(b"GET /index2.htm HTTP/1.1\r\n\r\n").unwrap();
 
-        let matches = responder.matches(&request, &application, &socket, &0);
-        assert!(matches);
+        let handler = responder.matches(&request, &application, &socket).unwrap();
 
         let mut headers: HashMap<String, String> = HashMap::new();
         if let Ok(metadata) = fs::metadata(&filename) {
@@ -203,12 +166,13 @@ mod tests {
             "404 File Not Found".to_string(),
             headers,
             response_body.into_bytes(),
-        ).to_bytes();
+        ).to_bytes().unwrap();
 
-        let given_response = responder
-            .respond(&request, &application, &socket, &0)
+        let given_response = handler
+            .respond(&request, &Context::new(), &application, &socket)
             .unwrap()
-            .to_bytes();
+            .to_bytes()
+            .unwrap();
         assert_eq!(expected_response, given_response);
     }
 }
@@ -3,11 +3,14 @@
 extern crate chrono;
 
 use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
 use std::fs::File;
 use std::hash::{Hash, Hasher};
+use std::io;
 use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+use std::mem;
 use std::path::Path;
 use std::path::PathBuf;
 use std::time::Duration;
@@ -15,23 +18,24 @@ use std::time::SystemTime;
 
 use chrono::offset::Utc;
 use chrono::{DateTime, TimeZone};
-use std::net::SocketAddr;
 
 use application_layer::http::request;
 use application_layer::http::response;
+use application_layer::http::response::Body;
 
 use mime;
-use response::tcp::http::ResponderInterface;
+use response::tcp::http::{Context, HandlerInterface, ResponderInterface};
+use transport_layer::Address;
 use Application;
 
-#[derive(Clone)]
-pub struct Responder {
-    pub filename: Option<String>,
-}
+/// Maximum number of byte ranges accepted in one request
+pub const MAX_RANGES: usize = 16;
+
+pub struct Responder {}
 
 impl Responder {
     pub fn new() -> Responder {
-        Responder { filename: None }
+        Responder {}
     }
 
     pub fn get_metadata_modified_as_rfc7231(modified: SystemTime) -> String {
@@ -145,21 +149,196 @@ impl Responder {
         return None;
     }
 
-    // Make this respond headers as a HashMap and a string for body
+    /// Parse a Range header value into inclusive byte ranges of a resource with `length` bytes.
+    /// Returns None if the header should be ignored and an empty vector if no range is satisfiable.
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http::filesystem::Responder;
+    /// assert_eq!(
+    ///     Responder::get_byte_ranges("bytes=0-9, 20-, -5", 100),
+    ///     Some(vec![(0, 9), (20, 99), (95, 99)])
+    /// );
+    /// assert_eq!(Responder::get_byte_ranges("bytes=200-300", 100), Some(vec![]));
+    /// assert_eq!(Responder::get_byte_ranges("items=0-9", 100), None);
+    /// assert_eq!(Responder::get_byte_ranges("bytes=9-0", 100), None);
+    /// ```
+    pub fn get_byte_ranges(range: &str, length: u64) -> Option<Vec<(u64, u64)>> {
+        let parts: Vec<&str> = range.trim().splitn(2, '=').collect();
+        if parts.len() != 2 || !parts[0].trim().eq_ignore_ascii_case("bytes") {
+            return None;
+        }
+        let specs: Vec<&str> = parts[1].split(',').collect();
+        if specs.len() > MAX_RANGES {
+            return None;
+        }
+        let mut ranges = Vec::new();
+        for spec in specs {
+            let bounds: Vec<&str> = spec.trim().splitn(2, '-').collect();
+            if bounds.len() != 2 {
+                return None;
+            }
+            let (first, last) = (bounds[0].trim(), bounds[1].trim());
+            if first.is_empty() {
+                // Suffix range of the last bytes
+                let suffix: u64 = last.parse().ok()?;
+                if suffix > 0 && length > 0 {
+                    ranges.push((length.saturating_sub(suffix), length - 1));
+                }
+            } else {
+                let first: u64 = first.parse().ok()?;
+                let last: u64 = match last {
+                    "" => u64::MAX,
+                    _ => last.parse().ok()?,
+                };
+                if last < first {
+                    return None;
+                }
+                if first < length {
+                    ranges.push((first, last.min(length - 1)));
+                }
+            }
+        }
+        Some(ranges)
+    }
+
+    /// Whether a If-Range header value matches the current ETag or Last-Modified value
+    pub fn is_if_range_matching(if_range: &str, headers: &HashMap<String, String>) -> bool {
+        let if_range = if_range.trim().trim_matches('"');
+        if let Some(etag) = headers.get("ETag") {
+            if if_range == etag {
+                return true;
+            }
+        }
+        if let Some(last_modified) = headers.get("Last-Modified") {
+            if if_range == last_modified {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Turn a full file response into a partial response if the request asks for byte ranges
+    pub fn get_partial_response(
+        mut response: response::Message,
+        request_message: &request::Message,
+    ) -> Result<response::Message, String> {
+        if response.status != "200 OK" {
+            return Ok(response);
+        }
+        response
+            .headers
+            .insert("Accept-Ranges".to_string(), "bytes".to_string());
+        if request_message.request_line.method != request::Method::Get {
+            return Ok(response);
+        }
+        let range = match request_message.headers.get("Range") {
+            Some(range) => range.to_string(),
+            None => return Ok(response),
+        };
+        if let Some(if_range) = request_message.headers.get("If-Range") {
+            if !Responder::is_if_range_matching(&if_range.to_string(), &response.headers) {
+                return Ok(response);
+            }
+        }
+        let length = match response.body {
+            Body::File(_, length) => length,
+            _ => return Ok(response),
+        };
+        let ranges = match Responder::get_byte_ranges(&range, length) {
+            Some(ranges) => ranges,
+            None => return Ok(response),
+        };
+
+        if ranges.is_empty() {
+            response.set_status("416 Range Not Satisfiable".to_string());
+            response
+                .headers
+                .insert("Content-Range".to_string(), format!("bytes */{}", length));
+            response
+                .headers
+                .insert("Content-Length".to_string(), "0".to_string());
+            response.body = Body::from(Vec::new());
+            return Ok(response);
+        }
+
+        let file = match mem::replace(&mut response.body, Body::from(Vec::new())) {
+            Body::File(file, _) => file,
+            _ => return Err("Error: Expected a file body".to_string()),
+        };
+        response.set_status("206 Partial Content".to_string());
+
+        if ranges.len() == 1 {
+            let (first, last) = ranges[0];
+            let mut file = file;
+            if let Err(error) = file.seek(SeekFrom::Start(first)) {
+                return Err(format!("Error: Failed to seek in file, error: {:?}", error));
+            }
+            response.headers.insert(
+                "Content-Range".to_string(),
+                format!("bytes {}-{}/{}", first, last, length),
+            );
+            response
+                .headers
+                .insert("Content-Length".to_string(), (last - first + 1).to_string());
+            response.body = Body::File(file, last - first + 1);
+            return Ok(response);
+        }
+
+        // Multiple ranges are sent as a multipart/byteranges body
+        let content_type = match response.headers.get("Content-Type") {
+            Some(content_type) => content_type.clone(),
+            None => "application/octet-stream".to_string(),
+        };
+        let mut hasher = DefaultHasher::new();
+        SystemTime::now().hash(&mut hasher);
+        range.hash(&mut hasher);
+        let boundary = format!("{:016x}", hasher.finish());
+
+        let mut segments = VecDeque::new();
+        let mut total: u64 = 0;
+        for (first, last) in ranges {
+            let part_header = format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary, content_type, first, last, length
+            );
+            total += part_header.len() as u64 + last - first + 1;
+            segments.push_back(Segment::Bytes(Cursor::new(part_header.into_bytes())));
+            segments.push_back(Segment::Range(first, last - first + 1));
+        }
+        let end = format!("\r\n--{}--\r\n", boundary);
+        total += end.len() as u64;
+        segments.push_back(Segment::Bytes(Cursor::new(end.into_bytes())));
+
+        response.headers.insert(
+            "Content-Type".to_string(),
+            format!("multipart/byteranges; boundary={}", boundary),
+        );
+        response
+            .headers
+            .insert("Content-Length".to_string(), total.to_string());
+        response.body = Body::Stream(
+            Box::new(ByteRanges {
+                file,
+                positioned: false,
+                segments,
+            }),
+            Some(total),
+        );
+        Ok(response)
+    }
+
+    // Make this respond headers as a HashMap and a file for body
     pub fn get_response(
         filename: &String,
         request_message: &request::Message,
         application: &Application,
     ) -> Result<response::Message, String> {
-        let mut response_body = Vec::new();
-
         // Try to open the file
         let file = File::open(filename);
         match file {
-            Ok(mut file) => {
-                // Try to read the file
-                match file.read_to_end(&mut response_body) {
-                    Ok(_) => {
+            Ok(file) => {
+                // Try to read the file metadata
+                match file.metadata() {
+                    Ok(metadata) => {
                         let mut status_code = "200 OK";
 
                         let protocol = request::Message::get_protocol_text(
@@ -168,57 +347,46 @@ impl Responder {
                         let mut headers: HashMap<String, String> = HashMap::new();
 
                         headers.insert("Content-Type".to_string(), mime::from_filename(&filename));
+                        headers.insert("Content-Length".to_string(), metadata.len().to_string());
 
-                        if let Ok(metadata) = fs::metadata(&filename) {
-                            headers
-                                .insert("Content-Length".to_string(), metadata.len().to_string());
-
-                            if let Ok(last_modified) = metadata.modified() {
-                                headers.insert(
-                                    "Last-Modified".to_string(),
-                                    Responder::get_metadata_modified_as_rfc7231(last_modified),
-                                );
-                                let etag = Responder::get_modified_hash(&last_modified);
-                                headers.insert("ETag".to_string(), etag.clone());
-
-                                let duration = Duration::new(2592000, 0); // TODO Make this dynamic
-                                headers.insert(
-                                    "Expires".to_string(),
-                                    Responder::get_metadata_modified_as_rfc7231(
-                                        last_modified + duration,
-                                    ),
-                                );
-
-                                if let Some(if_none_match) =
-                                    request_message.headers.get("If-None-Match")
-                                {
-                                    if if_none_match.to_string() == etag {
-                                        status_code = "304 Not Modified";
-                                        response_body = Vec::new();
-                                    }
+                        if let Ok(last_modified) = metadata.modified() {
+                            headers.insert(
+                                "Last-Modified".to_string(),
+                                Responder::get_metadata_modified_as_rfc7231(last_modified),
+                            );
+                            let etag = Responder::get_modified_hash(&last_modified);
+                            headers.insert("ETag".to_string(), etag.clone());
+
+                            let duration = Duration::new(2592000, 0); // TODO Make this dynamic
+                            headers.insert(
+                                "Expires".to_string(),
+                                Responder::get_metadata_modified_as_rfc7231(
+                                    last_modified + duration,
+                                ),
+                            );
+
+                            if let Some(if_none_match) =
+                                request_message.headers.get("If-None-Match")
+                            {
+                                if if_none_match.to_string() == etag {
+                                    status_code = "304 Not Modified";
                                 }
+                            }
 
-                                if status_code != "304 Not Modified" {
-                                    if let Some(if_modified_since) =
-                                        request_message.headers.get("If-Modified-Since")
+                            if status_code != "304 Not Modified" {
+                                if let Some(if_modified_since) =
+                                    request_message.headers.get("If-Modified-Since")
+                                {
+                                    if let Ok(if_modified_since_systemtime) =
+                                        Responder::get_rfc7231_as_systemtime(
+                                            &if_modified_since.to_string(),
+                                        )
                                     {
-                                        if let Ok(if_modified_since_systemtime) =
-                                            Responder::get_rfc7231_as_systemtime(
-                                                &if_modified_since.to_string(),
-                                            ) {
-                                            if let Ok(duration) = last_modified
-                                                .duration_since(if_modified_since_systemtime)
-                                            {
-                                                /* println!(
-                                                    "{:?} vs {:?} = {}",
-                                                    &if_modified_since_systemtime,
-                                                    &last_modified,
-                                                    duration.as_secs()
-                                                );  */
-                                                if duration.as_secs() <= 0 {
-                                                    status_code = "304 Not Modified";
-                                                    response_body = Vec::new();
-                                                }
+                                        if let Ok(duration) = last_modified
+                                            .duration_since(if_modified_since_systemtime)
+                                        {
+                                            if duration.as_secs() == 0 {
+                                                status_code = "304 Not Modified";
                                             }
                                         }
                                     }
@@ -231,6 +399,13 @@ impl Responder {
                             Responder::get_cache_control(&application),
                         );
 
+                        // The file is streamed to the client instead of read into memory
+                        let response_body = if status_code == "304 Not Modified" {
+                            Body::from(Vec::new())
+                        } else {
+                            Body::File(file, metadata.len())
+                        };
+
                         // Build HTTP response
                         return Ok(response::Message::new(
                             protocol.to_string(),
@@ -241,7 +416,7 @@ impl Responder {
                     }
                     Err(e) => {
                         return Err(format!(
-                            "Error: Failed to read file {}, error: {:?}",
+                            "Error: Failed to read metadata of file {}, error: {:?}",
                             filename, e
                         ));
                     }
@@ -257,124 +432,129 @@ impl Responder {
     }
 }
 
+/// A segment of a multipart/byteranges body
+enum Segment {
+    Bytes(Cursor<Vec<u8>>),
+    /// Offset and length of a range in the file
+    Range(u64, u64),
+}
+
+/// Reads the parts of a multipart/byteranges body, seeking in the file lazily
+struct ByteRanges {
+    file: File,
+    positioned: bool,
+    segments: VecDeque<Segment>,
+}
+
+impl Read for ByteRanges {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read_size = match self.segments.front_mut() {
+                None => return Ok(0),
+                Some(Segment::Bytes(bytes)) => bytes.read(buffer)?,
+                Some(Segment::Range(offset, length)) => {
+                    if !self.positioned {
+                        self.file.seek(SeekFrom::Start(*offset))?;
+                        self.positioned = true;
+                    }
+                    let limit = buffer.len().min(*length as usize);
+                    let read_size = self.file.read(&mut buffer[..limit])?;
+                    if read_size == 0 && *length > 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "File ended before end of range",
+                        ));
+                    }
+                    *offset += read_size as u64;
+                    *length -= read_size as u64;
+                    read_size
+                }
+            };
+            if read_size > 0 {
+                return Ok(read_size);
+            }
+            self.segments.pop_front();
+            self.positioned = false;
+        }
+    }
+}
+
+/// Responds with the file a request matched
+struct Handler {
+    filename: String,
+}
+
 impl ResponderInterface for Responder {
     fn matches(
-        &mut self,
+        &self,
         request_message: &request::Message,
         application: &Application,
-        _socket: &SocketAddr,
-        _overflow_bytes: &u64,
-    ) -> bool {
-        if let Some(filename) = Responder::get_matching_filename(&request_message, &application) {
-            self.filename = Some(filename);
-            return true;
-        }
-        return false;
+        _socket: &Address,
+    ) -> Option<Box<dyn HandlerInterface>> {
+        let filename = Responder::get_matching_filename(&request_message, &application)?;
+        Some(Box::new(Handler { filename }))
     }
+}
 
-    // Make this respond headers as a HashMap and a string for body
+impl HandlerInterface for Handler {
     fn respond(
-        &self,
+        self: Box<Self>,
         request_message: &request::Message,
+        _context: &Context,
         application: &Application,
-        _socket: &SocketAddr,
-        _overflow_bytes: &u64,
+        _socket: &Address,
     ) -> Result<response::Message, String> {
-        // Does filename exist?
-        if let Some(filename) = &self.filename {
-            let mut response = Responder::get_response(&filename, &request_message, &application)?;
-            // Build HTTP response
-            return Ok(response);
-        } else {
-            return Err("Error: Filename missing".to_string());
-        }
+        let response = Responder::get_response(&self.filename, &request_message, &application)?;
+        Responder::get_partial_response(response, &request_message)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::net::{IpAddr, Ipv4Addr};
-    use Config;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use test_config;
 
     #[test]
     fn matches() {
-        let config = Config {
-            feedback_error_file: Option::None,
-            feedback_info_file: Option::None,
-            filesystem_directory_index: "index.htm".to_string(),
-            file_not_found_file: "404.htm".to_string(),
-            filesystem_root: Config::get_canonical_root(&"./html/".to_string()).unwrap(),
-            server_host: "localhost".to_string(),
-            server_limit: 4,
-            server_port: 4040,
-            tcp_limit: 1024,
-        };
+        let config = test_config();
         let application = Application::new(config);
 
-        let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
-        let mut responder = Responder::new();
-        assert!(responder.matches(
-            &request::Message::from_tcp_stream(b"GET / HTTP/1.0").unwrap(),
-            &application,
-            &socket,
-            &0
-        ));
-        assert!(responder.matches(
-            &request::Message::from_tcp_stream(b"GET /index.htm HTTP/1.0").unwrap(),
-            &application,
-            &socket,
-            &0
+        let socket = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
         ));
+        let responder = Responder::new();
+        let request = request::Message::from_tcp_stream(b"GET / HTTP/1.0").unwrap();
+        assert!(responder.matches(&request, &application, &socket).is_some());
+        let request = request::Message::from_tcp_stream(b"GET /index.htm HTTP/1.0").unwrap();
+        assert!(responder.matches(&request, &application, &socket).is_some());
 
         // POST request with random header and null bytes
         let mut request: Vec<u8> =
             b"POST / HTTP/1.0\r\nAgent: Random browser\r\n\r\ntest=abc".to_vec();
         request.push(0);
         request.push(0);
-        assert!(responder.matches(
-            &request::Message::from_tcp_stream(&request).unwrap(),
-            &application,
-            &socket,
-            &0
-        ));
-
-        assert!(!responder.matches(
-            &request::Message::from_tcp_stream(b"GET /../README.md HTTP/1.0").unwrap(),
-            &application,
-            &socket,
-            &0
-        ));
-        assert!(!responder.matches(
-            &request::Message::from_tcp_stream(b"GET /.DS_Store HTTP/1.0").unwrap(),
-            &application,
-            &socket,
-            &0
-        ));
-        assert!(!responder.matches(
-            &request::Message::from_tcp_stream(b"GET /test.htm HTTP/1.1").unwrap(),
-            &application,
-            &socket,
-            &0
-        ));
+        let request = request::Message::from_tcp_stream(&request).unwrap();
+        assert!(responder.matches(&request, &application, &socket).is_some());
+
+        let request = request::Message::from_tcp_stream(b"GET /../README.md HTTP/1.0").unwrap();
+        assert!(responder.matches(&request, &application, &socket).is_none());
+        let request = request::Message::from_tcp_stream(b"GET /.DS_Store HTTP/1.0").unwrap();
+        assert!(responder.matches(&request, &application, &socket).is_none());
+        let request = request::Message::from_tcp_stream(b"GET /test.htm HTTP/1.1").unwrap();
+        assert!(responder.matches(&request, &application, &socket).is_none());
     }
 
     #[test]
     fn respond() {
-        let config = Config {
-            feedback_error_file: Option::None,
-            feedback_info_file: Option::None,
-            filesystem_directory_index: "index.htm".to_string(),
-            file_not_found_file: "404.htm".to_string(),
-            filesystem_root: Config::get_canonical_root(&"./html/".to_string()).unwrap(),
-            server_host: "localhost".to_string(),
-            server_limit: 4,
-            server_port: 4040,
-            tcp_limit: 1024,
-        };
+        let config = test_config();
         let application = Application::new(config);
-        let mut responder = Responder::new();
-        let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let responder = Responder::new();
+        let socket = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        ));
         let filename = "html/index.htm";
 
         let mut file = File::open(&filename).unwrap();
@@ -385,9 +565,6 @@ mod tests {
         file.read_to_string(&mut response_body).unwrap();
 
         let request = request::Message::from_tcp_stream(b"GET / HTTP/1.1\r\n\r\n").unwrap();
-        let matches = responder.matches(&request, &application, &socket, &0);
-        assert!(matches);
-
         let mut headers: HashMap<String, String> = HashMap::new();
         if let Ok(metadata) = fs::metadata(&filename) {
             if let Ok(last_modified) = metadata.modified() {
@@ -413,17 +590,22 @@ mod tests {
             Responder::get_cache_control(&application),
         );
 
+        headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+
         let expected_response = response::Message::new(
             "HTTP/1.1".to_string(),
             "200 OK".to_string(),
             headers,
             response_body.into_bytes(),
-        ).to_bytes();
+        ).to_bytes().unwrap();
 
         let given_response = responder
-            .respond(&request, &application, &socket, &0)
+            .matches(&request, &application, &socket)
             .unwrap()
-            .to_bytes();
+            .respond(&request, &Context::new(), &application, &socket)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
         assert_eq!(expected_response, given_response);
 
         // Matching If Modified Since
@@ -459,7 +641,7 @@ mod tests {
                     "304 Not Modified".to_string(),
                     headers,
                     response_body_empty,
-                ).to_bytes();
+                ).to_bytes().unwrap();
 
                 let request_string = format!(
                     "GET /index.htm HTTP/1.1\r\nIf-Modified-Since: {}\r\n\r\n",
@@ -468,9 +650,12 @@ mod tests {
                 let request = request::Message::from_tcp_stream(request_string.as_bytes()).unwrap();
 
                 let given_response = responder
-                    .respond(&request, &application, &socket, &0)
+                    .matches(&request, &application, &socket)
+                    .unwrap()
+                    .respond(&request, &Context::new(), &application, &socket)
                     .unwrap()
-                    .to_bytes();
+                    .to_bytes()
+                    .unwrap();
                 /* println!(
                     "request: {}, response: {:?}",
                     request_string,
@@ -510,12 +695,14 @@ mod tests {
                 let mut file = File::open(&filename).unwrap();
                 file.read_to_string(&mut response_body).unwrap();
 
+                headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+
                 let expected_response = response::Message::new(
                     "HTTP/1.1".to_string(),
                     "200 OK".to_string(),
                     headers,
                     response_body.into_bytes(),
-                ).to_bytes();
+                ).to_bytes().unwrap();
 
                 let duration = Duration::new(250000, 0);
                 let request_string = format!(
@@ -524,9 +711,12 @@ mod tests {
                 );
                 let request = request::Message::from_tcp_stream(request_string.as_bytes()).unwrap();
                 let given_response = responder
-                    .respond(&request, &application, &socket, &0)
+                    .matches(&request, &application, &socket)
+                    .unwrap()
+                    .respond(&request, &Context::new(), &application, &socket)
                     .unwrap()
-                    .to_bytes();
+                    .to_bytes()
+                    .unwrap();
 
                 /* println!(
                     "request: {}, response: {:?}, expected response: {:?}",
@@ -576,12 +766,15 @@ mod tests {
                     "304 Not Modified".to_string(),
                     headers,
                     response_body,
-                ).to_bytes();
+                ).to_bytes().unwrap();
 
                 let given_response = responder
-                    .respond(&request, &application, &socket, &0)
+                    .matches(&request, &application, &socket)
                     .unwrap()
-                    .to_bytes();
+                    .respond(&request, &Context::new(), &application, &socket)
+                    .unwrap()
+                    .to_bytes()
+                    .unwrap();
                 assert_eq!(expected_response, given_response);
             }
         }
@@ -616,12 +809,14 @@ mod tests {
                 let mut file = File::open(&filename).unwrap();
                 file.read_to_string(&mut response_body).unwrap();
 
+                headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+
                 let expected_response = response::Message::new(
                     "HTTP/1.1".to_string(),
                     "200 OK".to_string(),
                     headers,
                     response_body.into_bytes(),
-                ).to_bytes();
+                ).to_bytes().unwrap();
 
                 let duration = Duration::new(250000, 0);
                 let last_modified = last_modified - duration;
@@ -631,9 +826,12 @@ mod tests {
                 );
                 let request = request::Message::from_tcp_stream(request_string.as_bytes()).unwrap();
                 let given_response = responder
-                    .respond(&request, &application, &socket, &0)
+                    .matches(&request, &application, &socket)
                     .unwrap()
-                    .to_bytes();
+                    .respond(&request, &Context::new(), &application, &socket)
+                    .unwrap()
+                    .to_bytes()
+                    .unwrap();
 
                 /* println!(
                     "request: {}, response: {:?}, expected response: {:?}",
@@ -645,4 +843,130 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn respond_range() {
+        let config = test_config();
+        let application = Application::new(config);
+        let responder = Responder::new();
+        let socket = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        ));
+        let filename = "html/index.htm";
+
+        let mut file_body = Vec::new();
+        File::open(filename)
+            .unwrap()
+            .read_to_end(&mut file_body)
+            .unwrap();
+        let length = file_body.len();
+
+        // Single range
+        let request = request::Message::from_tcp_stream(
+            b"GET /index.htm HTTP/1.1\r\nRange: bytes=10-19\r\n\r\n",
+        ).unwrap();
+        let mut response = responder
+            .matches(&request, &application, &socket)
+            .unwrap()
+            .respond(&request, &Context::new(), &application, &socket)
+            .unwrap();
+        assert_eq!(response.status, "206 Partial Content".to_string());
+        assert_eq!(
+            response.get_header("Content-Range"),
+            Some(&format!("bytes 10-19/{}", length))
+        );
+        assert_eq!(
+            response.get_header("Content-Length"),
+            Some(&"10".to_string())
+        );
+        let mut body = Vec::new();
+        response.write_body_to(&mut body).unwrap();
+        assert_eq!(body, file_body[10..20].to_vec());
+
+        // Multiple ranges
+        let request = request::Message::from_tcp_stream(
+            b"GET /index.htm HTTP/1.1\r\nRange: bytes=0-4, -5\r\n\r\n",
+        ).unwrap();
+        let mut response = responder
+            .matches(&request, &application, &socket)
+            .unwrap()
+            .respond(&request, &Context::new(), &application, &socket)
+            .unwrap();
+        assert_eq!(response.status, "206 Partial Content".to_string());
+        let content_type = response.get_header("Content-Type").unwrap().clone();
+        assert!(content_type.starts_with("multipart/byteranges; boundary="));
+        let boundary = content_type.split("boundary=").nth(1).unwrap().to_string();
+        let content_length: usize = response
+            .get_header("Content-Length")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut body = Vec::new();
+        response.write_body_to(&mut body).unwrap();
+        assert_eq!(body.len(), content_length);
+        let mut expected_body = format!(
+            "\r\n--{}\r\nContent-Type: text/html\r\nContent-Range: bytes 0-4/{}\r\n\r\n",
+            boundary, length
+        ).into_bytes();
+        expected_body.extend_from_slice(&file_body[0..5]);
+        expected_body.extend_from_slice(
+            format!(
+                "\r\n--{}\r\nContent-Type: text/html\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary,
+                length - 5,
+                length - 1,
+                length
+            ).as_bytes(),
+        );
+        expected_body.extend_from_slice(&file_body[length - 5..]);
+        expected_body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+        assert_eq!(body, expected_body);
+
+        // Unsatisfiable range
+        let request_string = format!(
+            "GET /index.htm HTTP/1.1\r\nRange: bytes={}-\r\n\r\n",
+            length
+        );
+        let request = request::Message::from_tcp_stream(request_string.as_bytes()).unwrap();
+        let response = responder
+            .matches(&request, &application, &socket)
+            .unwrap()
+            .respond(&request, &Context::new(), &application, &socket)
+            .unwrap();
+        assert_eq!(response.status, "416 Range Not Satisfiable".to_string());
+        assert_eq!(
+            response.get_header("Content-Range"),
+            Some(&format!("bytes */{}", length))
+        );
+
+        // If-Range matching the ETag and not matching
+        let last_modified = fs::metadata(filename).unwrap().modified().unwrap();
+        let request_string = format!(
+            "GET /index.htm HTTP/1.1\r\nRange: bytes=0-0\r\nIf-Range: \"{}\"\r\n\r\n",
+            Responder::get_modified_hash(&last_modified)
+        );
+        let request = request::Message::from_tcp_stream(request_string.as_bytes()).unwrap();
+        let response = responder
+            .matches(&request, &application, &socket)
+            .unwrap()
+            .respond(&request, &Context::new(), &application, &socket)
+            .unwrap();
+        assert_eq!(response.status, "206 Partial Content".to_string());
+
+        let request = request::Message::from_tcp_stream(
+            b"GET /index.htm HTTP/1.1\r\nRange: bytes=0-0\r\nIf-Range: \"outdated\"\r\n\r\n",
+        ).unwrap();
+        let response = responder
+            .matches(&request, &application, &socket)
+            .unwrap()
+            .respond(&request, &Context::new(), &application, &socket)
+            .unwrap();
+        assert_eq!(response.status, "200 OK".to_string());
+        assert_eq!(
+            response.get_header("Accept-Ranges"),
+            Some(&"bytes".to_string())
+        );
+        assert_eq!(response.body.len(), Some(length as u64));
+    }
 }
@@ -0,0 +1,224 @@
+//! # TCP HTTP HTTPS redirect response
+//! Used for sending clients of plain HTTP to the same resource over HTTPS.
+
+use application_layer::http::request;
+use application_layer::http::response;
+
+use std::collections::HashMap;
+use transport_layer::Address;
+use Application;
+
+use response::tcp::http::{is_forwarded_https, Context, HandlerInterface, ResponderInterface};
+
+#[derive(Default)]
+pub struct Responder {}
+
+impl Responder {
+    pub fn new() -> Responder {
+        Responder {}
+    }
+
+    /// Build the HTTPS URL of a request from its Host header field, the configured host is
+    /// used when the header field is missing or invalid
+    /// ```rust
+    /// use milstian_internet_framework::application_layer::http::request;
+    /// use milstian_internet_framework::response::tcp::http::https_redirect::Responder;
+    /// let request = request::Message::from_tcp_stream(
+    ///     b"GET /about.htm?a=b HTTP/1.1\r\nHost: example.com:8080\r\n\r\n"
+    /// ).unwrap();
+    /// assert_eq!(
+    ///     Responder::get_location(&request, "localhost", 443),
+    ///     "https://example.com/about.htm?a=b"
+    /// );
+    /// assert_eq!(
+    ///     Responder::get_location(&request, "localhost", 8443),
+    ///     "https://example.com:8443/about.htm?a=b"
+    /// );
+    /// ```
+    pub fn get_location(
+        request_message: &request::Message,
+        server_host: &str,
+        port: u32,
+    ) -> String {
+        let host = match request_message.headers.get("Host") {
+            Some(host) => Responder::get_host_name(&host.to_string())
+                .unwrap_or(server_host)
+                .to_string(),
+            None => server_host.to_string(),
+        };
+        let mut location = format!("https://{}", host);
+        if port != 443 {
+            location.push_str(&format!(":{}", port));
+        }
+        location.push_str(&request_message.request_line.request_uri_base);
+        if !request_message.request_line.query_string.is_empty() {
+            location.push('?');
+            location.push_str(&request_message.request_line.query_string);
+        }
+        location
+    }
+
+    /// Get the host name of a Host header field without its port
+    fn get_host_name(host: &str) -> Option<&str> {
+        let host = host.trim();
+        let name = if host.starts_with('[') {
+            // IPv6 literal
+            &host[..host.find(']')? + 1]
+        } else {
+            host.split(':').next()?
+        };
+        let valid = name
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || "-._[]:".contains(character));
+        if name.is_empty() || !valid {
+            return None;
+        }
+        Some(name)
+    }
+}
+
+impl ResponderInterface for Responder {
+    fn matches(
+        &self,
+        request_message: &request::Message,
+        application: &Application,
+        socket: &Address,
+    ) -> Option<Box<dyn HandlerInterface>> {
+        // Requests a trusted proxy received over HTTPS would be redirected forever
+        if is_forwarded_https(request_message, application, socket) {
+            return None;
+        }
+        Some(Box::new(Responder {}))
+    }
+}
+
+impl HandlerInterface for Responder {
+    fn respond(
+        self: Box<Self>,
+        request_message: &request::Message,
+        _context: &Context,
+        application: &Application,
+        _socket: &Address,
+    ) -> Result<response::Message, String> {
+        let config = application.get_config();
+        // Only safe methods may be changed to GET by clients following a 301
+        let status_code = match request_message.request_line.method {
+            request::Method::Get | request::Method::Head => "301 Moved Permanently",
+            _ => "308 Permanent Redirect",
+        };
+        let protocol = request::Message::get_protocol_text(&request_message.request_line.protocol);
+        let mut headers: HashMap<String, String> = HashMap::new();
+        headers.insert(
+            "Location".to_string(),
+            Responder::get_location(request_message, &config.server_host, config.tls_port),
+        );
+
+        Ok(response::Message::new(
+            protocol,
+            status_code.to_string(),
+            headers,
+            Vec::new(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use test_config;
+
+    fn get_application() -> Application {
+        let mut config = test_config();
+        config.server_host = "localhost".to_string();
+        config.server_trusted_proxies = vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))];
+        Application::new(config)
+    }
+
+    #[test]
+    fn test_matches() {
+        let application = get_application();
+        let responder = Responder::new();
+        let request = request::Message::from_tcp_stream(
+            b"GET /index.htm HTTP/1.1\r\nX-Forwarded-Proto: https\r\n\r\n",
+        )
+        .unwrap();
+
+        let client = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        ));
+        assert!(responder.matches(&request, &application, &client).is_some());
+        let proxy = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            8080,
+        ));
+        assert!(responder.matches(&request, &application, &proxy).is_none());
+        // Clients resolved from forwarding header fields keep the protocol of the proxy
+        let forwarded = Address::Forwarded(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)));
+        assert!(responder
+            .matches(&request, &application, &forwarded)
+            .is_none());
+
+        let request = request::Message::from_tcp_stream(
+            b"GET /index.htm HTTP/1.1\r\nX-Forwarded-Proto: http\r\n\r\n",
+        )
+        .unwrap();
+        assert!(responder.matches(&request, &application, &proxy).is_some());
+    }
+
+    #[test]
+    fn test_respond() {
+        let application = get_application();
+        let responder = Responder::new();
+        let socket = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        ));
+
+        let request = request::Message::from_tcp_stream(
+            b"GET /index.htm HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        )
+        .unwrap();
+        let response = responder
+            .matches(&request, &application, &socket)
+            .unwrap()
+            .respond(&request, &Context::new(), &application, &socket)
+            .unwrap();
+        assert_eq!(response.status, "301 Moved Permanently");
+        assert_eq!(
+            response.get_header("Location"),
+            Some(&"https://example.com/index.htm".to_string())
+        );
+
+        let request =
+            request::Message::from_tcp_stream(b"POST /form HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n")
+                .unwrap();
+        let response = responder
+            .matches(&request, &application, &socket)
+            .unwrap()
+            .respond(&request, &Context::new(), &application, &socket)
+            .unwrap();
+        assert_eq!(response.status, "308 Permanent Redirect");
+        assert_eq!(
+            response.get_header("Location"),
+            Some(&"https://[::1]/form".to_string())
+        );
+
+        // Invalid or missing hosts are replaced by the configured host
+        let request =
+            request::Message::from_tcp_stream(b"GET / HTTP/1.1\r\nHost: evil.com/path@\r\n\r\n")
+                .unwrap();
+        let response = responder
+            .matches(&request, &application, &socket)
+            .unwrap()
+            .respond(&request, &Context::new(), &application, &socket)
+            .unwrap();
+        assert_eq!(
+            response.get_header("Location"),
+            Some(&"https://localhost/".to_string())
+        );
+    }
+}
@@ -4,12 +4,21 @@
 pub mod error;
 pub mod file_not_found;
 pub mod filesystem;
+pub mod https_redirect;
+pub mod reader;
+pub mod router;
 
-use std::net::SocketAddr;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
 
 use application_layer::http::request;
 use application_layer::http::response;
 
+use state::State;
+use thread::get_panic_message;
+use transport_layer::Address;
 use Application;
 
 pub struct Dispatcher {
@@ -29,8 +38,7 @@ impl Dispatcher {
         &mut self,
         request: &[u8],
         _application: &Application,
-        _socket: &SocketAddr,
-        _overflow_bytes: &u64,
+        _socket: &Address,
     ) -> bool {
         if let Some(request_message) = request::Message::from_tcp_stream(request) {
             self.request_message = Some(request_message);
@@ -39,77 +47,619 @@ impl Dispatcher {
         false
     }
 
-    /// Make the first http response that matches respond
+    /// Make the first http response that matches respond, the middleware of the application
+    /// runs around the responders
     pub fn respond(
-        &self,
-        _request: &[u8],
+        &mut self,
+        request: &[u8],
         application: &Application,
-        socket: &SocketAddr,
-        responders: Vec<Box<ResponderInterface + Send>>,
-        overflow_bytes: &u64,
-    ) -> Result<(Vec<u8>, String), String> {
-        if let Some(request_message) = &self.request_message {
-            for mut responder in responders.into_iter() {
-                if responder.matches(&request_message, &application, &socket, &overflow_bytes) {
-                    if let Ok(mut response) =
-                        responder.respond(&request_message, &application, &socket, &overflow_bytes)
-                    {
-                        let mut log = String::new();
-                        if let Some(request_message) = &self.request_message {
-                            let mut agent = String::new();
-                            let mut referer = String::new();
-                            if let Some(http_agent) = request_message.headers.get("User-Agent") {
-                                agent = http_agent.to_string();
-                            }
-                            if let Some(http_referer) = request_message.headers.get("Referer") {
-                                referer = http_referer.to_string();
-                            }
-                            log = format!(
-                                "HTTP access - \"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\"",
-                                socket,
-                                &request_message.request_line.raw,
-                                agent,
-                                referer,
-                                &response.status,
-                                &response.body.len()
-                            );
-                        }
-                        return Ok((response.to_bytes(), log));
-                    }
+        socket: &Address,
+        responders: &[Box<dyn ResponderInterface>],
+    ) -> Result<(response::Message, String), String> {
+        let request_message = match self.request_message {
+            Some(ref mut request_message) => request_message,
+            None => return Err("Found no matching HTTP responder".to_string()),
+        };
+        let response = run_middleware(
+            application.get_middleware(),
+            request_message,
+            &mut Context::new(),
+            application,
+            socket,
+            |request_message, context| {
+                Dispatcher::get_response(request_message, context, application, socket, responders)
+            },
+        )?;
+
+        let mut agent = String::new();
+        let mut referer = String::new();
+        if let Some(http_agent) = request_message.headers.get("User-Agent") {
+            agent = http_agent.to_string();
+        }
+        if let Some(http_referer) = request_message.headers.get("Referer") {
+            referer = http_referer.to_string();
+        }
+        let length = match response.body.len() {
+            Some(length) => length.to_string(),
+            None => "-".to_string(),
+        };
+        let log = format!(
+            "HTTP access - \"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\"",
+            socket,
+            reader::Request::get_request_line(request),
+            agent,
+            referer,
+            &response.status,
+            length
+        );
+        Ok((response, log))
+    }
+
+    /// Response of the first responder that matches, the middleware of its handler runs
+    /// around the handler. When the handler fails the responders after it answer the request
+    /// inside that middleware, which may already have changed the request.
+    fn get_response(
+        request_message: &mut request::Message,
+        context: &mut Context,
+        application: &Application,
+        socket: &Address,
+        responders: &[Box<dyn ResponderInterface>],
+    ) -> Result<response::Message, String> {
+        for (index, responder) in responders.iter().enumerate() {
+            // A panicking responder is answered by the error responder
+            let result = match panic::catch_unwind(AssertUnwindSafe(|| {
+                responder.matches(request_message, application, socket)
+            })) {
+                Ok(None) => continue,
+                Ok(Some(handler)) => panic::catch_unwind(AssertUnwindSafe(|| {
+                    let middleware = handler.get_middleware().to_vec();
+                    run_middleware(
+                        &middleware,
+                        request_message,
+                        context,
+                        application,
+                        socket,
+                        |request_message, context| {
+                            handler
+                                .respond(request_message, context, application, socket)
+                                .or_else(|error| {
+                                    application
+                                        .get_feedback()
+                                        .error(format!("HTTP responder failed, error: {}", error));
+                                    Dispatcher::get_response(
+                                        request_message,
+                                        context,
+                                        application,
+                                        socket,
+                                        &responders[index + 1..],
+                                    )
+                                })
+                        },
+                    )
+                })),
+                Err(payload) => Err(payload),
+            };
+            match result {
+                Ok(result) => return result,
+                Err(payload) => {
+                    application.get_feedback().error(format!(
+                        "HTTP responder panicked, error: {}",
+                        get_panic_message(&payload)
+                    ));
+                    return Box::new(error::Responder::new()).respond(
+                        request_message,
+                        context,
+                        application,
+                        socket,
+                    );
                 }
             }
         }
 
-        return Err("Found no matching HTTP responder".to_string());
+        Err("Found no matching HTTP responder".to_string())
     }
 }
 
-pub trait ResponderInterface: ResponderInterfaceCopy {
-    fn matches(&mut self, &request::Message, &Application, &SocketAddr, &u64) -> bool;
-    fn respond(
+/// Run the middleware in order before responding and in reverse order after it, middleware
+/// that answers the request itself skips the response and the middleware after it
+fn run_middleware<F>(
+    middleware: &[Arc<dyn MiddlewareInterface>],
+    request_message: &mut request::Message,
+    context: &mut Context,
+    application: &Application,
+    socket: &Address,
+    respond: F,
+) -> Result<response::Message, String>
+where
+    F: FnOnce(&mut request::Message, &mut Context) -> Result<response::Message, String>,
+{
+    let mut ran = middleware.len();
+    let mut response = None;
+    for (index, item) in middleware.iter().enumerate() {
+        if let Some(answer) = item.before(request_message, context, application, socket) {
+            ran = index;
+            response = Some(answer);
+            break;
+        }
+    }
+    let mut response = match response {
+        Some(response) => response,
+        None => respond(request_message, context)?,
+    };
+    for item in middleware[..ran].iter().rev() {
+        item.after(request_message, &mut response, context, application, socket);
+    }
+    Ok(response)
+}
+
+/// Whether a request arrived over HTTPS at a trusted reverse proxy, as told by the first
+/// protocol of its X-Forwarded-Proto header field. The address of a forwarded client was
+/// resolved from the header fields of a trusted peer, which tells the protocol as well.
+pub fn is_forwarded_https(
+    request_message: &request::Message,
+    application: &Application,
+    socket: &Address,
+) -> bool {
+    let trusted = match socket {
+        Address::Forwarded(_) => true,
+        _ => application.get_config().is_trusted_peer(socket),
+    };
+    if !trusted {
+        return false;
+    }
+    match get_header(request_message, "X-Forwarded-Proto") {
+        Some(protocol) => protocol
+            .split(',')
+            .next()
+            .map(|protocol| protocol.trim().eq_ignore_ascii_case("https"))
+            .unwrap_or(false),
+        None => false,
+    }
+}
+
+/// The address of the client that made a request, a trusted reverse proxy tells it in the
+/// Forwarded, X-Forwarded-For or X-Real-IP header field. Every proxy appends the address it
+/// received the request from, so the client is the last address that is not a trusted proxy.
+/// ```rust
+/// use milstian_internet_framework::application_layer::http::request;
+/// use milstian_internet_framework::response::tcp::http::get_client_address;
+/// use milstian_internet_framework::transport_layer::Address;
+/// use milstian_internet_framework::{Application, Config};
+/// let mut config = Config::from_env_args(vec![
+///     "".to_string(),
+///     "127.0.0.1".to_string(),
+///     "0".to_string(),
+///     "4".to_string(),
+///     "index.htm".to_string(),
+///     "./html/".to_string(),
+///     "404.htm".to_string(),
+///     "1024".to_string(),
+/// ]).unwrap();
+/// config.server_trusted_proxies = vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+/// let application = Application::new(config);
+/// let request = request::Message::from_tcp_stream(
+///     b"GET / HTTP/1.1\r\nX-Forwarded-For: 192.0.2.1, 203.0.113.7, 10.0.0.2\r\n\r\n",
+/// ).unwrap();
+/// let proxy = Address::Tcp("10.0.0.1:8080".parse().unwrap());
+/// assert_eq!(
+///     get_client_address(&request, &application, &proxy),
+///     Address::Forwarded("203.0.113.7".parse().unwrap())
+/// );
+/// let client = Address::Tcp("192.0.2.1:8080".parse().unwrap());
+/// assert_eq!(get_client_address(&request, &application, &client), client);
+/// ```
+pub fn get_client_address(
+    request_message: &request::Message,
+    application: &Application,
+    socket: &Address,
+) -> Address {
+    let config = application.get_config();
+    if !config.is_trusted_peer(socket) {
+        return socket.clone();
+    }
+    let addresses: Vec<Option<IpAddr>> =
+        if let Some(forwarded) = get_header(request_message, "Forwarded") {
+            forwarded.split(',').map(parse_forwarded_element).collect()
+        } else if let Some(forwarded_for) = get_header(request_message, "X-Forwarded-For") {
+            forwarded_for.split(',').map(parse_node).collect()
+        } else if let Some(real_ip) = get_header(request_message, "X-Real-IP") {
+            vec![parse_node(&real_ip)]
+        } else {
+            return socket.clone();
+        };
+
+    for address in addresses.iter().rev() {
+        match address {
+            Some(ip) if config.is_trusted_proxy(ip) => {}
+            Some(ip) => return Address::Forwarded(*ip),
+            // Obfuscated and unknown addresses end the chain of trust
+            None => return socket.clone(),
+        }
+    }
+    match addresses.first() {
+        Some(Some(ip)) => Address::Forwarded(*ip),
+        _ => socket.clone(),
+    }
+}
+
+/// Parse the `for` parameter of an element of a Forwarded header field like
+/// `for=192.0.2.1;proto=https`
+fn parse_forwarded_element(element: &str) -> Option<IpAddr> {
+    element
+        .split(';')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if key.trim().eq_ignore_ascii_case("for") => Some(value),
+                _ => None,
+            }
+        })
+        .next()
+        .and_then(parse_node)
+}
+
+/// Parse a node of a forwarding header field like `192.0.2.1`, `"[2001:db8::1]:4711"` or
+/// `2001:db8::1`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(address) = node.parse::<SocketAddr>() {
+        return Some(address.ip());
+    }
+    node.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Get the value of a header field with a case-insensitive name
+fn get_header(request_message: &request::Message, name: &str) -> Option<String> {
+    request_message
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.to_string())
+}
+
+/// Responders of a listener that its workers share, the first responder that matches a
+/// request responds to it
+pub type Responders = Arc<Vec<Box<dyn ResponderInterface>>>;
+
+pub trait ResponderInterface: Send + Sync {
+    /// A handler that responds to the request when the responder matches it, state of the
+    /// request is kept in the handler so responders can be shared by every worker. Requests
+    /// that exceed the TCP limit are answered with 413 Payload Too Large before any responder
+    fn matches(
         &self,
-        &request::Message,
-        &Application,
-        &SocketAddr,
-        &u64,
+        request_message: &request::Message,
+        application: &Application,
+        socket: &Address,
+    ) -> Option<Box<dyn HandlerInterface>>;
+}
+
+pub trait HandlerInterface {
+    /// Respond to the request that the responder matched
+    fn respond(
+        self: Box<Self>,
+        request_message: &request::Message,
+        context: &Context,
+        application: &Application,
+        socket: &Address,
     ) -> Result<response::Message, String>;
+
+    /// Middleware that runs around this handler only, like the middleware of a route
+    fn get_middleware(&self) -> &[Arc<dyn MiddlewareInterface>] {
+        &[]
+    }
 }
 
-pub trait ResponderInterfaceCopy {
-    fn clone_box(&self) -> Box<ResponderInterface + Send>;
+/// State of the request being served, it lives as long as the request
+#[derive(Debug, Default)]
+pub struct Context {
+    /// Values that middleware attaches to the request by their type, like the authenticated
+    /// user, for the middleware and handlers after it
+    pub extensions: State,
 }
 
-impl<T> ResponderInterfaceCopy for T
-where
-    T: 'static + ResponderInterface + Clone + Send,
-{
-    fn clone_box(&self) -> Box<ResponderInterface + Send> {
-        Box::new(self.clone())
+impl Context {
+    pub fn new() -> Context {
+        Context::default()
+    }
+}
+
+/// Cross-cutting logic around responders, like authentication, header fields or timing
+pub trait MiddlewareInterface: Send + Sync {
+    /// Inspect or rewrite a request before it is answered, a response answers the request
+    /// instead of the responders. Extensions inserted into the context are seen by the
+    /// middleware and handlers after this one.
+    fn before(
+        &self,
+        _request_message: &mut request::Message,
+        _context: &mut Context,
+        _application: &Application,
+        _socket: &Address,
+    ) -> Option<response::Message> {
+        None
+    }
+
+    /// Inspect or change the response to a request
+    fn after(
+        &self,
+        _request_message: &request::Message,
+        _response_message: &mut response::Message,
+        _context: &mut Context,
+        _application: &Application,
+        _socket: &Address,
+    ) {
+    }
+}
+
+impl fmt::Debug for dyn MiddlewareInterface {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "MiddlewareInterface")
     }
 }
 
-impl Clone for Box<ResponderInterface + Send> {
-    fn clone(&self) -> Box<ResponderInterface + Send> {
-        self.clone_box()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use test_config;
+
+    /// Answers requests without an Authorization header field itself
+    struct Authorization {}
+
+    impl MiddlewareInterface for Authorization {
+        fn before(
+            &self,
+            request_message: &mut request::Message,
+            _context: &mut Context,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<response::Message> {
+            if request_message.headers.contains_key("Authorization") {
+                return None;
+            }
+            Some(response::Message::new(
+                "HTTP/1.1".to_string(),
+                "401 Unauthorized".to_string(),
+                HashMap::new(),
+                Vec::new(),
+            ))
+        }
+    }
+
+    /// Rewrites requests for /old and /broken to /index.htm
+    struct Rewrite {}
+
+    impl MiddlewareInterface for Rewrite {
+        fn before(
+            &self,
+            request_message: &mut request::Message,
+            _context: &mut Context,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<response::Message> {
+            let path = &request_message.request_line.request_uri_base;
+            if path == "/old" || path == "/broken" {
+                request_message.request_line.request_uri_base = "/index.htm".to_string();
+            }
+            None
+        }
+    }
+
+    /// Appends its name to the X-Order header field of responses
+    struct Tag {
+        name: &'static str,
+    }
+
+    impl MiddlewareInterface for Tag {
+        fn after(
+            &self,
+            _request_message: &request::Message,
+            response_message: &mut response::Message,
+            _context: &mut Context,
+            _application: &Application,
+            _socket: &Address,
+        ) {
+            let order = match response_message.get_header("X-Order") {
+                Some(order) => format!("{},{}", order, self.name),
+                None => self.name.to_string(),
+            };
+            response_message
+                .headers
+                .insert("X-Order".to_string(), order);
+        }
+    }
+
+    /// Name of the client that middleware attaches to requests
+    struct User(String);
+
+    /// Shared state of the application
+    struct Greeting(&'static str);
+
+    /// Attaches the user of the X-User header field to requests
+    struct Identify {}
+
+    impl MiddlewareInterface for Identify {
+        fn before(
+            &self,
+            request_message: &mut request::Message,
+            context: &mut Context,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<response::Message> {
+            if let Some(user) = request_message.headers.get("X-User") {
+                context.extensions.insert(User(user.to_string()));
+            }
+            None
+        }
+    }
+
+    /// Greets the user of requests for /greet
+    struct Greeter {}
+
+    impl ResponderInterface for Greeter {
+        fn matches(
+            &self,
+            request_message: &request::Message,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<Box<dyn HandlerInterface>> {
+            if request_message.request_line.request_uri_base == "/greet" {
+                return Some(Box::new(Greeter {}));
+            }
+            None
+        }
+    }
+
+    impl HandlerInterface for Greeter {
+        fn respond(
+            self: Box<Self>,
+            _request_message: &request::Message,
+            context: &Context,
+            application: &Application,
+            _socket: &Address,
+        ) -> Result<response::Message, String> {
+            let greeting = application.get_state::<Greeting>().unwrap();
+            let user = match context.extensions.get::<User>() {
+                Some(user) => user.0.clone(),
+                None => "stranger".to_string(),
+            };
+            let mut headers = HashMap::new();
+            headers.insert("X-Greeting".to_string(), format!("{} {}", greeting.0, user));
+            Ok(response::Message::new(
+                "HTTP/1.1".to_string(),
+                "200 OK".to_string(),
+                headers,
+                Vec::new(),
+            ))
+        }
+    }
+
+    /// Fails to respond to requests for /broken after its middleware rewrote them
+    struct Broken {
+        middleware: Vec<Arc<dyn MiddlewareInterface>>,
+    }
+
+    impl ResponderInterface for Broken {
+        fn matches(
+            &self,
+            request_message: &request::Message,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<Box<dyn HandlerInterface>> {
+            if request_message.request_line.request_uri_base == "/broken" {
+                return Some(Box::new(Broken {
+                    middleware: vec![Arc::new(Tag { name: "route" }), Arc::new(Rewrite {})],
+                }));
+            }
+            None
+        }
+    }
+
+    impl HandlerInterface for Broken {
+        fn respond(
+            self: Box<Self>,
+            _request_message: &request::Message,
+            _context: &Context,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Result<response::Message, String> {
+            Err("Broken".to_string())
+        }
+
+        fn get_middleware(&self) -> &[Arc<dyn MiddlewareInterface>] {
+            &self.middleware
+        }
+    }
+
+    fn get_application() -> Application {
+        Application::new(test_config())
+    }
+
+    fn get_response(application: &Application, request: &[u8]) -> response::Message {
+        let socket = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        ));
+        let responders: Vec<Box<dyn ResponderInterface>> = vec![
+            Box::new(Greeter {}),
+            Box::new(Broken {
+                middleware: Vec::new(),
+            }),
+            Box::new(filesystem::Responder::new()),
+            Box::new(error::Responder::new()),
+        ];
+        let mut dispatcher = Dispatcher::new();
+        assert!(dispatcher.matches(request, application, &socket));
+        dispatcher
+            .respond(request, application, &socket, &responders)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_middleware() {
+        let application = get_application()
+            .with_middleware(Arc::new(Tag { name: "first" }))
+            .with_middleware(Arc::new(Authorization {}))
+            .with_middleware(Arc::new(Rewrite {}))
+            .with_middleware(Arc::new(Tag { name: "last" }));
+
+        // Middleware after the one that answers a request does not run
+        let response = get_response(&application, b"GET /old HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status, "401 Unauthorized");
+        assert_eq!(response.get_header("X-Order"), Some(&"first".to_string()));
+
+        let response = get_response(
+            &application,
+            b"GET /old HTTP/1.1\r\nAuthorization: Basic YTpi\r\n\r\n",
+        );
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(
+            response.get_header("X-Order"),
+            Some(&"last,first".to_string())
+        );
+
+        let response = get_response(
+            &application,
+            b"GET /missing.htm HTTP/1.1\r\nAuthorization: Basic YTpi\r\n\r\n",
+        );
+        assert_eq!(response.status, "500 Internal Server Error");
+        assert_eq!(
+            response.get_header("X-Order"),
+            Some(&"last,first".to_string())
+        );
+
+        // The responders after a failing handler answer its rewritten request inside its
+        // middleware
+        let response = get_response(&get_application(), b"GET /broken HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(response.get_header("X-Order"), Some(&"route".to_string()));
+    }
+
+    #[test]
+    fn test_state() {
+        let application = get_application()
+            .with_state(Greeting("Hello"))
+            .with_middleware(Arc::new(Identify {}));
+
+        let response = get_response(&application, b"GET /greet HTTP/1.1\r\nX-User: Ada\r\n\r\n");
+        assert_eq!(
+            response.get_header("X-Greeting"),
+            Some(&"Hello Ada".to_string())
+        );
+        // Extensions do not outlive their request
+        let response = get_response(&application, b"GET /greet HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            response.get_header("X-Greeting"),
+            Some(&"Hello stranger".to_string())
+        );
     }
 }
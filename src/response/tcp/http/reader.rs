@@ -0,0 +1,1176 @@
+//! # TCP HTTP Request reader
+//! Frames HTTP requests from a byte stream by reading the head until the header terminator
+//! and then exactly `Content-Length` bytes of body, or decoding a chunked body.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+use metrics::Timeout;
+
+/// Maximum size in bytes of a request line and its header fields.
+pub const HEAD_LIMIT: usize = 8192;
+
+const HEAD_TERMINATOR: &[u8] = b"\r\n\r\n";
+
+/// Methods that requests are decoded for
+const SUPPORTED_METHODS: [&str; 9] = [
+    "CONNECT", "DELETE", "GET", "HEAD", "OPTIONS", "PATCH", "POST", "PUT", "TRACE",
+];
+
+/// HTTP versions that requests are decoded for
+const SUPPORTED_VERSIONS: [&str; 4] = ["HTTP/0.9", "HTTP/1.0", "HTTP/1.1", "HTTP/2.0"];
+
+/// Header fields that frame, route or authenticate a request and are dropped from trailers,
+/// the head has already been validated without them
+const FORBIDDEN_TRAILERS: [&str; 10] = [
+    "Authorization",
+    "Connection",
+    "Content-Length",
+    "Cookie",
+    "Forwarded",
+    "Host",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "X-Forwarded-",
+];
+
+/// Errors that can occur while framing a request from a stream
+#[derive(Debug)]
+pub enum Error {
+    /// The stream was closed before any byte of a new request arrived
+    Closed,
+    /// The stream was closed before the header terminator was received
+    IncompleteHead(usize),
+    /// The stream was closed before the announced body was received, (expected, received)
+    IncompleteBody(u64, u64),
+    /// The request line and header fields exceeded the head limit
+    HeadTooLarge(usize),
+    /// The request line alone exceeded the head limit
+    RequestLineTooLarge(usize),
+    /// The Content-Length header field could not be parsed, was repeated with another value or
+    /// came with Transfer-Encoding
+    InvalidContentLength(String),
+    /// A chunk size line or chunk terminator of a chunked body was malformed
+    InvalidChunk(String),
+    /// The announced body exceeded the limit of the request size, (announced, limit)
+    PayloadTooLarge(u64, usize),
+    /// The stream was closed before the last chunk was received, (received)
+    IncompleteChunkedBody(u64),
+    /// The Transfer-Encoding header field did not end with chunked
+    UnsupportedTransferEncoding(String),
+    /// The head, body or whole request was not received before its deadline
+    Timeout(Timeout),
+    /// Failed to read from the underlying stream
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Closed => write!(f, "Stream was closed before a request was received"),
+            Error::IncompleteHead(received) => write!(
+                f,
+                "Stream was closed after {} bytes before end of header fields",
+                received
+            ),
+            Error::IncompleteBody(expected, received) => write!(
+                f,
+                "Stream was closed after {} of {} bytes of message body",
+                received, expected
+            ),
+            Error::HeadTooLarge(limit) => {
+                write!(f, "Request header fields exceeded limit of {} bytes", limit)
+            }
+            Error::RequestLineTooLarge(limit) => {
+                write!(f, "Request line exceeded limit of {} bytes", limit)
+            }
+            Error::InvalidContentLength(value) => {
+                write!(f, "Invalid Content-Length header field: {:?}", value)
+            }
+            Error::InvalidChunk(line) => write!(f, "Invalid chunk in message body: {:?}", line),
+            Error::PayloadTooLarge(announced, limit) => write!(
+                f,
+                "Announced message body of {} bytes exceeded request limit of {} bytes",
+                announced, limit
+            ),
+            Error::IncompleteChunkedBody(received) => write!(
+                f,
+                "Stream was closed after {} bytes of chunked message body",
+                received
+            ),
+            Error::UnsupportedTransferEncoding(value) => {
+                write!(f, "Unsupported Transfer-Encoding header field: {:?}", value)
+            }
+            Error::Timeout(timeout) => write!(f, "Request timed out, time-out: {:?}", timeout),
+            Error::Io(error) => write!(f, "Failed to read from stream, error: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl Error {
+    /// Status of the response that tells the client why its request was refused, errors of
+    /// clients that went away or failed streams have no response
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http::reader::{Error, HEAD_LIMIT};
+    /// assert_eq!(
+    ///     Error::RequestLineTooLarge(HEAD_LIMIT).get_status(),
+    ///     Some("414 URI Too Long")
+    /// );
+    /// assert_eq!(Error::IncompleteHead(10).get_status(), None);
+    /// ```
+    pub fn get_status(&self) -> Option<&'static str> {
+        match self {
+            Error::HeadTooLarge(_) => Some("431 Request Header Fields Too Large"),
+            Error::RequestLineTooLarge(_) => Some("414 URI Too Long"),
+            Error::InvalidContentLength(_) | Error::InvalidChunk(_) => Some("400 Bad Request"),
+            Error::PayloadTooLarge(_, _) => Some("413 Payload Too Large"),
+            Error::UnsupportedTransferEncoding(_) => Some("501 Not Implemented"),
+            Error::Timeout(_) => Some("408 Request Timeout"),
+            Error::Closed
+            | Error::IncompleteHead(_)
+            | Error::IncompleteBody(_, _)
+            | Error::IncompleteChunkedBody(_)
+            | Error::Io(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+/// A framed HTTP request
+#[derive(Debug)]
+pub struct Request {
+    /// Request line and header fields including the header terminator
+    pub head: Vec<u8>,
+    /// Message body, limited by the byte limit
+    pub body: Vec<u8>,
+    /// Number of body bytes that was read but discarded because of the byte limit
+    pub overflow_bytes: u64,
+}
+
+impl Request {
+    /// Get the first line of a request head
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http::reader::Request;
+    /// assert_eq!(
+    ///     Request::get_request_line(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+    ///     "GET / HTTP/1.1".to_string()
+    /// );
+    /// ```
+    pub fn get_request_line(head: &[u8]) -> String {
+        let end = Request::find(head, b"\r\n").unwrap_or(head.len());
+        String::from_utf8_lossy(&head[..end]).to_string()
+    }
+
+    /// Status of the response to a request head that could not be decoded, requests of an
+    /// unknown method or HTTP version are told apart from malformed requests
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http::reader::Request;
+    /// assert_eq!(
+    ///     Request::get_invalid_status(b"BREW /pot HTTP/1.1\r\n\r\n"),
+    ///     "501 Not Implemented"
+    /// );
+    /// assert_eq!(
+    ///     Request::get_invalid_status(b"GET / HTTP/3.0\r\n\r\n"),
+    ///     "505 HTTP Version Not Supported"
+    /// );
+    /// assert_eq!(Request::get_invalid_status(b"GET /\t HTTP\r\n\r\n"), "400 Bad Request");
+    /// ```
+    pub fn get_invalid_status(head: &[u8]) -> &'static str {
+        let line = Request::get_request_line(head);
+        let parts: Vec<&str> = line.split(' ').collect();
+        if parts.len() == 3 {
+            let (method, version) = (parts[0], parts[2]);
+            let valid_version = version.len() == 8
+                && version.starts_with("HTTP/")
+                && version.as_bytes()[6] == b'.'
+                && version.as_bytes()[5].is_ascii_digit()
+                && version.as_bytes()[7].is_ascii_digit();
+            if valid_version && !SUPPORTED_VERSIONS.contains(&version) {
+                return "505 HTTP Version Not Supported";
+            }
+            let valid_method = !method.is_empty()
+                && method
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte));
+            if valid_version && valid_method && !SUPPORTED_METHODS.contains(&method) {
+                return "501 Not Implemented";
+            }
+        }
+        "400 Bad Request"
+    }
+
+    /// Find the value of a header field by case-insensitive name
+    pub fn get_header_field(head: &[u8], name: &str) -> Option<String> {
+        let head = String::from_utf8_lossy(head);
+        for line in head.split("\r\n").skip(1) {
+            let parts: Vec<&str> = line.splitn(2, ':').collect();
+            if parts.len() == 2 && parts[0].trim().eq_ignore_ascii_case(name) {
+                return Some(parts[1].trim().to_string());
+            }
+        }
+        None
+    }
+
+    /// Get the announced body length of a request head, every Content-Length header field has
+    /// to be digits only and repeated fields have to agree
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http::reader::Request;
+    /// assert_eq!(
+    ///     Request::get_content_length(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n").unwrap(),
+    ///     5
+    /// );
+    /// assert_eq!(
+    ///     Request::get_content_length(
+    ///         b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\n"
+    ///     )
+    ///     .unwrap(),
+    ///     5
+    /// );
+    /// assert!(Request::get_content_length(
+    ///     b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 0\r\n\r\n"
+    /// )
+    /// .is_err());
+    /// assert!(
+    ///     Request::get_content_length(b"POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\n").is_err()
+    /// );
+    /// ```
+    pub fn get_content_length(head: &[u8]) -> Result<u64, Error> {
+        let head = String::from_utf8_lossy(head);
+        let mut content_length = None;
+        for line in head.split("\r\n").skip(1) {
+            let parts: Vec<&str> = line.splitn(2, ':').collect();
+            if parts.len() != 2 || !parts[0].trim().eq_ignore_ascii_case("Content-Length") {
+                continue;
+            }
+            let value = parts[1].trim();
+            let length = match parse_number(value, 10) {
+                Some(length) => length,
+                None => return Err(Error::InvalidContentLength(value.to_string())),
+            };
+            if content_length.is_some() && content_length != Some(length) {
+                return Err(Error::InvalidContentLength(value.to_string()));
+            }
+            content_length = Some(length);
+        }
+        Ok(content_length.unwrap_or(0))
+    }
+
+    /// Whether the client wants the connection to persist after the response, HTTP/1.1
+    /// connections persist and HTTP/1.0 connections close unless the Connection header says otherwise
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http::reader::Request;
+    /// assert!(Request::is_keep_alive(b"GET / HTTP/1.1\r\n\r\n"));
+    /// assert!(!Request::is_keep_alive(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n"));
+    /// assert!(!Request::is_keep_alive(b"GET / HTTP/1.0\r\n\r\n"));
+    /// assert!(Request::is_keep_alive(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n"));
+    /// ```
+    pub fn is_keep_alive(head: &[u8]) -> bool {
+        let mut keep_alive = Request::get_request_line(head).ends_with(" HTTP/1.1");
+        if let Some(connection) = Request::get_header_field(head, "Connection") {
+            for option in connection.split(',') {
+                let option = option.trim();
+                if option.eq_ignore_ascii_case("close") {
+                    return false;
+                } else if option.eq_ignore_ascii_case("keep-alive") {
+                    keep_alive = true;
+                }
+            }
+        }
+        keep_alive
+    }
+
+    /// Build the head of a decoded chunked request, the Transfer-Encoding header field is
+    /// replaced by Content-Length and trailer fields are appended to the header fields except
+    /// those that frame, route or authenticate a request
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http::reader::Request;
+    /// assert_eq!(
+    ///     Request::get_decoded_head(
+    ///         b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nHost: localhost\r\n\r\n",
+    ///         &vec![b"Expires: never".to_vec(), b"Host: example.com".to_vec()],
+    ///         12
+    ///     ),
+    ///     b"POST / HTTP/1.1\r\nHost: localhost\r\nExpires: never\r\nContent-Length: 12\r\n\r\n".to_vec()
+    /// );
+    /// ```
+    pub fn get_decoded_head(head: &[u8], trailers: &[Vec<u8>], length: u64) -> Vec<u8> {
+        let mut decoded = Vec::with_capacity(head.len());
+        let end = head.len().saturating_sub(HEAD_TERMINATOR.len());
+        for (index, line) in head[..end].split(|byte| *byte == b'\n').enumerate() {
+            let line = match line.last() {
+                Some(b'\r') => &line[..line.len() - 1],
+                _ => line,
+            };
+            if index > 0 {
+                let name = line.split(|byte| *byte == b':').next().unwrap_or(&[]);
+                let name = String::from_utf8_lossy(name);
+                let name = name.trim();
+                if name.eq_ignore_ascii_case("Transfer-Encoding")
+                    || name.eq_ignore_ascii_case("Content-Length")
+                {
+                    continue;
+                }
+            }
+            decoded.extend_from_slice(line);
+            decoded.extend_from_slice(b"\r\n");
+        }
+        for trailer in trailers {
+            if Request::is_forbidden_trailer(trailer) {
+                continue;
+            }
+            decoded.extend_from_slice(trailer);
+            decoded.extend_from_slice(b"\r\n");
+        }
+        decoded.extend_from_slice(format!("Content-Length: {}\r\n\r\n", length).as_bytes());
+        decoded
+    }
+
+    /// Whether a trailer field is not allowed to be merged into the header fields
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http::reader::Request;
+    /// assert!(Request::is_forbidden_trailer(b"content-length: 0"));
+    /// assert!(Request::is_forbidden_trailer(b"X-Forwarded-For: 10.0.0.1"));
+    /// assert!(!Request::is_forbidden_trailer(b"Checksum: 42"));
+    /// ```
+    pub fn is_forbidden_trailer(trailer: &[u8]) -> bool {
+        let name = match trailer.iter().position(|byte| *byte == b':') {
+            Some(position) => String::from_utf8_lossy(&trailer[..position]).to_string(),
+            None => return true,
+        };
+        let name = name.trim();
+        FORBIDDEN_TRAILERS.iter().any(|forbidden| {
+            if forbidden.ends_with('-') {
+                name.len() >= forbidden.len()
+                    && name.as_bytes()[..forbidden.len()].eq_ignore_ascii_case(forbidden.as_bytes())
+            } else {
+                name.eq_ignore_ascii_case(forbidden)
+            }
+        })
+    }
+
+    /// Get the length of a request head including the header terminator if it has been received
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http::reader::Request;
+    /// assert_eq!(Request::get_head_length(b"GET / HTTP/1.1\r\n\r\nabc"), Some(18));
+    /// assert_eq!(Request::get_head_length(b"GET / HTTP/1.1\r\n"), None);
+    /// ```
+    pub fn get_head_length(buffer: &[u8]) -> Option<usize> {
+        Request::find(buffer, HEAD_TERMINATOR).map(|position| position + HEAD_TERMINATOR.len())
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+
+    /// Concatenate head and body into one buffer
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.head.len() + self.body.len());
+        bytes.extend_from_slice(&self.head);
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+/// A stream that can limit how long a read may block
+pub trait ReadTimeout: Read {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for &TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl ReadTimeout for UnixStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl<T: AsRef<[u8]>> ReadTimeout for Cursor<T> {
+    fn set_read_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads requests from a stream, bytes received after a request are kept for the next one
+pub struct Reader<R> {
+    body_timeout: Option<Duration>,
+    deadline: Option<(Instant, Timeout)>,
+    framer: Framer,
+    header_timeout: Option<Duration>,
+    request_deadline: Option<Instant>,
+    stream: R,
+}
+
+impl<R: ReadTimeout> Reader<R> {
+    pub fn new(stream: R) -> Reader<R> {
+        Reader {
+            body_timeout: None,
+            deadline: None,
+            framer: Framer::new(),
+            header_timeout: None,
+            request_deadline: None,
+            stream,
+        }
+    }
+
+    /// Limit the time from the start of reading a request until its head is received and
+    /// from then until its body is received
+    pub fn set_timeouts(
+        &mut self,
+        header_timeout: Option<Duration>,
+        body_timeout: Option<Duration>,
+    ) {
+        self.header_timeout = header_timeout;
+        self.body_timeout = body_timeout;
+    }
+
+    /// Limit the time until the next request has to be received completely
+    pub fn set_request_deadline(&mut self, request_deadline: Option<Instant>) {
+        self.request_deadline = request_deadline;
+    }
+
+    /// Start a reading phase, the earliest of the phase and request deadline applies
+    fn start_phase(&mut self, timeout: Option<Duration>, kind: Timeout) -> Result<(), Error> {
+        let phase_deadline = timeout.map(|timeout| (Instant::now() + timeout, kind));
+        let request_deadline = self
+            .request_deadline
+            .map(|deadline| (deadline, Timeout::Request));
+        self.deadline = match (phase_deadline, request_deadline) {
+            (Some(phase), Some(request)) => Some(if request.0 < phase.0 { request } else { phase }),
+            (phase, request) => phase.or(request),
+        };
+        if self.deadline.is_none() {
+            self.stream.set_read_timeout(None)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.stream
+    }
+
+    /// Get the stream back with the bytes that were received after the last request
+    pub fn into_parts(self) -> (R, Vec<u8>) {
+        (self.stream, self.framer.into_inner())
+    }
+
+    /// Whether bytes of a following request have already been received
+    pub fn has_buffered(&self) -> bool {
+        !self.framer.is_empty()
+    }
+
+    /// Read more bytes from the stream into the framer, returns number of read bytes
+    fn fill(&mut self) -> Result<usize, Error> {
+        let mut temp_buffer = [0; 4096];
+        loop {
+            if let Some((deadline, kind)) = self.deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(Error::Timeout(kind));
+                }
+                self.stream.set_read_timeout(Some(deadline - now))?;
+            }
+            match self.stream.read(&mut temp_buffer) {
+                Ok(read_size) => {
+                    self.framer.push(&temp_buffer[..read_size]);
+                    return Ok(read_size);
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref error)
+                    if self.deadline.is_some()
+                        && (error.kind() == io::ErrorKind::WouldBlock
+                            || error.kind() == io::ErrorKind::TimedOut) => {}
+                Err(error) => return Err(Error::Io(error)),
+            }
+        }
+    }
+
+    /// Read the next request from the stream, an announced body beyond `limit` bytes of total
+    /// request size is refused and chunked body bytes beyond it are counted as overflow bytes.
+    pub fn read_request(&mut self, limit: usize) -> Result<Request, Error> {
+        let header_timeout = self.header_timeout;
+        self.start_phase(header_timeout, Timeout::Header)?;
+        let mut reading_body = false;
+        loop {
+            let request = self.framer.next(limit)?;
+            if !reading_body && (request.is_some() || self.framer.has_head()) {
+                reading_body = true;
+                let body_timeout = self.body_timeout;
+                self.start_phase(body_timeout, Timeout::Body)?;
+            }
+            if let Some(request) = request {
+                return Ok(request);
+            }
+            if self.fill()? == 0 {
+                return Err(self.framer.get_closed_error());
+            }
+        }
+    }
+}
+
+/// Frames requests from bytes as they arrive without blocking. What was parsed is kept
+/// between calls so every byte is looked at once, a request keeps at most the head limit and
+/// `limit` bytes in memory. An announced body beyond them is refused and chunked body bytes
+/// beyond them are counted as overflow bytes.
+/// ```rust
+/// use milstian_internet_framework::response::tcp::http::reader::Framer;
+/// let mut framer = Framer::new();
+/// framer.push(b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nte");
+/// assert!(framer.next(1024).unwrap().is_none());
+/// assert!(framer.has_head());
+/// framer.push(b"stGET / HTTP/1.1\r\n");
+/// assert_eq!(framer.next(1024).unwrap().unwrap().body, b"test".to_vec());
+/// assert!(framer.next(1024).unwrap().is_none());
+/// assert_eq!(framer.into_inner(), b"GET / HTTP/1.1\r\n".to_vec());
+/// ```
+#[derive(Debug, Default)]
+pub struct Framer {
+    buffer: Vec<u8>,
+    /// Bytes of the current request that were taken from the buffer
+    consumed: u64,
+    partial: Option<Partial>,
+    /// Bytes at the start of the buffer that were searched for a terminator
+    searched: usize,
+}
+
+/// A request whose head has been received
+#[derive(Debug)]
+struct Partial {
+    body: Vec<u8>,
+    body_limit: usize,
+    coding: Coding,
+    head: Vec<u8>,
+    overflow_bytes: u64,
+    /// Bytes of body that were received, of a chunked body without the chunk framing
+    received: u64,
+}
+
+/// How the end of a body is found
+#[derive(Debug)]
+enum Coding {
+    /// The announced length of the body
+    Length(u64),
+    Chunked(Chunked),
+}
+
+/// What a chunked body waits for
+#[derive(Debug)]
+enum Chunked {
+    Size,
+    /// The remaining bytes of a chunk
+    Data(u64),
+    Terminator,
+    /// The trailer fields so far and their size
+    Trailers(Vec<Vec<u8>>, usize),
+}
+
+impl Framer {
+    pub fn new() -> Framer {
+        Framer::default()
+    }
+
+    /// Add received bytes
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Whether the head of the current request has been received
+    pub fn has_head(&self) -> bool {
+        self.partial.is_some()
+    }
+
+    /// Whether no byte of a following request has been received
+    pub fn is_empty(&self) -> bool {
+        self.partial.is_none() && self.buffer.is_empty()
+    }
+
+    /// Number of bytes of the current request that have been received
+    pub fn get_received(&self) -> u64 {
+        self.consumed + self.buffer.len() as u64
+    }
+
+    /// Get the bytes that were received after the last request
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    /// Frame the next request from the bytes received so far, `None` if it is not complete
+    pub fn next(&mut self, limit: usize) -> Result<Option<Request>, Error> {
+        if self.partial.is_none() {
+            let head_length = match Request::find(&self.buffer[self.searched..], HEAD_TERMINATOR) {
+                Some(position) => self.searched + position + HEAD_TERMINATOR.len(),
+                None if self.buffer.len() > HEAD_LIMIT => return Err(self.get_head_error()),
+                None => {
+                    self.searched = self.buffer.len().saturating_sub(HEAD_TERMINATOR.len() - 1);
+                    return Ok(None);
+                }
+            };
+            if head_length > HEAD_LIMIT {
+                return Err(self.get_head_error());
+            }
+            let head: Vec<u8> = self.buffer.drain(..head_length).collect();
+            self.consumed = head_length as u64;
+            self.searched = 0;
+            self.partial = Some(Partial::new(head, limit)?);
+        }
+
+        let length = self.buffer.len();
+        let complete = match self.partial {
+            Some(ref mut partial) => partial.decode(&mut self.buffer, &mut self.searched)?,
+            None => false,
+        };
+        self.consumed += (length - self.buffer.len()) as u64;
+        if !complete {
+            return Ok(None);
+        }
+        self.consumed = 0;
+        Ok(self.partial.take().map(|partial| Request {
+            head: partial.head,
+            body: partial.body,
+            overflow_bytes: partial.overflow_bytes,
+        }))
+    }
+
+    /// Error of a stream that was closed before the current request was complete
+    pub fn get_closed_error(&self) -> Error {
+        match self.partial {
+            None if self.buffer.is_empty() => Error::Closed,
+            None => Error::IncompleteHead(self.buffer.len()),
+            Some(ref partial) => match partial.coding {
+                Coding::Length(length) => Error::IncompleteBody(length, partial.received),
+                Coding::Chunked(_) => Error::IncompleteChunkedBody(partial.received),
+            },
+        }
+    }
+
+    /// Error of a head that exceeded the head limit, a request line that did not end within
+    /// the limit is told apart from too many header fields
+    fn get_head_error(&self) -> Error {
+        let end = self.buffer.len().min(HEAD_LIMIT);
+        match Request::find(&self.buffer[..end], b"\r\n") {
+            Some(_) => Error::HeadTooLarge(HEAD_LIMIT),
+            None => Error::RequestLineTooLarge(HEAD_LIMIT),
+        }
+    }
+}
+
+impl Partial {
+    /// Find how the body of a request head ends
+    fn new(head: Vec<u8>, limit: usize) -> Result<Partial, Error> {
+        let coding = match Request::get_header_field(&head, "Transfer-Encoding") {
+            Some(transfer_encoding) => {
+                // Chunked has to be the final transfer-coding of a request
+                let final_coding = transfer_encoding.rsplit(',').next().unwrap_or("").trim();
+                if !final_coding.eq_ignore_ascii_case("chunked") {
+                    return Err(Error::UnsupportedTransferEncoding(transfer_encoding));
+                }
+                // Proxies that frame by the other header field would see another request
+                if let Some(content_length) = Request::get_header_field(&head, "Content-Length") {
+                    return Err(Error::InvalidContentLength(content_length));
+                }
+                Coding::Chunked(Chunked::Size)
+            }
+            None => {
+                // A body that can not fit is refused before any of it is read
+                let length = Request::get_content_length(&head)?;
+                if length > limit.saturating_sub(head.len()) as u64 {
+                    return Err(Error::PayloadTooLarge(length, limit));
+                }
+                Coding::Length(length)
+            }
+        };
+        Ok(Partial {
+            body: Vec::new(),
+            body_limit: limit.saturating_sub(head.len()),
+            coding,
+            head,
+            overflow_bytes: 0,
+            received: 0,
+        })
+    }
+
+    /// Move body bytes out of the buffer, returns whether the body is complete
+    fn decode(&mut self, buffer: &mut Vec<u8>, searched: &mut usize) -> Result<bool, Error> {
+        loop {
+            let chunked = match self.coding {
+                Coding::Length(length) => {
+                    let take = (buffer.len() as u64).min(length - self.received) as usize;
+                    self.take(buffer, take);
+                    return Ok(self.received == length);
+                }
+                Coding::Chunked(Chunked::Size) => {
+                    let line = match take_line(buffer, searched)? {
+                        Some(line) => String::from_utf8_lossy(&line).to_string(),
+                        None => return Ok(false),
+                    };
+                    // Chunk extensions are ignored
+                    let size = line.split(';').next().unwrap_or("").trim();
+                    match parse_number(size, 16) {
+                        Some(0) => Chunked::Trailers(Vec::new(), 0),
+                        Some(size) => Chunked::Data(size),
+                        None => return Err(Error::InvalidChunk(line)),
+                    }
+                }
+                Coding::Chunked(Chunked::Data(remaining)) => {
+                    let take = (buffer.len() as u64).min(remaining) as usize;
+                    self.take(buffer, take);
+                    if (take as u64) < remaining {
+                        self.coding = Coding::Chunked(Chunked::Data(remaining - take as u64));
+                        return Ok(false);
+                    }
+                    Chunked::Terminator
+                }
+                Coding::Chunked(Chunked::Terminator) => match take_line(buffer, searched)? {
+                    Some(ref line) if line.is_empty() => Chunked::Size,
+                    Some(line) => {
+                        return Err(Error::InvalidChunk(
+                            String::from_utf8_lossy(&line).to_string(),
+                        ))
+                    }
+                    None => return Ok(false),
+                },
+                Coding::Chunked(Chunked::Trailers(ref mut trailers, ref mut trailers_size)) => {
+                    let line = match take_line(buffer, searched)? {
+                        Some(line) => line,
+                        None => return Ok(false),
+                    };
+                    if line.is_empty() {
+                        let length = self.body.len() as u64 + self.overflow_bytes;
+                        self.head = Request::get_decoded_head(&self.head, trailers, length);
+                        return Ok(true);
+                    }
+                    *trailers_size += line.len();
+                    if *trailers_size > HEAD_LIMIT {
+                        return Err(Error::HeadTooLarge(HEAD_LIMIT));
+                    }
+                    trailers.push(line);
+                    continue;
+                }
+            };
+            self.coding = Coding::Chunked(chunked);
+        }
+    }
+
+    /// Take bytes of body from the buffer, bytes beyond the body limit are only counted
+    fn take(&mut self, buffer: &mut Vec<u8>, take: usize) {
+        let keep = take.min(self.body_limit.saturating_sub(self.body.len()));
+        self.body.extend_from_slice(&buffer[..keep]);
+        self.overflow_bytes += (take - keep) as u64;
+        self.received += take as u64;
+        buffer.drain(..take);
+    }
+}
+
+/// Parse a number of only digits in the radix, unlike `u64::from_str_radix` no sign is accepted
+fn parse_number(value: &str, radix: u32) -> Option<u64> {
+    if value.is_empty() || !value.chars().all(|character| character.is_digit(radix)) {
+        return None;
+    }
+    u64::from_str_radix(value, radix).ok()
+}
+
+/// Take a line terminated by CRLF from the buffer, the terminator is not included
+fn take_line(buffer: &mut Vec<u8>, searched: &mut usize) -> Result<Option<Vec<u8>>, Error> {
+    match Request::find(&buffer[*searched..], b"\r\n") {
+        Some(position) => {
+            let end = *searched + position;
+            *searched = 0;
+            Ok(Some(buffer.drain(..end + 2).take(end).collect()))
+        }
+        None if buffer.len() > HEAD_LIMIT => Err(Error::InvalidChunk(format!(
+            "Line exceeded limit of {} bytes",
+            HEAD_LIMIT
+        ))),
+        None => {
+            *searched = buffer.len().saturating_sub(1);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    /// A stream that returns its data in segments of a fixed size
+    struct Segmented {
+        data: Vec<u8>,
+        position: usize,
+        segment: usize,
+    }
+
+    impl Read for Segmented {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let end = (self.position + self.segment)
+                .min(self.data.len())
+                .min(self.position + buffer.len());
+            let size = end - self.position;
+            buffer[..size].copy_from_slice(&self.data[self.position..end]);
+            self.position = end;
+            Ok(size)
+        }
+    }
+
+    impl ReadTimeout for Segmented {
+        fn set_read_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A stream that returns its data and then times out like a socket with a read time-out
+    struct Stalled {
+        data: Cursor<Vec<u8>>,
+        timeout: Option<Duration>,
+    }
+
+    impl Read for Stalled {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let size = self.data.read(buffer)?;
+            if size > 0 {
+                return Ok(size);
+            }
+            if let Some(timeout) = self.timeout {
+                ::std::thread::sleep(timeout);
+            }
+            Err(io::Error::new(io::ErrorKind::WouldBlock, "Read timed out"))
+        }
+    }
+
+    impl ReadTimeout for Stalled {
+        fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+            self.timeout = timeout;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_request() {
+        let mut reader = Reader::new(Cursor::new(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec(),
+        ));
+        let request = reader.read_request(1024).unwrap();
+        assert_eq!(
+            request.head,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()
+        );
+        assert!(request.body.is_empty());
+        assert_eq!(request.overflow_bytes, 0);
+        match reader.read_request(1024) {
+            Err(Error::Closed) => {}
+            other => panic!("Expected closed stream, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_request_body_in_segments() {
+        let mut data = b"POST / HTTP/1.1\r\ncontent-length: 600\r\n\r\n".to_vec();
+        let mut body = Vec::new();
+        for index in 0..600 {
+            body.push((index % 3) as u8);
+        }
+        data.extend_from_slice(&body);
+        let mut reader = Reader::new(Segmented {
+            data,
+            position: 0,
+            segment: 7,
+        });
+        let request = reader.read_request(4096).unwrap();
+        assert_eq!(request.body, body);
+        assert_eq!(request.overflow_bytes, 0);
+    }
+
+    #[test]
+    fn read_request_overflow() {
+        let mut reader = Reader::new(Cursor::new(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\na\r\n0123456789\r\n0\r\n\r\n"
+                .to_vec(),
+        ));
+        let request = reader.read_request(52).unwrap();
+        assert_eq!(request.body, b"01234".to_vec());
+        assert_eq!(request.overflow_bytes, 5);
+
+        // An announced body that can not fit is refused before it is read
+        let mut reader = Reader::new(Cursor::new(
+            b"POST / HTTP/1.1\r\nContent-Length: 10000000000\r\n\r\n0123456789".to_vec(),
+        ));
+        match reader.read_request(1024) {
+            Err(Error::PayloadTooLarge(10000000000, 1024)) => {}
+            other => panic!("Expected too large payload, got {:?}", other),
+        }
+        assert_eq!(reader.into_parts().1, b"0123456789".to_vec());
+    }
+
+    #[test]
+    fn read_request_errors() {
+        let mut reader = Reader::new(Cursor::new(b"GET / HTTP/1.1\r\nHost: loc".to_vec()));
+        match reader.read_request(1024) {
+            Err(Error::IncompleteHead(25)) => {}
+            other => panic!("Expected incomplete head, got {:?}", other),
+        }
+
+        let mut reader = Reader::new(Cursor::new(
+            b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n01234".to_vec(),
+        ));
+        match reader.read_request(1024) {
+            Err(Error::IncompleteBody(10, 5)) => {}
+            other => panic!("Expected incomplete body, got {:?}", other),
+        }
+
+        for head in [
+            &b"POST / HTTP/1.1\r\nContent-Length: ten\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nContent-Length: +4\r\n\r\ntest"[..],
+            &b"POST / HTTP/1.1\r\nContent-Length: 4\r\ncontent-length: 0\r\n\r\ntest"[..],
+        ]
+        .iter()
+        {
+            let mut reader = Reader::new(Cursor::new(head.to_vec()));
+            match reader.read_request(1024) {
+                Err(Error::InvalidContentLength(_)) => {}
+                other => panic!("Expected invalid content length, got {:?}", other),
+            }
+        }
+
+        let mut data = b"GET / HTTP/1.1\r\n".to_vec();
+        data.extend_from_slice(&[b'a'; HEAD_LIMIT]);
+        let mut reader = Reader::new(Cursor::new(data));
+        match reader.read_request(1024) {
+            Err(Error::HeadTooLarge(HEAD_LIMIT)) => {}
+            other => panic!("Expected too large head, got {:?}", other),
+        }
+
+        let mut data = b"GET /".to_vec();
+        data.extend_from_slice(&[b'a'; HEAD_LIMIT]);
+        data.extend_from_slice(b" HTTP/1.1\r\n\r\n");
+        let mut reader = Reader::new(Cursor::new(data));
+        match reader.read_request(1024) {
+            Err(Error::RequestLineTooLarge(HEAD_LIMIT)) => {}
+            other => panic!("Expected too large request line, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_request_chunked() {
+        let mut reader = Reader::new(Segmented {
+            data: b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;name=value\r\nab\0cd\r\n10\r\n0123456789abcdef\r\n0\r\nChecksum: 42\r\n\r\nGET / HTTP/1.1\r\n\r\n".to_vec(),
+            position: 0,
+            segment: 3,
+        });
+        let request = reader.read_request(1024).unwrap();
+        assert_eq!(request.body, b"ab\0cd0123456789abcdef".to_vec());
+        assert_eq!(
+            request.head,
+            b"POST /upload HTTP/1.1\r\nChecksum: 42\r\nContent-Length: 21\r\n\r\n".to_vec()
+        );
+        let request = reader.read_request(1024).unwrap();
+        assert_eq!(
+            Request::get_request_line(&request.head),
+            "GET / HTTP/1.1".to_string()
+        );
+
+        for size in [&b"zz"[..], &b"+5"[..], &b""[..]].iter() {
+            let mut data = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+            data.extend_from_slice(size);
+            data.extend_from_slice(b"\r\nabcde\r\n0\r\n\r\n");
+            let mut reader = Reader::new(Cursor::new(data));
+            match reader.read_request(1024) {
+                Err(Error::InvalidChunk(_)) => {}
+                other => panic!("Expected invalid chunk, got {:?}", other),
+            }
+        }
+
+        let mut reader = Reader::new(Cursor::new(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nabc".to_vec(),
+        ));
+        match reader.read_request(1024) {
+            Err(Error::IncompleteChunkedBody(3)) => {}
+            other => panic!("Expected incomplete chunked body, got {:?}", other),
+        }
+
+        let mut reader = Reader::new(Cursor::new(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n".to_vec(),
+        ));
+        match reader.read_request(1024) {
+            Err(Error::UnsupportedTransferEncoding(_)) => {}
+            other => panic!("Expected unsupported transfer encoding, got {:?}", other),
+        }
+
+        let mut reader = Reader::new(Cursor::new(
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n".to_vec(),
+        ));
+        match reader.read_request(1024) {
+            Err(Error::InvalidContentLength(_)) => {}
+            other => panic!("Expected invalid content length, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_request_timeouts() {
+        let stalled = |data: &[u8]| Stalled {
+            data: Cursor::new(data.to_vec()),
+            timeout: None,
+        };
+        let timeout = Some(Duration::from_millis(10));
+
+        let mut reader = Reader::new(stalled(b"GET / HTTP/1.1\r\nHost: loc"));
+        reader.set_timeouts(timeout, timeout);
+        match reader.read_request(1024) {
+            Err(Error::Timeout(Timeout::Header)) => {}
+            other => panic!("Expected header time-out, got {:?}", other),
+        }
+
+        let mut reader = Reader::new(stalled(b"POST / HTTP/1.1\r\nContent-Length: 8\r\n\r\ntest"));
+        reader.set_timeouts(timeout, timeout);
+        match reader.read_request(1024) {
+            Err(Error::Timeout(Timeout::Body)) => {}
+            other => panic!("Expected body time-out, got {:?}", other),
+        }
+
+        let mut reader = Reader::new(stalled(b"POST / HTTP/1.1\r\nContent-Length: 8\r\n\r\ntest"));
+        reader.set_timeouts(None, Some(Duration::from_secs(60)));
+        reader.set_request_deadline(Some(Instant::now() + Duration::from_millis(10)));
+        match reader.read_request(1024) {
+            Err(Error::Timeout(Timeout::Request)) => {}
+            other => panic!("Expected request time-out, got {:?}", other),
+        }
+
+        // Without time-outs the read error is passed on
+        let mut reader = Reader::new(stalled(b"GET / HTTP/1.1\r\n"));
+        match reader.read_request(1024) {
+            Err(Error::Io(_)) => {}
+            other => panic!("Expected read error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn framer() {
+        let received = b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\ntestGET / HTTP/1.1\r\n";
+        let mut framer = Framer::new();
+        assert!(framer.next(1024).unwrap().is_none());
+        assert!(framer.is_empty());
+
+        // Bytes arriving one by one give the same request
+        let mut requests = Vec::new();
+        for byte in received.iter() {
+            framer.push(&[*byte]);
+            if let Some(request) = framer.next(1024).unwrap() {
+                requests.push(request);
+            }
+        }
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].body, b"test".to_vec());
+        assert!(framer.next(1024).unwrap().is_none());
+        assert_eq!(framer.get_received(), 16);
+        assert_eq!(framer.into_inner(), b"GET / HTTP/1.1\r\n".to_vec());
+
+        let mut framer = Framer::new();
+        framer.push(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n");
+        match framer.next(1024) {
+            Err(Error::InvalidContentLength(_)) => {}
+            other => panic!("Expected invalid content length, got {:?}", other),
+        }
+
+        // A head without a terminator is rejected as soon as it exceeds the limit
+        let mut framer = Framer::new();
+        framer.push(b"GET / HTTP/1.1\r\n");
+        framer.push(&vec![b'a'; HEAD_LIMIT]);
+        match framer.next(1024) {
+            Err(Error::HeadTooLarge(_)) => {}
+            other => panic!("Expected head too large, got {:?}", other),
+        }
+
+        // An announced body beyond the limit is refused as soon as the head is received
+        let mut framer = Framer::new();
+        framer.push(b"POST / HTTP/1.1\r\nContent-Length: 10000000\r\n\r\n");
+        match framer.next(1024) {
+            Err(Error::PayloadTooLarge(10000000, 1024)) => {}
+            other => panic!("Expected too large payload, got {:?}", other),
+        }
+
+        // Chunked body bytes beyond the limit are only counted
+        let mut framer = Framer::new();
+        framer.push(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n");
+        let chunk = vec![b'a'; 4096];
+        let mut sent = 0;
+        let mut request = None;
+        while request.is_none() {
+            if sent < 10000000 {
+                let size = chunk.len().min(10000000 - sent);
+                framer.push(format!("{:x}\r\n", size).as_bytes());
+                framer.push(&chunk[..size]);
+                framer.push(b"\r\n");
+                sent += size;
+            } else {
+                framer.push(b"0\r\n\r\n");
+            }
+            request = framer.next(1024).unwrap();
+            assert!(framer.buffer.len() <= chunk.len() + 8);
+            if let Some(ref partial) = framer.partial {
+                assert!(partial.body.len() <= 1024);
+            }
+        }
+        let request = request.unwrap();
+        assert_eq!(request.body.len(), 1024 - 47);
+        assert_eq!(request.overflow_bytes, 10000000 - request.body.len() as u64);
+
+        framer.push(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n");
+        framer.push(b"5000\r\n");
+        framer.push(&vec![b'b'; 0x5000]);
+        framer.push(b"\r\n0\r\nX-Trailer: yes\r\n\r\n");
+        let request = framer.next(1024).unwrap().unwrap();
+        assert_eq!(request.body.len() as u64 + request.overflow_bytes, 0x5000);
+        assert_eq!(
+            Request::get_header_field(&request.head, "X-Trailer"),
+            Some("yes".to_string())
+        );
+        assert!(framer.is_empty());
+    }
+
+    #[test]
+    fn read_request_forbidden_trailers() {
+        let mut reader = Reader::new(Cursor::new(
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n4\r\ntest\r\n0\r\nContent-Length: 0\r\nHost: example.com\r\nAuthorization: Basic YQ==\r\nX-Forwarded-For: 10.0.0.1\r\nChecksum: 42\r\n\r\n".to_vec(),
+        ));
+        let request = reader.read_request(1024).unwrap();
+        assert_eq!(request.body, b"test".to_vec());
+        assert_eq!(
+            request.head,
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nChecksum: 42\r\nContent-Length: 4\r\n\r\n"
+                .to_vec()
+        );
+        assert_eq!(
+            Request::get_header_field(&request.head, "Content-Length"),
+            Some("4".to_string())
+        );
+    }
+}
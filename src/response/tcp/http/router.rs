@@ -0,0 +1,631 @@
+//! # TCP HTTP Router
+//! Used for dispatching requests to handlers by their method and path. Routes are patterns
+//! like `GET /users/:id/posts/*rest` where `:id` matches one path segment and `*rest` matches
+//! the remaining segments, they are kept in a tree of path segments so finding the route of a
+//! request does not depend on the number of routes.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use application_layer::http::request;
+use application_layer::http::response;
+
+use response::tcp::http::{Context, HandlerInterface, MiddlewareInterface, ResponderInterface};
+use transport_layer::Address;
+use Application;
+
+/// Methods that routes can be registered for
+const METHODS: [&str; 9] = [
+    "CONNECT", "DELETE", "GET", "HEAD", "OPTIONS", "PATCH", "POST", "PUT", "TRACE",
+];
+
+/// Responds to a request of a route with the parameters extracted from its path
+pub type Handler = dyn Fn(
+        &request::Message,
+        &Parameters,
+        &Context,
+        &Application,
+        &Address,
+    ) -> Result<response::Message, String>
+    + Send
+    + Sync;
+
+/// Path parameters and wildcards of a matched route, values are the percent-decoded segments
+/// as requested
+#[derive(Debug, Default, PartialEq)]
+pub struct Parameters {
+    values: Vec<(String, String)>,
+}
+
+impl Parameters {
+    /// Get the value of a parameter by its name without the `:` or `*` prefix
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+struct Route {
+    handler: Arc<Handler>,
+    middleware: Arc<Vec<Arc<dyn MiddlewareInterface>>>,
+    pattern: String,
+}
+
+/// Routes of a path by their method
+type Methods = HashMap<String, Route>;
+
+/// Segment of the route tree, static segments take precedence over parameters and parameters
+/// over wildcards
+#[derive(Default)]
+struct Node {
+    children: HashMap<String, Node>,
+    methods: Methods,
+    parameter: Option<(String, Box<Node>)>,
+    wildcard: Option<(String, Methods)>,
+}
+
+impl Node {
+    /// Find the routes of a path that accept the method, any method is accepted without one
+    fn find(
+        &self,
+        segments: &[&str],
+        method: Option<&str>,
+        parameters: &mut Vec<(String, String)>,
+    ) -> Option<&Methods> {
+        let (segment, rest) = match segments.split_first() {
+            Some(split) => split,
+            None if accepts(&self.methods, method) => return Some(&self.methods),
+            None => return None,
+        };
+        if let Some(child) = self.children.get(*segment) {
+            if let Some(methods) = child.find(rest, method, parameters) {
+                return Some(methods);
+            }
+        }
+        if let Some((ref name, ref child)) = self.parameter {
+            if !segment.is_empty() {
+                parameters.push((name.clone(), decode(segment)));
+                if let Some(methods) = child.find(rest, method, parameters) {
+                    return Some(methods);
+                }
+                parameters.pop();
+            }
+        }
+        if let Some((ref name, ref methods)) = self.wildcard {
+            if accepts(methods, method) {
+                parameters.push((name.clone(), decode(&segments.join("/"))));
+                return Some(methods);
+            }
+        }
+        None
+    }
+}
+
+/// HEAD requests are answered by the GET route when there is no HEAD route
+fn get_route<'a>(methods: &'a Methods, method: &str) -> Option<&'a Route> {
+    methods.get(method).or_else(|| {
+        if method == "HEAD" {
+            methods.get("GET")
+        } else {
+            None
+        }
+    })
+}
+
+fn accepts(methods: &Methods, method: Option<&str>) -> bool {
+    match method {
+        Some(method) => get_route(methods, method).is_some(),
+        None => !methods.is_empty(),
+    }
+}
+
+fn get_method_name(method: &request::Method) -> Option<&'static str> {
+    match method {
+        request::Method::Connect => Some("CONNECT"),
+        request::Method::Delete => Some("DELETE"),
+        request::Method::Get => Some("GET"),
+        request::Method::Head => Some("HEAD"),
+        request::Method::Invalid => None,
+        request::Method::Options => Some("OPTIONS"),
+        request::Method::Patch => Some("PATCH"),
+        request::Method::Post => Some("POST"),
+        request::Method::Put => Some("PUT"),
+        request::Method::Trace => Some("TRACE"),
+    }
+}
+
+/// Segments of a path without its leading slash, a trailing slash is an empty last segment
+fn get_segments(path: &str) -> Vec<&str> {
+    path[1..].split('/').collect()
+}
+
+/// Decode percent-encoded bytes of a path, invalid escapes and UTF-8 are kept as they are
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' && index + 2 < bytes.len() {
+            let high = (bytes[index + 1] as char).to_digit(16);
+            let low = (bytes[index + 2] as char).to_digit(16);
+            if let (Some(high), Some(low)) = (high, low) {
+                decoded.push((high * 16 + low) as u8);
+                index += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[index]);
+        index += 1;
+    }
+    match String::from_utf8(decoded) {
+        Ok(decoded) => decoded,
+        Err(_) => value.to_string(),
+    }
+}
+
+/// Matches requests of its routes, requests for a path of the router with another method are
+/// answered with 405 Method Not Allowed
+#[derive(Default)]
+pub struct Router {
+    root: Node,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Register a handler for a route pattern, fails when the pattern is invalid or conflicts
+    /// with a registered route
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http::router::Router;
+    /// let mut router = Router::new();
+    /// let handler = |_: &_, _: &_, _: &_, _: &_, _: &_| Err("Not implemented".to_string());
+    /// assert!(router.route("GET /users/:id", handler).is_ok());
+    /// assert!(router.route("GET /users/new", handler).is_ok());
+    /// assert!(router.route("GET /users/:name", handler).is_err());
+    /// assert!(router.route("GET /files/*path/edit", handler).is_err());
+    /// ```
+    pub fn route<F>(&mut self, pattern: &str, handler: F) -> Result<(), String>
+    where
+        F: Fn(
+                &request::Message,
+                &Parameters,
+                &Context,
+                &Application,
+                &Address,
+            ) -> Result<response::Message, String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.route_with_middleware(pattern, Vec::new(), handler)
+    }
+
+    /// Register a handler for a route pattern with middleware that runs around the handler
+    /// after the middleware of the application
+    pub fn route_with_middleware<F>(
+        &mut self,
+        pattern: &str,
+        middleware: Vec<Arc<dyn MiddlewareInterface>>,
+        handler: F,
+    ) -> Result<(), String>
+    where
+        F: Fn(
+                &request::Message,
+                &Parameters,
+                &Context,
+                &Application,
+                &Address,
+            ) -> Result<response::Message, String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let parts: Vec<&str> = pattern.split_whitespace().collect();
+        if parts.len() != 2 {
+            return Err(format!(
+                "Route {} is not a method followed by a path",
+                pattern
+            ));
+        }
+        let (method, path) = (parts[0], parts[1]);
+        if !METHODS.contains(&method) {
+            return Err(format!("Route {} has unknown method {}", pattern, method));
+        }
+        if !path.starts_with('/') {
+            return Err(format!(
+                "Route {} has a path without leading slash",
+                pattern
+            ));
+        }
+
+        let mut segments = get_segments(path);
+        let wildcard = segments
+            .last()
+            .and_then(|segment| segment.strip_prefix('*'));
+        if wildcard.is_some() {
+            segments.pop();
+        }
+
+        if segments.iter().any(|segment| segment.starts_with('*')) {
+            return Err(format!(
+                "Route {} has a wildcard that is not the last segment",
+                pattern
+            ));
+        }
+        let mut names: Vec<&str> = segments
+            .iter()
+            .filter_map(|segment| segment.strip_prefix(':'))
+            .collect();
+        names.extend(wildcard);
+        for (index, name) in names.iter().enumerate() {
+            if name.is_empty() {
+                return Err(format!("Route {} has a parameter without name", pattern));
+            }
+            if names[..index].contains(name) {
+                return Err(format!("Route {} has parameter {} twice", pattern, name));
+            }
+        }
+
+        let mut node = &mut self.root;
+        for segment in segments {
+            node = if let Some(name) = segment.strip_prefix(':') {
+                if let Some((ref existing, _)) = node.parameter {
+                    if existing != name {
+                        return Err(format!(
+                            "Route {} names parameter :{} that is named :{} by another route",
+                            pattern, name, existing
+                        ));
+                    }
+                }
+                &mut node
+                    .parameter
+                    .get_or_insert_with(|| (name.to_string(), Box::new(Node::default())))
+                    .1
+            } else {
+                node.children.entry(segment.to_string()).or_default()
+            };
+        }
+        let methods = match wildcard {
+            Some(name) => {
+                if let Some((ref existing, _)) = node.wildcard {
+                    if existing != name {
+                        return Err(format!(
+                            "Route {} names wildcard *{} that is named *{} by another route",
+                            pattern, name, existing
+                        ));
+                    }
+                }
+                &mut node
+                    .wildcard
+                    .get_or_insert_with(|| (name.to_string(), HashMap::new()))
+                    .1
+            }
+            None => &mut node.methods,
+        };
+        if let Some(existing) = methods.get(method) {
+            return Err(format!(
+                "Route {} conflicts with route {}",
+                pattern, existing.pattern
+            ));
+        }
+        methods.insert(
+            method.to_string(),
+            Route {
+                handler: Arc::new(handler),
+                middleware: Arc::new(middleware),
+                pattern: pattern.to_string(),
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Calls the handler of a matched route
+struct RouteHandler {
+    handler: Arc<Handler>,
+    middleware: Arc<Vec<Arc<dyn MiddlewareInterface>>>,
+    parameters: Parameters,
+}
+
+/// Responds to requests for a path of the router with a method it has no route for
+struct MethodNotAllowed {
+    allow: Vec<String>,
+}
+
+impl ResponderInterface for Router {
+    fn matches(
+        &self,
+        request_message: &request::Message,
+        _application: &Application,
+        _socket: &Address,
+    ) -> Option<Box<dyn HandlerInterface>> {
+        let path = &request_message.request_line.request_uri_base;
+        if !path.starts_with('/') {
+            return None;
+        }
+        let segments = get_segments(path);
+        let method = get_method_name(&request_message.request_line.method)?;
+
+        let mut values = Vec::new();
+        if let Some(methods) = self.root.find(&segments, Some(method), &mut values) {
+            let route = get_route(methods, method)?;
+            return Some(Box::new(RouteHandler {
+                handler: Arc::clone(&route.handler),
+                middleware: Arc::clone(&route.middleware),
+                parameters: Parameters { values },
+            }));
+        }
+
+        let mut values = Vec::new();
+        let methods = self.root.find(&segments, None, &mut values)?;
+        let mut allow: Vec<String> = methods.keys().cloned().collect();
+        if methods.contains_key("GET") && !methods.contains_key("HEAD") {
+            allow.push("HEAD".to_string());
+        }
+        allow.sort();
+        Some(Box::new(MethodNotAllowed { allow }))
+    }
+}
+
+impl HandlerInterface for RouteHandler {
+    fn respond(
+        self: Box<Self>,
+        request_message: &request::Message,
+        context: &Context,
+        application: &Application,
+        socket: &Address,
+    ) -> Result<response::Message, String> {
+        (self.handler)(
+            request_message,
+            &self.parameters,
+            context,
+            application,
+            socket,
+        )
+    }
+
+    fn get_middleware(&self) -> &[Arc<dyn MiddlewareInterface>] {
+        &self.middleware
+    }
+}
+
+impl HandlerInterface for MethodNotAllowed {
+    fn respond(
+        self: Box<Self>,
+        request_message: &request::Message,
+        _context: &Context,
+        _application: &Application,
+        _socket: &Address,
+    ) -> Result<response::Message, String> {
+        let protocol = request::Message::get_protocol_text(&request_message.request_line.protocol);
+        let mut headers: HashMap<String, String> = HashMap::new();
+        headers.insert("Allow".to_string(), self.allow.join(", "));
+        Ok(response::Message::new(
+            protocol,
+            "405 Method Not Allowed".to_string(),
+            headers,
+            Vec::new(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use response::tcp::http::Dispatcher;
+    use test_config;
+
+    fn get_application() -> Application {
+        Application::new(test_config())
+    }
+
+    /// Responds with the name of the route and its parameters in order
+    fn echo(
+        name: &'static str,
+    ) -> impl Fn(
+        &request::Message,
+        &Parameters,
+        &Context,
+        &Application,
+        &Address,
+    ) -> Result<response::Message, String>
+           + Send
+           + Sync {
+        move |_request, parameters, _context, _application, _socket| {
+            let mut body = name.to_string();
+            for (key, value) in &parameters.values {
+                body.push_str(&format!(" {}={}", key, value));
+            }
+            Ok(response::Message::new(
+                "HTTP/1.1".to_string(),
+                "200 OK".to_string(),
+                HashMap::new(),
+                body.into_bytes(),
+            ))
+        }
+    }
+
+    fn get_response(router: &Router, request: &[u8]) -> Option<response::Message> {
+        let application = get_application();
+        let socket = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        ));
+        let request = request::Message::from_tcp_stream(request).unwrap();
+        router
+            .matches(&request, &application, &socket)
+            .map(|handler| {
+                handler
+                    .respond(&request, &Context::new(), &application, &socket)
+                    .unwrap()
+            })
+    }
+
+    fn get_body(router: &Router, request: &[u8]) -> Option<String> {
+        get_response(router, request).map(|mut response| {
+            let mut body = Vec::new();
+            response.write_body_to(&mut body).unwrap();
+            String::from_utf8(body).unwrap()
+        })
+    }
+
+    #[test]
+    fn test_matches() {
+        let mut router = Router::new();
+        router.route("GET /", echo("index")).unwrap();
+        router.route("GET /users/new", echo("new")).unwrap();
+        router.route("GET /users/:id", echo("user")).unwrap();
+        router
+            .route("GET /users/:id/posts/*rest", echo("posts"))
+            .unwrap();
+        router.route("POST /users/:id", echo("update")).unwrap();
+        router.route("GET /users/new/:tab", echo("tab")).unwrap();
+
+        assert_eq!(
+            get_body(&router, b"GET / HTTP/1.1\r\n\r\n"),
+            Some("index".to_string())
+        );
+        assert_eq!(
+            get_body(&router, b"GET /users/new HTTP/1.1\r\n\r\n"),
+            Some("new".to_string())
+        );
+        assert_eq!(
+            get_body(&router, b"GET /users/42?a=b HTTP/1.1\r\n\r\n"),
+            Some("user id=42".to_string())
+        );
+        assert_eq!(
+            get_body(&router, b"POST /users/42 HTTP/1.1\r\n\r\n"),
+            Some("update id=42".to_string())
+        );
+        // Static segments are tried first and parameters when they lead nowhere
+        assert_eq!(
+            get_body(&router, b"POST /users/new HTTP/1.1\r\n\r\n"),
+            Some("update id=new".to_string())
+        );
+        assert_eq!(
+            get_body(&router, b"GET /users/new/posts/a/b HTTP/1.1\r\n\r\n"),
+            Some("posts id=new rest=a/b".to_string())
+        );
+        assert_eq!(
+            get_body(&router, b"GET /users/new/settings HTTP/1.1\r\n\r\n"),
+            Some("tab tab=settings".to_string())
+        );
+        assert_eq!(
+            get_body(&router, b"GET /users/J%C3%B6rg%20K HTTP/1.1\r\n\r\n"),
+            Some("user id=J\u{f6}rg K".to_string())
+        );
+        assert_eq!(
+            get_body(&router, b"GET /users/1/posts/a%2Fb/%zz HTTP/1.1\r\n\r\n"),
+            Some("posts id=1 rest=a/b/%zz".to_string())
+        );
+        assert_eq!(
+            get_body(&router, b"HEAD /users/42 HTTP/1.1\r\n\r\n"),
+            Some("user id=42".to_string())
+        );
+
+        assert!(get_response(&router, b"GET /users HTTP/1.1\r\n\r\n").is_none());
+        assert!(get_response(&router, b"GET /users/ HTTP/1.1\r\n\r\n").is_none());
+        assert!(get_response(&router, b"GET /users/42/posts HTTP/1.1\r\n\r\n").is_none());
+        assert!(get_response(&router, b"GET /about HTTP/1.1\r\n\r\n").is_none());
+    }
+
+    /// Answers every request with 403 Forbidden
+    struct Forbidden {}
+
+    impl MiddlewareInterface for Forbidden {
+        fn before(
+            &self,
+            _request_message: &mut request::Message,
+            _context: &mut Context,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<response::Message> {
+            Some(response::Message::new(
+                "HTTP/1.1".to_string(),
+                "403 Forbidden".to_string(),
+                HashMap::new(),
+                Vec::new(),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_middleware() {
+        let mut router = Router::new();
+        router.route("GET /users/:id", echo("user")).unwrap();
+        router
+            .route_with_middleware(
+                "GET /admin/*path",
+                vec![Arc::new(Forbidden {})],
+                echo("admin"),
+            )
+            .unwrap();
+        let responders: Vec<Box<dyn ResponderInterface>> = vec![Box::new(router)];
+
+        let application = get_application();
+        let socket = Address::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        ));
+        for (request, status) in [
+            (&b"GET /users/42 HTTP/1.1\r\n\r\n"[..], "200 OK"),
+            (&b"GET /admin/users HTTP/1.1\r\n\r\n"[..], "403 Forbidden"),
+        ] {
+            let mut dispatcher = Dispatcher::new();
+            assert!(dispatcher.matches(request, &application, &socket));
+            let (response, _) = dispatcher
+                .respond(request, &application, &socket, &responders)
+                .unwrap();
+            assert_eq!(response.status, status);
+        }
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let mut router = Router::new();
+        router.route("GET /users/:id", echo("user")).unwrap();
+        router.route("PUT /users/:id", echo("update")).unwrap();
+        router.route("POST /files/*path", echo("upload")).unwrap();
+
+        let response = get_response(&router, b"DELETE /users/42 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(response.status, "405 Method Not Allowed");
+        assert_eq!(
+            response.get_header("Allow"),
+            Some(&"GET, HEAD, PUT".to_string())
+        );
+
+        let response = get_response(&router, b"GET /files/a/b HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(response.status, "405 Method Not Allowed");
+        assert_eq!(response.get_header("Allow"), Some(&"POST".to_string()));
+    }
+
+    #[test]
+    fn test_route() {
+        let mut router = Router::new();
+        assert!(router.route("GET /users/:id", echo("user")).is_ok());
+        assert!(router.route("GET /users/:id", echo("user")).is_err());
+        assert!(router.route("GET /users/:name", echo("user")).is_err());
+        assert!(router.route("PUT /users/:name/edit", echo("user")).is_err());
+        assert!(router.route("PUT /users/:id/edit", echo("user")).is_ok());
+        assert!(router.route("GET /files/*path", echo("file")).is_ok());
+        assert!(router.route("GET /files/*rest", echo("file")).is_err());
+        assert!(router.route("GET /files/*path", echo("file")).is_err());
+        assert!(router.route("PUT /files/*path", echo("file")).is_ok());
+
+        assert!(router.route("GET /a/*path/b", echo("invalid")).is_err());
+        assert!(router.route("GET /a/:id/:id", echo("invalid")).is_err());
+        assert!(router.route("GET /a/:id/*id", echo("invalid")).is_err());
+        assert!(router.route("GET /a/:", echo("invalid")).is_err());
+        assert!(router.route("GET /a/*", echo("invalid")).is_err());
+        assert!(router.route("GET a", echo("invalid")).is_err());
+        assert!(router.route("FETCH /a", echo("invalid")).is_err());
+        assert!(router.route("/a", echo("invalid")).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! # HTTP/2 frames
+//! Every frame starts with a 9 byte header of payload length, type, flags and stream
+//! identifier.
+
+use std::fmt;
+
+/// Size of the frame header
+pub const HEADER_LENGTH: usize = 9;
+
+/// Largest payload allowed before the peer changes it with SETTINGS
+pub const DEFAULT_MAX_SIZE: usize = 16384;
+
+/// Largest payload size a peer can allow with SETTINGS
+pub const MAX_MAX_SIZE: usize = 16_777_215;
+
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const PRIORITY: u8 = 0x2;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PUSH_PROMISE: u8 = 0x5;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+pub const FLAG_ACK: u8 = 0x1;
+pub const FLAG_END_STREAM: u8 = 0x1;
+pub const FLAG_END_HEADERS: u8 = 0x4;
+pub const FLAG_PADDED: u8 = 0x8;
+pub const FLAG_PRIORITY: u8 = 0x20;
+
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// Why a stream or connection was ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    SettingsTimeout = 0x4,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    Cancel = 0x8,
+    CompressionError = 0x9,
+    ConnectError = 0xa,
+    EnhanceYourCalm = 0xb,
+    InadequateSecurity = 0xc,
+    Http11Required = 0xd,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A frame of a HTTP/2 connection
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(kind: u8, flags: u8, stream_id: u32, payload: Vec<u8>) -> Frame {
+        Frame {
+            kind,
+            flags,
+            stream_id,
+            payload,
+        }
+    }
+
+    /// Build a SETTINGS frame from identifiers and values
+    pub fn settings(settings: &[(u16, u32)]) -> Frame {
+        let mut payload = Vec::with_capacity(settings.len() * 6);
+        for &(identifier, value) in settings {
+            payload.extend_from_slice(&identifier.to_be_bytes());
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        Frame::new(SETTINGS, 0, 0, payload)
+    }
+
+    pub fn window_update(stream_id: u32, increment: u32) -> Frame {
+        Frame::new(
+            WINDOW_UPDATE,
+            0,
+            stream_id,
+            increment.to_be_bytes().to_vec(),
+        )
+    }
+
+    pub fn rst_stream(stream_id: u32, error_code: ErrorCode) -> Frame {
+        Frame::new(
+            RST_STREAM,
+            0,
+            stream_id,
+            (error_code as u32).to_be_bytes().to_vec(),
+        )
+    }
+
+    /// Build a GOAWAY frame with the last stream that was or will be processed
+    pub fn goaway(last_stream_id: u32, error_code: ErrorCode, debug: &str) -> Frame {
+        let mut payload = Vec::with_capacity(8 + debug.len());
+        payload.extend_from_slice(&last_stream_id.to_be_bytes());
+        payload.extend_from_slice(&(error_code as u32).to_be_bytes());
+        payload.extend_from_slice(debug.as_bytes());
+        Frame::new(GOAWAY, 0, 0, payload)
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag == flag
+    }
+
+    /// Parse a frame from the start of a buffer, returns the frame and number of used bytes
+    /// or `None` if the frame is not complete yet
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http2::frame::{self, Frame};
+    /// let mut buffer = Vec::new();
+    /// Frame::window_update(1, 100).write_to(&mut buffer);
+    /// assert_eq!(Frame::parse(&buffer[..5], frame::DEFAULT_MAX_SIZE), Ok(None));
+    /// assert_eq!(
+    ///     Frame::parse(&buffer, frame::DEFAULT_MAX_SIZE),
+    ///     Ok(Some((Frame::window_update(1, 100), 13)))
+    /// );
+    /// ```
+    pub fn parse(buffer: &[u8], max_size: usize) -> Result<Option<(Frame, usize)>, ErrorCode> {
+        if buffer.len() < HEADER_LENGTH {
+            return Ok(None);
+        }
+        let length = (buffer[0] as usize) << 16 | (buffer[1] as usize) << 8 | buffer[2] as usize;
+        if length > max_size {
+            return Err(ErrorCode::FrameSizeError);
+        }
+        if buffer.len() < HEADER_LENGTH + length {
+            return Ok(None);
+        }
+        let stream_id = read_u32(&buffer[5..9]) & 0x7fff_ffff;
+        Ok(Some((
+            Frame::new(
+                buffer[3],
+                buffer[4],
+                stream_id,
+                buffer[HEADER_LENGTH..HEADER_LENGTH + length].to_vec(),
+            ),
+            HEADER_LENGTH + length,
+        )))
+    }
+
+    /// Get the data of a DATA or HEADERS frame without padding and priority fields
+    pub fn get_data(&self) -> Result<&[u8], ErrorCode> {
+        let mut data = &self.payload[..];
+        let mut padding = 0;
+        if self.has_flag(FLAG_PADDED) {
+            if data.is_empty() {
+                return Err(ErrorCode::ProtocolError);
+            }
+            padding = data[0] as usize;
+            data = &data[1..];
+        }
+        if self.kind == HEADERS && self.has_flag(FLAG_PRIORITY) {
+            if data.len() < 5 {
+                return Err(ErrorCode::ProtocolError);
+            }
+            data = &data[5..];
+        }
+        if padding > data.len() {
+            return Err(ErrorCode::ProtocolError);
+        }
+        Ok(&data[..data.len() - padding])
+    }
+
+    /// Append the encoded frame to a buffer
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        let length = self.payload.len();
+        buffer.extend_from_slice(&[
+            (length >> 16) as u8,
+            (length >> 8) as u8,
+            length as u8,
+            self.kind,
+            self.flags,
+        ]);
+        buffer.extend_from_slice(&(self.stream_id & 0x7fff_ffff).to_be_bytes());
+        buffer.extend_from_slice(&self.payload);
+    }
+}
+
+/// Read a big-endian number from the first four bytes of a slice
+pub fn read_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let mut buffer = Vec::new();
+        Frame::settings(&[(SETTINGS_INITIAL_WINDOW_SIZE, 10)]).write_to(&mut buffer);
+        Frame::goaway(3, ErrorCode::ProtocolError, "bad").write_to(&mut buffer);
+        assert_eq!(
+            buffer[..15].to_vec(),
+            vec![0, 0, 6, 4, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 10]
+        );
+
+        let (frame, used) = Frame::parse(&buffer, DEFAULT_MAX_SIZE).unwrap().unwrap();
+        assert_eq!(frame.kind, SETTINGS);
+        assert_eq!(used, 15);
+        let (frame, used) = Frame::parse(&buffer[15..], DEFAULT_MAX_SIZE)
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame, Frame::goaway(3, ErrorCode::ProtocolError, "bad"));
+        assert_eq!(used, buffer.len() - 15);
+        assert_eq!(Frame::parse(&buffer, 5), Err(ErrorCode::FrameSizeError));
+    }
+
+    #[test]
+    fn get_data() {
+        let frame = Frame::new(
+            HEADERS,
+            FLAG_PADDED | FLAG_PRIORITY,
+            1,
+            vec![2, 0, 0, 0, 3, 15, b'a', b'b', 0, 0],
+        );
+        assert_eq!(frame.get_data(), Ok(&b"ab"[..]));
+
+        let frame = Frame::new(DATA, FLAG_PADDED, 1, vec![4, b'a', 0, 0]);
+        assert_eq!(frame.get_data(), Err(ErrorCode::ProtocolError));
+
+        let frame = Frame::new(DATA, FLAG_END_STREAM, 1, b"abc".to_vec());
+        assert_eq!(frame.get_data(), Ok(&b"abc"[..]));
+    }
+}
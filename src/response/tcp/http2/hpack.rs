@@ -0,0 +1,787 @@
+//! # HPACK header compression
+//! Header blocks are decoded with a dynamic table, the encoder only uses the static table so
+//! it doesn't need to track the decoder of the client.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Size of the dynamic table before SETTINGS or a table size update changes it
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Overhead of every dynamic table entry in addition to its name and value
+const ENTRY_OVERHEAD: usize = 32;
+
+/// The header fields every peer knows by index, starting at index 1
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Huffman codes and their bit lengths by symbol, the last symbol is end-of-string
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+/// Errors that make a header block impossible to decode
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The block ended inside a representation
+    Truncated,
+    /// An integer doesn't fit in the supported range
+    Integer,
+    /// A Huffman encoded string is invalid
+    Huffman,
+    /// A representation refers to a table entry that doesn't exist
+    Index(usize),
+    /// A table size update exceeds the size allowed by the settings or comes too late
+    TableSize(usize),
+    /// The decoded header list exceeds the limit
+    ListSize(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "Header block is truncated"),
+            Error::Integer => write!(f, "Integer is too large"),
+            Error::Huffman => write!(f, "Invalid Huffman encoded string"),
+            Error::Index(index) => write!(f, "Invalid table index: {}", index),
+            Error::TableSize(size) => write!(f, "Invalid table size update: {}", size),
+            Error::ListSize(size) => write!(f, "Header list is too large: {}", size),
+        }
+    }
+}
+
+/// A decoded header field of name and value
+pub type Field = (Vec<u8>, Vec<u8>);
+
+/// Decodes the header blocks of one direction of a connection
+pub struct Decoder {
+    list_limit: usize,
+    max_size: usize,
+    size: usize,
+    table: VecDeque<Field>,
+}
+
+impl Decoder {
+    /// Create a decoder for header lists up to a size in bytes, counted like the dynamic table
+    pub fn new(list_limit: usize) -> Decoder {
+        Decoder {
+            list_limit,
+            max_size: DEFAULT_TABLE_SIZE,
+            size: 0,
+            table: VecDeque::new(),
+        }
+    }
+
+    /// Decode a complete header block
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::http2::hpack::Decoder;
+    /// let mut decoder = Decoder::new(16384);
+    /// // Indexed :method GET and a literal with indexed name :path
+    /// assert_eq!(
+    ///     decoder.decode(&[0x82, 0x04, 0x02, b'/', b'a']).unwrap(),
+    ///     vec![
+    ///         (b":method".to_vec(), b"GET".to_vec()),
+    ///         (b":path".to_vec(), b"/a".to_vec()),
+    ///     ]
+    /// );
+    /// ```
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<Field>, Error> {
+        let mut fields = Vec::new();
+        let mut list_size = 0;
+        let mut position = 0;
+        while position < block.len() {
+            let byte = block[position];
+            let field = if byte & 0x80 == 0x80 {
+                let index = decode_integer(block, &mut position, 7)?;
+                self.get(index)?
+            } else if byte & 0xc0 == 0x40 {
+                let field = self.decode_literal(block, &mut position, 6)?;
+                self.insert(field.clone());
+                field
+            } else if byte & 0xe0 == 0x20 {
+                // Size updates are only allowed at the start of a block
+                let size = decode_integer(block, &mut position, 5)?;
+                if size > DEFAULT_TABLE_SIZE || !fields.is_empty() {
+                    return Err(Error::TableSize(size));
+                }
+                self.max_size = size;
+                self.evict(0);
+                continue;
+            } else {
+                self.decode_literal(block, &mut position, 4)?
+            };
+            list_size += field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+            if list_size > self.list_limit {
+                return Err(Error::ListSize(list_size));
+            }
+            fields.push(field);
+        }
+        Ok(fields)
+    }
+
+    fn decode_literal(
+        &self,
+        block: &[u8],
+        position: &mut usize,
+        prefix: u8,
+    ) -> Result<Field, Error> {
+        let index = decode_integer(block, position, prefix)?;
+        let name = if index == 0 {
+            decode_string(block, position)?
+        } else {
+            self.get(index)?.0
+        };
+        let value = decode_string(block, position)?;
+        Ok((name, value))
+    }
+
+    fn get(&self, index: usize) -> Result<Field, Error> {
+        if index == 0 {
+            return Err(Error::Index(index));
+        }
+        if index <= STATIC_TABLE.len() {
+            let (name, value) = STATIC_TABLE[index - 1];
+            return Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()));
+        }
+        match self.table.get(index - STATIC_TABLE.len() - 1) {
+            Some(field) => Ok(field.clone()),
+            None => Err(Error::Index(index)),
+        }
+    }
+
+    fn insert(&mut self, field: Field) {
+        let size = field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // Entries larger than the table just empty it
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front(field);
+        }
+    }
+
+    /// Evict the oldest entries until there is room for an entry of a size
+    fn evict(&mut self, size: usize) {
+        while self.size + size > self.max_size {
+            match self.table.pop_back() {
+                Some(field) => self.size -= field.0.len() + field.1.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Encode header fields without adding them to the dynamic table of the client, names are
+/// expected in lower-case
+/// ```rust
+/// use milstian_internet_framework::response::tcp::http2::hpack::{encode, Decoder};
+/// let fields = vec![
+///     (":status".to_string(), "200".to_string()),
+///     ("content-type".to_string(), "text/html".to_string()),
+///     ("x-custom".to_string(), "value".to_string()),
+/// ];
+/// let block = encode(&fields);
+/// assert_eq!(block[0], 0x88);
+/// let decoded = Decoder::new(16384).decode(&block).unwrap();
+/// assert_eq!(decoded[1], (b"content-type".to_vec(), b"text/html".to_vec()));
+/// assert_eq!(decoded[2], (b"x-custom".to_vec(), b"value".to_vec()));
+/// ```
+pub fn encode(fields: &[(String, String)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in fields {
+        let mut name_index = 0;
+        let mut field_index = 0;
+        for (index, &(static_name, static_value)) in STATIC_TABLE.iter().enumerate() {
+            if static_name == name {
+                if static_value == value {
+                    field_index = index + 1;
+                    break;
+                }
+                if name_index == 0 {
+                    name_index = index + 1;
+                }
+            }
+        }
+        if field_index > 0 {
+            encode_integer(&mut block, field_index, 7, 0x80);
+            continue;
+        }
+        encode_integer(&mut block, name_index, 4, 0x00);
+        if name_index == 0 {
+            encode_string(&mut block, name.as_bytes());
+        }
+        encode_string(&mut block, value.as_bytes());
+    }
+    block
+}
+
+/// Decode an integer with a prefix of a number of bits
+fn decode_integer(block: &[u8], position: &mut usize, prefix: u8) -> Result<usize, Error> {
+    let mask = (1usize << prefix) - 1;
+    let mut value = match block.get(*position) {
+        Some(&byte) => byte as usize & mask,
+        None => return Err(Error::Truncated),
+    };
+    *position += 1;
+    if value < mask {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let byte = match block.get(*position) {
+            Some(&byte) => byte,
+            None => return Err(Error::Truncated),
+        };
+        *position += 1;
+        if shift > 28 {
+            return Err(Error::Integer);
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn encode_integer(block: &mut Vec<u8>, value: usize, prefix: u8, first: u8) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        block.push(first | value as u8);
+        return;
+    }
+    block.push(first | mask as u8);
+    let mut value = value - mask;
+    while value >= 0x80 {
+        block.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+fn decode_string(block: &[u8], position: &mut usize) -> Result<Vec<u8>, Error> {
+    let huffman = match block.get(*position) {
+        Some(&byte) => byte & 0x80 == 0x80,
+        None => return Err(Error::Truncated),
+    };
+    let length = decode_integer(block, position, 7)?;
+    if block.len() - *position < length {
+        return Err(Error::Truncated);
+    }
+    let data = &block[*position..*position + length];
+    *position += length;
+    if huffman {
+        decode_huffman(data)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+fn encode_string(block: &mut Vec<u8>, data: &[u8]) {
+    encode_integer(block, data.len(), 7, 0x00);
+    block.extend_from_slice(data);
+}
+
+/// Decode a Huffman encoded string, the codes are canonical so the codes of every length
+/// are consecutive in symbol order
+fn decode_huffman(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut first_codes = [0u32; 31];
+    let mut symbols: Vec<Vec<u16>> = vec![Vec::new(); 31];
+    for (symbol, &(code, length)) in HUFFMAN_CODES.iter().enumerate() {
+        let length = length as usize;
+        if symbols[length].is_empty() {
+            first_codes[length] = code;
+        }
+        symbols[length].push(symbol as u16);
+    }
+
+    let mut decoded = Vec::with_capacity(data.len() * 8 / 5);
+    let mut code: u32 = 0;
+    let mut length = 0;
+    for byte in data {
+        for shift in (0..8).rev() {
+            code = code << 1 | (byte >> shift & 1) as u32;
+            length += 1;
+            if length > 30 {
+                return Err(Error::Huffman);
+            }
+            let index = code.wrapping_sub(first_codes[length]) as usize;
+            if code >= first_codes[length] && index < symbols[length].len() {
+                let symbol = symbols[length][index];
+                if symbol == 256 {
+                    return Err(Error::Huffman);
+                }
+                decoded.push(symbol as u8);
+                code = 0;
+                length = 0;
+            }
+        }
+    }
+    // Padding is the most significant bits of end-of-string
+    if length > 7 || code != (1 << length) - 1 {
+        return Err(Error::Huffman);
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_fields(fields: &[(&str, &str)]) -> Vec<Field> {
+        fields
+            .iter()
+            .map(|&(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect()
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        let hex: String = hex.split_whitespace().collect();
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn integer() {
+        // Examples of RFC 7541 appendix C.1
+        let mut block = Vec::new();
+        encode_integer(&mut block, 10, 5, 0);
+        encode_integer(&mut block, 1337, 5, 0);
+        encode_integer(&mut block, 42, 8, 0);
+        assert_eq!(block, vec![10, 31, 154, 10, 42]);
+
+        let mut position = 0;
+        assert_eq!(decode_integer(&block, &mut position, 5), Ok(10));
+        assert_eq!(decode_integer(&block, &mut position, 5), Ok(1337));
+        assert_eq!(decode_integer(&block, &mut position, 8), Ok(42));
+        assert_eq!(
+            decode_integer(&[31, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01], &mut 0, 5),
+            Err(Error::Integer)
+        );
+        assert_eq!(
+            decode_integer(&[31, 0xff], &mut 0, 5),
+            Err(Error::Truncated)
+        );
+    }
+
+    #[test]
+    fn requests_with_huffman() {
+        // Examples of RFC 7541 appendix C.4
+        let mut decoder = Decoder::new(16384);
+        assert_eq!(
+            decoder
+                .decode(&from_hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"))
+                .unwrap(),
+            get_fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+        );
+        assert_eq!(
+            decoder
+                .decode(&from_hex("8286 84be 5886 a8eb 1064 9cbf"))
+                .unwrap(),
+            get_fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])
+        );
+        assert_eq!(
+            decoder
+                .decode(&from_hex(
+                    "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"
+                ))
+                .unwrap(),
+            get_fields(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ])
+        );
+        assert_eq!(decoder.size, 164);
+    }
+
+    #[test]
+    fn eviction() {
+        // Responses of RFC 7541 appendix C.5 with a table of 256 bytes
+        let mut decoder = Decoder::new(16384);
+        decoder.decode(&[0x3f, 0xe1, 0x01]).unwrap();
+        assert_eq!(decoder.max_size, 256);
+        decoder
+            .decode(&from_hex(
+                "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133
+                 2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70
+                 6c65 2e63 6f6d",
+            ))
+            .unwrap();
+        assert_eq!(decoder.size, 222);
+        assert_eq!(
+            decoder.decode(&from_hex("4803 3330 37c1 c0bf")).unwrap()[0],
+            (b":status".to_vec(), b"307".to_vec())
+        );
+        assert_eq!(decoder.table.len(), 4);
+        assert_eq!(decoder.size, 222);
+    }
+
+    #[test]
+    fn errors() {
+        let mut decoder = Decoder::new(100);
+        assert_eq!(decoder.decode(&[0x80]), Err(Error::Index(0)));
+        assert_eq!(decoder.decode(&[0xbe]), Err(Error::Index(62)));
+        assert_eq!(
+            decoder.decode(&[0x3f, 0xe2, 0x1f]),
+            Err(Error::TableSize(4097))
+        );
+        assert_eq!(decoder.decode(&[0x82, 0x20]), Err(Error::TableSize(0)));
+        // A padding of zeros and a string with end-of-string are invalid
+        assert_eq!(decoder.decode(&[0x04, 0x81, 0x00]), Err(Error::Huffman));
+        assert_eq!(
+            decoder.decode(&[0x04, 0x84, 0xff, 0xff, 0xff, 0xff]),
+            Err(Error::Huffman)
+        );
+        assert_eq!(
+            decoder.decode(&[0x82, 0x82, 0x82]),
+            Err(Error::ListSize(126))
+        );
+    }
+
+    #[test]
+    fn huffman() {
+        // Every symbol decodes from its own code
+        for (symbol, &(code, length)) in HUFFMAN_CODES[..256].iter().enumerate() {
+            let padding = (8 - length % 8) % 8;
+            let bits = (code as u64) << padding | ((1 << padding) - 1);
+            let bytes = (length as usize + padding as usize) / 8;
+            let data: Vec<u8> = (0..bytes)
+                .rev()
+                .map(|index| (bits >> (index * 8)) as u8)
+                .collect();
+            assert_eq!(decode_huffman(&data), Ok(vec![symbol as u8]));
+        }
+    }
+}
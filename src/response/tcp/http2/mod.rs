@@ -0,0 +1,1210 @@
+//! # HTTP/2 connections
+//! The streams of a connection are multiplexed over one socket. Every request is framed as
+//! a HTTP/1.x message and answered by the same responders, response bodies are sent as
+//! flow-controlled DATA frames that take turns between streams.
+
+pub mod frame;
+pub mod hpack;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use application_layer::http::response;
+use metrics::Timeout;
+use response::tcp::http::reader::Request;
+use response::tcp::http::Responders;
+use response::tcp::{Dispatcher, Stream};
+use transport_layer::Address;
+use Application;
+
+use self::frame::{ErrorCode, Frame};
+
+/// The connection preface every HTTP/2 client starts with
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Maximum number of streams a client may have open at once
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+
+/// Maximum size of a header block and of the header list it decodes to
+const MAX_HEADER_LIST_SIZE: usize = 65536;
+
+/// Size of flow-control windows before SETTINGS or WINDOW_UPDATE change them
+const DEFAULT_WINDOW_SIZE: i64 = 65535;
+
+const MAX_WINDOW_SIZE: i64 = 0x7fff_ffff;
+
+/// How often a connection that waits for the client checks whether shutdown was requested
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Header fields that only apply to a HTTP/1.x connection
+const CONNECTION_FIELDS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Serve the streams of a HTTP/2 connection until the client closes it, it fails or shutdown
+/// is requested, the received bytes have to start with the connection preface
+pub fn serve<S: Stream>(
+    stream: S,
+    received: Vec<u8>,
+    socket: Address,
+    application: Application,
+    responders: Responders,
+    shutdown: Arc<AtomicBool>,
+) {
+    let mut connection = Connection::new(stream, received, socket.clone(), application, responders);
+    let result = connection.serve(&shutdown);
+    let application = connection.application.clone();
+    match result {
+        Ok(()) => {}
+        Err(Failure::Connection(error_code, message)) => {
+            application.get_feedback().error(format!(
+                "HTTP/2 connection from {} failed, error: {} {}",
+                socket, error_code, message
+            ));
+            let last_stream_id = connection.last_stream_id;
+            connection.write_frame(Frame::goaway(last_stream_id, error_code, &message));
+            let _ = connection.flush();
+        }
+        Err(Failure::Io(error)) => {
+            application.get_feedback().error(format!(
+                "Failed HTTP/2 connection from {}, error: {}",
+                socket, error
+            ));
+        }
+    }
+    let _ = connection.stream.shutdown_write();
+    application.get_feedback().info(format!(
+        "Closing HTTP/2 connection from {} after {} streams",
+        socket, connection.served
+    ));
+}
+
+/// Why a connection can't continue
+enum Failure {
+    /// The client broke the protocol, it is told why with GOAWAY
+    Connection(ErrorCode, String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Failure {
+    fn from(error: io::Error) -> Failure {
+        Failure::Io(error)
+    }
+}
+
+/// A request and its response on one stream
+struct Exchange {
+    body: Vec<u8>,
+    fields: Vec<(String, String)>,
+    overflow_bytes: u64,
+    /// Whether the client has sent the whole request
+    received: bool,
+    receive_window: i64,
+    response: Option<Outgoing>,
+    send_window: i64,
+}
+
+/// The body of a response that is being sent
+struct Outgoing {
+    body: Box<dyn Read + Send>,
+    buffer: Vec<u8>,
+    ended: bool,
+}
+
+struct Connection<S: Stream> {
+    application: Application,
+    /// Stream whose header block continues in CONTINUATION frames with its flags and the
+    /// block so far
+    continuation: Option<(u32, u8, Vec<u8>)>,
+    decoder: hpack::Decoder,
+    exchanges: BTreeMap<u32, Exchange>,
+    /// Whether GOAWAY was sent or received, no new streams are started
+    going_away: bool,
+    initial_window_size: i64,
+    input: Vec<u8>,
+    last_activity: Instant,
+    last_stream_id: u32,
+    max_frame_size: usize,
+    output: Vec<u8>,
+    receive_window: i64,
+    responders: Responders,
+    send_window: i64,
+    served: usize,
+    socket: Address,
+    stream: S,
+}
+
+impl<S: Stream> Connection<S> {
+    fn new(
+        stream: S,
+        received: Vec<u8>,
+        socket: Address,
+        application: Application,
+        responders: Responders,
+    ) -> Connection<S> {
+        Connection {
+            application,
+            continuation: None,
+            decoder: hpack::Decoder::new(MAX_HEADER_LIST_SIZE),
+            exchanges: BTreeMap::new(),
+            going_away: false,
+            initial_window_size: DEFAULT_WINDOW_SIZE,
+            input: received,
+            last_activity: Instant::now(),
+            last_stream_id: 0,
+            max_frame_size: frame::DEFAULT_MAX_SIZE,
+            output: Vec::new(),
+            receive_window: DEFAULT_WINDOW_SIZE,
+            responders,
+            send_window: DEFAULT_WINDOW_SIZE,
+            served: 0,
+            socket,
+            stream,
+        }
+    }
+
+    fn serve(&mut self, shutdown: &AtomicBool) -> Result<(), Failure> {
+        let write_timeout =
+            Dispatcher::get_timeout(self.application.get_config().server_write_timeout);
+        self.stream.get_socket().set_write_timeout(write_timeout)?;
+        self.write_frame(Frame::settings(&[
+            (
+                frame::SETTINGS_MAX_CONCURRENT_STREAMS,
+                MAX_CONCURRENT_STREAMS,
+            ),
+            (
+                frame::SETTINGS_MAX_HEADER_LIST_SIZE,
+                MAX_HEADER_LIST_SIZE as u32,
+            ),
+        ]));
+        self.flush()?;
+
+        while self.input.len() < PREFACE.len() {
+            if !self.read(true)? || self.going_away {
+                return Ok(());
+            }
+        }
+        if !self.input.starts_with(PREFACE) {
+            return Err(Failure::Connection(
+                ErrorCode::ProtocolError,
+                "Invalid connection preface".to_string(),
+            ));
+        }
+        self.input.drain(..PREFACE.len());
+
+        loop {
+            self.process_frames()?;
+            self.respond();
+            let progress = self.send_data();
+            if shutdown.load(Ordering::SeqCst) && !self.going_away {
+                self.go_away();
+            }
+            self.flush()?;
+            if self.going_away && self.exchanges.is_empty() {
+                return Ok(());
+            }
+            // Frames like WINDOW_UPDATE are received between frames of response bodies
+            if !self.read(!progress)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Tell the client that no new streams are started
+    fn go_away(&mut self) {
+        self.going_away = true;
+        let last_stream_id = self.last_stream_id;
+        self.write_frame(Frame::goaway(last_stream_id, ErrorCode::NoError, ""));
+    }
+
+    /// Read received bytes into the input buffer, waits for them if specified, returns false
+    /// if the client closed the connection
+    fn read(&mut self, wait: bool) -> Result<bool, Failure> {
+        let mut buffer = [0; 16384];
+        let pending = self.stream.has_pending();
+        if !wait && !pending {
+            self.stream.get_socket().set_nonblocking(true)?;
+        } else if !pending {
+            self.stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        }
+        let result = self.stream.read(&mut buffer);
+        if !wait && !pending {
+            self.stream.get_socket().set_nonblocking(false)?;
+        }
+        match result {
+            Ok(0) => Ok(false),
+            Ok(read_size) => {
+                self.input.extend_from_slice(&buffer[..read_size]);
+                self.last_activity = Instant::now();
+                Ok(true)
+            }
+            Err(ref error)
+                if error.kind() == ErrorKind::WouldBlock
+                    || error.kind() == ErrorKind::TimedOut
+                    || error.kind() == ErrorKind::Interrupted =>
+            {
+                if wait {
+                    self.check_idle();
+                }
+                Ok(true)
+            }
+            Err(error) => Err(Failure::Io(error)),
+        }
+    }
+
+    /// Close a connection without streams after the keep-alive time-out and one with
+    /// incomplete requests after the body time-out
+    fn check_idle(&mut self) {
+        let config = self.application.get_config();
+        let idle = self.last_activity.elapsed();
+        if self.exchanges.is_empty() {
+            if !self.going_away && idle >= Duration::from_secs(config.server_keep_alive_timeout) {
+                self.go_away();
+            }
+        } else if let Some(timeout) = Dispatcher::get_timeout(config.server_body_timeout) {
+            if idle >= timeout {
+                self.application.get_metrics().add_timeout(Timeout::Body);
+                self.application.get_feedback().error(format!(
+                    "HTTP/2 requests from {} timed out, time-out: {:?}",
+                    self.socket,
+                    Timeout::Body
+                ));
+                let ids: Vec<u32> = self.exchanges.keys().cloned().collect();
+                for id in ids {
+                    self.reset(id, ErrorCode::Cancel);
+                }
+                if !self.going_away {
+                    self.go_away();
+                }
+            }
+        }
+    }
+
+    fn write_frame(&mut self, frame: Frame) {
+        frame.write_to(&mut self.output);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.output.is_empty() {
+            self.stream.write_all(&self.output)?;
+            self.output.clear();
+        }
+        self.stream.flush()
+    }
+
+    /// End a stream with an error
+    fn reset(&mut self, stream_id: u32, error_code: ErrorCode) {
+        self.exchanges.remove(&stream_id);
+        self.write_frame(Frame::rst_stream(stream_id, error_code));
+    }
+
+    /// Handle every complete frame of the input buffer
+    fn process_frames(&mut self) -> Result<(), Failure> {
+        let mut position = 0;
+        let result = loop {
+            match Frame::parse(&self.input[position..], frame::DEFAULT_MAX_SIZE) {
+                Ok(Some((frame, used))) => {
+                    position += used;
+                    if let Err(failure) = self.handle(frame) {
+                        break Err(failure);
+                    }
+                }
+                Ok(None) => break Ok(()),
+                Err(error_code) => {
+                    break Err(Failure::Connection(
+                        error_code,
+                        "Frame is too large".to_string(),
+                    ))
+                }
+            }
+        };
+        self.input.drain(..position);
+        result
+    }
+
+    fn handle(&mut self, frame: Frame) -> Result<(), Failure> {
+        if let Some((stream_id, _, _)) = self.continuation {
+            if frame.kind != frame::CONTINUATION || frame.stream_id != stream_id {
+                return Err(protocol_error("Expected CONTINUATION frame"));
+            }
+        }
+        match frame.kind {
+            frame::DATA => self.on_data(frame),
+            frame::HEADERS => self.on_headers(frame),
+            frame::PRIORITY => {
+                if frame.stream_id == 0 {
+                    return Err(protocol_error("PRIORITY frame without stream"));
+                }
+                if frame.payload.len() != 5 {
+                    self.reset(frame.stream_id, ErrorCode::FrameSizeError);
+                }
+                Ok(())
+            }
+            frame::RST_STREAM => {
+                if frame.stream_id == 0 || frame.stream_id > self.last_stream_id {
+                    return Err(protocol_error("RST_STREAM frame on idle stream"));
+                }
+                if frame.payload.len() != 4 {
+                    return Err(frame_size_error("RST_STREAM"));
+                }
+                self.exchanges.remove(&frame.stream_id);
+                Ok(())
+            }
+            frame::SETTINGS => self.on_settings(frame),
+            frame::PUSH_PROMISE => Err(protocol_error("Clients can not push")),
+            frame::PING => {
+                if frame.stream_id != 0 {
+                    return Err(protocol_error("PING frame on stream"));
+                }
+                if frame.payload.len() != 8 {
+                    return Err(frame_size_error("PING"));
+                }
+                if !frame.has_flag(frame::FLAG_ACK) {
+                    self.write_frame(Frame::new(frame::PING, frame::FLAG_ACK, 0, frame.payload));
+                }
+                Ok(())
+            }
+            frame::GOAWAY => {
+                if frame.stream_id != 0 {
+                    return Err(protocol_error("GOAWAY frame on stream"));
+                }
+                // Started streams are still answered
+                self.going_away = true;
+                Ok(())
+            }
+            frame::WINDOW_UPDATE => self.on_window_update(frame),
+            frame::CONTINUATION => self.on_continuation(frame),
+            // Unknown frame types are ignored
+            _ => Ok(()),
+        }
+    }
+
+    fn on_data(&mut self, frame: Frame) -> Result<(), Failure> {
+        if frame.stream_id == 0 {
+            return Err(protocol_error("DATA frame without stream"));
+        }
+        // Padding counts against flow control too
+        let length = frame.payload.len() as i64;
+        if length > self.receive_window {
+            return Err(Failure::Connection(
+                ErrorCode::FlowControlError,
+                "DATA frame exceeds connection window".to_string(),
+            ));
+        }
+        self.receive_window -= length;
+        if length > 0 {
+            self.write_frame(Frame::window_update(0, length as u32));
+            self.receive_window += length;
+        }
+
+        let data = match frame.get_data() {
+            Ok(data) => data,
+            Err(error_code) => {
+                return Err(Failure::Connection(
+                    error_code,
+                    "Invalid padding".to_string(),
+                ))
+            }
+        };
+        let limit = self.application.get_config().tcp_limit;
+        let end_stream = frame.has_flag(frame::FLAG_END_STREAM);
+        let error_code = match self.exchanges.get_mut(&frame.stream_id) {
+            Some(ref mut exchange) if !exchange.received => {
+                if length > exchange.receive_window {
+                    Some(ErrorCode::FlowControlError)
+                } else {
+                    // Bytes beyond the limit are counted like for HTTP/1.x requests
+                    let kept = data.len().min(limit.saturating_sub(exchange.body.len()));
+                    exchange.body.extend_from_slice(&data[..kept]);
+                    exchange.overflow_bytes += (data.len() - kept) as u64;
+                    exchange.received = end_stream;
+                    if !end_stream {
+                        exchange.receive_window -= length;
+                    }
+                    None
+                }
+            }
+            _ => {
+                if frame.stream_id > self.last_stream_id {
+                    return Err(protocol_error("DATA frame on idle stream"));
+                }
+                Some(ErrorCode::StreamClosed)
+            }
+        };
+        match error_code {
+            Some(error_code) => self.reset(frame.stream_id, error_code),
+            None => {
+                if !end_stream && length > 0 {
+                    self.write_frame(Frame::window_update(frame.stream_id, length as u32));
+                    if let Some(exchange) = self.exchanges.get_mut(&frame.stream_id) {
+                        exchange.receive_window += length;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_headers(&mut self, frame: Frame) -> Result<(), Failure> {
+        if frame.stream_id == 0 || frame.stream_id % 2 == 0 {
+            return Err(protocol_error("HEADERS frame on invalid stream"));
+        }
+        let block = match frame.get_data() {
+            Ok(block) => block.to_vec(),
+            Err(error_code) => {
+                return Err(Failure::Connection(
+                    error_code,
+                    "Invalid padding".to_string(),
+                ))
+            }
+        };
+        if frame.has_flag(frame::FLAG_END_HEADERS) {
+            self.on_header_block(frame.stream_id, frame.flags, &block)
+        } else {
+            self.continuation = Some((frame.stream_id, frame.flags, block));
+            Ok(())
+        }
+    }
+
+    fn on_continuation(&mut self, frame: Frame) -> Result<(), Failure> {
+        let (stream_id, flags, mut block) = match self.continuation.take() {
+            Some(continuation) => continuation,
+            None => return Err(protocol_error("CONTINUATION frame without HEADERS")),
+        };
+        block.extend_from_slice(&frame.payload);
+        if block.len() > MAX_HEADER_LIST_SIZE {
+            return Err(Failure::Connection(
+                ErrorCode::EnhanceYourCalm,
+                "Header block is too large".to_string(),
+            ));
+        }
+        if frame.has_flag(frame::FLAG_END_HEADERS) {
+            self.on_header_block(stream_id, flags, &block)
+        } else {
+            self.continuation = Some((stream_id, flags, block));
+            Ok(())
+        }
+    }
+
+    fn on_header_block(&mut self, stream_id: u32, flags: u8, block: &[u8]) -> Result<(), Failure> {
+        // Every block is decoded to keep the dynamic table in sync with the client
+        let fields = match self.decoder.decode(block) {
+            Ok(fields) => fields,
+            Err(error) => {
+                return Err(Failure::Connection(
+                    ErrorCode::CompressionError,
+                    error.to_string(),
+                ))
+            }
+        };
+        let end_stream = flags & frame::FLAG_END_STREAM == frame::FLAG_END_STREAM;
+
+        // Trailers end the request, their fields are not passed on
+        let trailers = match self.exchanges.get_mut(&stream_id) {
+            Some(exchange) => {
+                if exchange.received {
+                    Some(ErrorCode::StreamClosed)
+                } else if !end_stream {
+                    Some(ErrorCode::ProtocolError)
+                } else {
+                    exchange.received = true;
+                    None
+                }
+            }
+            None => {
+                if stream_id <= self.last_stream_id {
+                    return Err(Failure::Connection(
+                        ErrorCode::StreamClosed,
+                        "HEADERS frame on closed stream".to_string(),
+                    ));
+                }
+                self.last_stream_id = stream_id;
+                if self.going_away {
+                    // The client knows from GOAWAY that the stream is not processed
+                    return Ok(());
+                }
+                if self.exchanges.len() >= MAX_CONCURRENT_STREAMS as usize {
+                    self.reset(stream_id, ErrorCode::RefusedStream);
+                    return Ok(());
+                }
+                match get_request_fields(fields) {
+                    Some(fields) => {
+                        let initial_window_size = self.initial_window_size;
+                        self.exchanges.insert(
+                            stream_id,
+                            Exchange {
+                                body: Vec::new(),
+                                fields,
+                                overflow_bytes: 0,
+                                received: end_stream,
+                                receive_window: DEFAULT_WINDOW_SIZE,
+                                response: None,
+                                send_window: initial_window_size,
+                            },
+                        );
+                        return Ok(());
+                    }
+                    None => Some(ErrorCode::ProtocolError),
+                }
+            }
+        };
+        if let Some(error_code) = trailers {
+            self.reset(stream_id, error_code);
+        }
+        Ok(())
+    }
+
+    fn on_settings(&mut self, frame: Frame) -> Result<(), Failure> {
+        if frame.stream_id != 0 {
+            return Err(protocol_error("SETTINGS frame on stream"));
+        }
+        if frame.has_flag(frame::FLAG_ACK) {
+            if !frame.payload.is_empty() {
+                return Err(frame_size_error("SETTINGS acknowledgement"));
+            }
+            return Ok(());
+        }
+        if frame.payload.len() % 6 != 0 {
+            return Err(frame_size_error("SETTINGS"));
+        }
+        for setting in frame.payload.chunks(6) {
+            let identifier = (setting[0] as u16) << 8 | setting[1] as u16;
+            let value = frame::read_u32(&setting[2..]);
+            match identifier {
+                frame::SETTINGS_ENABLE_PUSH if value > 1 => {
+                    return Err(protocol_error("Invalid SETTINGS_ENABLE_PUSH"));
+                }
+                frame::SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = value as i64;
+                    if value > MAX_WINDOW_SIZE {
+                        return Err(Failure::Connection(
+                            ErrorCode::FlowControlError,
+                            "Invalid SETTINGS_INITIAL_WINDOW_SIZE".to_string(),
+                        ));
+                    }
+                    // Windows of open streams change by the difference
+                    let delta = value - self.initial_window_size;
+                    self.initial_window_size = value;
+                    for exchange in self.exchanges.values_mut() {
+                        exchange.send_window += delta;
+                        if exchange.send_window > MAX_WINDOW_SIZE {
+                            return Err(Failure::Connection(
+                                ErrorCode::FlowControlError,
+                                "Stream window is too large".to_string(),
+                            ));
+                        }
+                    }
+                }
+                frame::SETTINGS_MAX_FRAME_SIZE => {
+                    let value = value as usize;
+                    if !(frame::DEFAULT_MAX_SIZE..=frame::MAX_MAX_SIZE).contains(&value) {
+                        return Err(protocol_error("Invalid SETTINGS_MAX_FRAME_SIZE"));
+                    }
+                    self.max_frame_size = value;
+                }
+                // Responses don't use the dynamic table and servers don't push
+                _ => {}
+            }
+        }
+        self.write_frame(Frame::new(frame::SETTINGS, frame::FLAG_ACK, 0, Vec::new()));
+        Ok(())
+    }
+
+    fn on_window_update(&mut self, frame: Frame) -> Result<(), Failure> {
+        if frame.payload.len() != 4 {
+            return Err(frame_size_error("WINDOW_UPDATE"));
+        }
+        let increment = (frame::read_u32(&frame.payload) & 0x7fff_ffff) as i64;
+        if frame.stream_id == 0 {
+            if increment == 0 {
+                return Err(protocol_error("WINDOW_UPDATE of zero"));
+            }
+            self.send_window += increment;
+            if self.send_window > MAX_WINDOW_SIZE {
+                return Err(Failure::Connection(
+                    ErrorCode::FlowControlError,
+                    "Connection window is too large".to_string(),
+                ));
+            }
+            return Ok(());
+        }
+        let error_code = match self.exchanges.get_mut(&frame.stream_id) {
+            Some(exchange) => {
+                exchange.send_window += increment;
+                if increment == 0 {
+                    Some(ErrorCode::ProtocolError)
+                } else if exchange.send_window > MAX_WINDOW_SIZE {
+                    Some(ErrorCode::FlowControlError)
+                } else {
+                    None
+                }
+            }
+            // Updates can arrive after a stream was closed
+            None => None,
+        };
+        if let Some(error_code) = error_code {
+            self.reset(frame.stream_id, error_code);
+        }
+        Ok(())
+    }
+
+    /// Answer every request that was received completely
+    fn respond(&mut self) {
+        let ready: Vec<u32> = self
+            .exchanges
+            .iter()
+            .filter(|&(_, exchange)| exchange.received && exchange.response.is_none())
+            .map(|(&stream_id, _)| stream_id)
+            .collect();
+        for stream_id in ready {
+            let request = match self.exchanges.get(&stream_id) {
+                Some(exchange) => get_request(exchange),
+                None => continue,
+            };
+            let secure = self.stream.is_secure();
+            let response = Dispatcher::http_response(
+                &request,
+                &self.socket,
+                &self.application,
+                &self.responders,
+                secure,
+                false,
+                0,
+            );
+            self.served += 1;
+            match response {
+                (ref response, _, _) if response.upgrade.is_some() => {
+                    // Streams share the connection so none of them can take it over
+                    self.application.get_feedback().error(format!(
+                        "Taking over the connection is not supported over HTTP/2, stream: {}",
+                        stream_id
+                    ));
+                    let response = response::Message::new(
+                        "HTTP/2.0".to_string(),
+                        "501 Not Implemented".to_string(),
+                        Default::default(),
+                        Vec::new(),
+                    );
+                    self.send_headers(stream_id, response, false);
+                }
+                (response, _, send_body) => self.send_headers(stream_id, response, send_body),
+            }
+        }
+    }
+
+    /// Send the status and header fields of a response, the body follows in DATA frames
+    fn send_headers(&mut self, stream_id: u32, response: response::Message, send_body: bool) {
+        let status = response
+            .status
+            .split(' ')
+            .next()
+            .unwrap_or("500")
+            .to_string();
+        let mut fields = vec![(":status".to_string(), status)];
+        let mut headers: Vec<(&String, &String)> = response.headers.iter().collect();
+        headers.sort();
+        for (name, value) in headers {
+            let name = name.to_lowercase();
+            if !CONNECTION_FIELDS.contains(&name.as_str()) {
+                fields.push((name, value.clone()));
+            }
+        }
+        let block = hpack::encode(&fields);
+        let end_stream = !send_body || response.body.is_empty();
+
+        // Blocks larger than a frame continue in CONTINUATION frames
+        let mut chunks = block.chunks(self.max_frame_size).peekable();
+        let mut kind = frame::HEADERS;
+        let mut flags = if end_stream {
+            frame::FLAG_END_STREAM
+        } else {
+            0
+        };
+        if chunks.peek().is_none() {
+            flags |= frame::FLAG_END_HEADERS;
+            self.write_frame(Frame::new(kind, flags, stream_id, Vec::new()));
+        }
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= frame::FLAG_END_HEADERS;
+            }
+            self.write_frame(Frame::new(kind, flags, stream_id, chunk.to_vec()));
+            kind = frame::CONTINUATION;
+            flags = 0;
+        }
+
+        if end_stream {
+            self.exchanges.remove(&stream_id);
+        } else if let Some(exchange) = self.exchanges.get_mut(&stream_id) {
+            exchange.response = Some(Outgoing {
+                body: response.body.into_reader(),
+                buffer: Vec::new(),
+                ended: false,
+            });
+        }
+    }
+
+    /// Send one DATA frame for every response with data and room in its window, returns
+    /// whether any frame was sent
+    fn send_data(&mut self) -> bool {
+        let mut progress = false;
+        let mut ended = Vec::new();
+        let mut failed = Vec::new();
+        for (&stream_id, exchange) in self.exchanges.iter_mut() {
+            let outgoing = match exchange.response {
+                Some(ref mut outgoing) => outgoing,
+                None => continue,
+            };
+            if outgoing.buffer.is_empty() && !outgoing.ended {
+                outgoing.buffer.resize(self.max_frame_size, 0);
+                match read_some(&mut outgoing.body, &mut outgoing.buffer) {
+                    Ok(read_size) => {
+                        outgoing.buffer.truncate(read_size);
+                        outgoing.ended = read_size == 0;
+                    }
+                    Err(error) => {
+                        self.application.get_feedback().error(format!(
+                            "Failed to read HTTP/2 response body, error: {}",
+                            error
+                        ));
+                        failed.push(stream_id);
+                        continue;
+                    }
+                }
+            }
+            if outgoing.buffer.is_empty() {
+                // The end of the body doesn't use the window
+                Frame::new(frame::DATA, frame::FLAG_END_STREAM, stream_id, Vec::new())
+                    .write_to(&mut self.output);
+                ended.push(stream_id);
+                progress = true;
+                continue;
+            }
+            let window = self.send_window.min(exchange.send_window);
+            if window <= 0 {
+                continue;
+            }
+            let size = outgoing.buffer.len().min(window as usize);
+            let data: Vec<u8> = outgoing.buffer.drain(..size).collect();
+            self.send_window -= size as i64;
+            exchange.send_window -= size as i64;
+            Frame::new(frame::DATA, 0, stream_id, data).write_to(&mut self.output);
+            progress = true;
+        }
+        for stream_id in ended {
+            self.exchanges.remove(&stream_id);
+        }
+        for stream_id in failed {
+            self.reset(stream_id, ErrorCode::InternalError);
+        }
+        progress
+    }
+}
+
+fn protocol_error(message: &str) -> Failure {
+    Failure::Connection(ErrorCode::ProtocolError, message.to_string())
+}
+
+fn frame_size_error(kind: &str) -> Failure {
+    Failure::Connection(
+        ErrorCode::FrameSizeError,
+        format!("Invalid size of {} frame", kind),
+    )
+}
+
+/// Read at least one byte unless the reader has ended
+fn read_some(reader: &mut Box<dyn Read + Send>, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buffer) {
+            Err(ref error) if error.kind() == ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+/// Validate the header fields of a request, pseudo-header fields have to come first and
+/// names have to be lower-case
+fn get_request_fields(fields: Vec<hpack::Field>) -> Option<Vec<(String, String)>> {
+    let mut request_fields: Vec<(String, String)> = Vec::new();
+    let mut regular = false;
+    for (name, value) in fields {
+        let name = String::from_utf8(name).ok()?;
+        let value = String::from_utf8(value).ok()?;
+        let token = name.strip_prefix(':').unwrap_or(&name);
+        if token.is_empty()
+            || !token.bytes().all(is_token_byte)
+            || value.contains('\r')
+            || value.contains('\n')
+            || value.contains('\0')
+        {
+            return None;
+        }
+        if name.starts_with(':') {
+            let known =
+                name == ":method" || name == ":scheme" || name == ":path" || name == ":authority";
+            let duplicate = request_fields.iter().any(|field| field.0 == name);
+            if regular || !known || duplicate {
+                return None;
+            }
+        } else {
+            regular = true;
+            if CONNECTION_FIELDS.contains(&name.as_str()) || (name == "te" && value != "trailers") {
+                return None;
+            }
+        }
+        request_fields.push((name, value));
+    }
+    let has = |name: &str| {
+        request_fields
+            .iter()
+            .any(|field| field.0 == name && !field.1.is_empty())
+    };
+    if has(":method") && has(":scheme") && has(":path") {
+        Some(request_fields)
+    } else {
+        None
+    }
+}
+
+/// Whether a byte may be in a lower-case field name, see the token of RFC 7230 section 3.2.6
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_lowercase() || byte.is_ascii_digit() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Frame the request of a stream as a HTTP/1.x message for the responders, header field
+/// names get their usual capitalization
+fn get_request(exchange: &Exchange) -> Request {
+    let get = |name: &str| {
+        exchange
+            .fields
+            .iter()
+            .find(|field| field.0 == name)
+            .map(|field| field.1.as_str())
+            .unwrap_or("")
+    };
+    let mut head = format!("{} {} HTTP/2.0\r\n", get(":method"), get(":path"));
+    if !get(":authority").is_empty() {
+        head.push_str(&format!("Host: {}\r\n", get(":authority")));
+    }
+
+    // Repeated fields are combined, cookies with their own separator
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for (name, value) in exchange.fields.iter() {
+        if name.starts_with(':') || name == "host" || name == "content-length" {
+            continue;
+        }
+        let name = get_capitalized(name);
+        let separator = if name == "Cookie" { "; " } else { ", " };
+        match headers.iter_mut().find(|header| header.0 == name) {
+            Some(header) => {
+                header.1.push_str(separator);
+                header.1.push_str(value);
+            }
+            None => headers.push((name, value.clone())),
+        }
+    }
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    let length = exchange.body.len() as u64 + exchange.overflow_bytes;
+    if length > 0 {
+        head.push_str(&format!("Content-Length: {}\r\n", length));
+    }
+    head.push_str("\r\n");
+
+    Request {
+        head: head.into_bytes(),
+        body: exchange.body.clone(),
+        overflow_bytes: exchange.overflow_bytes,
+    }
+}
+
+/// Capitalize every word of a header field name
+/// ```rust
+/// use milstian_internet_framework::response::tcp::http2::get_capitalized;
+/// assert_eq!(get_capitalized("if-none-match"), "If-None-Match");
+/// ```
+pub fn get_capitalized(name: &str) -> String {
+    name.split('-')
+        .map(|word| {
+            let mut characters = word.chars();
+            match characters.next() {
+                Some(first) => first.to_uppercase().chain(characters).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::thread;
+
+    use response::tcp::http::{error, filesystem};
+    use test_config;
+
+    fn get_application() -> Application {
+        let mut config = test_config();
+        config.server_keep_alive_timeout = 1;
+        Application::new(config)
+    }
+
+    /// Serve one connection with the HTTP/1.x dispatcher, returns the client end
+    fn connect() -> (TcpStream, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, socket) = listener.accept().unwrap();
+            let responders: Responders = Arc::new(vec![
+                Box::new(filesystem::Responder::new()),
+                Box::new(error::Responder::new()),
+            ]);
+            Dispatcher::http(
+                stream,
+                socket.into(),
+                get_application(),
+                responders,
+                Arc::new(AtomicBool::new(false)),
+            );
+        });
+        (TcpStream::connect(address).unwrap(), server)
+    }
+
+    fn send(client: &mut TcpStream, frames: Vec<Frame>) {
+        let mut buffer = Vec::new();
+        for frame in frames {
+            frame.write_to(&mut buffer);
+        }
+        client.write_all(&buffer).unwrap();
+    }
+
+    fn get_headers(stream_id: u32, flags: u8, fields: &[(&str, &str)]) -> Frame {
+        let fields: Vec<(String, String)> = fields
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Frame::new(
+            frame::HEADERS,
+            flags | frame::FLAG_END_HEADERS,
+            stream_id,
+            hpack::encode(&fields),
+        )
+    }
+
+    /// Read frames until a condition is met by the last one, returns all read frames
+    fn receive<F: Fn(&Frame) -> bool>(
+        client: &mut TcpStream,
+        buffer: &mut Vec<u8>,
+        until: F,
+    ) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let mut read_buffer = [0; 4096];
+        loop {
+            while let Some((frame, used)) = Frame::parse(buffer, frame::MAX_MAX_SIZE).unwrap() {
+                buffer.drain(..used);
+                let done = until(&frame);
+                frames.push(frame);
+                if done {
+                    return frames;
+                }
+            }
+            let read_size = client.read(&mut read_buffer).unwrap();
+            assert!(read_size > 0, "Connection closed after {:?}", frames);
+            buffer.extend_from_slice(&read_buffer[..read_size]);
+        }
+    }
+
+    #[test]
+    fn prior_knowledge() {
+        let (mut client, server) = connect();
+        let mut buffer = Vec::new();
+        client.write_all(PREFACE).unwrap();
+        send(
+            &mut client,
+            vec![
+                Frame::settings(&[(frame::SETTINGS_INITIAL_WINDOW_SIZE, 100)]),
+                get_headers(
+                    1,
+                    frame::FLAG_END_STREAM,
+                    &[
+                        (":method", "GET"),
+                        (":scheme", "http"),
+                        (":path", "/index.htm"),
+                        (":authority", "localhost"),
+                    ],
+                ),
+                get_headers(
+                    3,
+                    frame::FLAG_END_STREAM,
+                    &[
+                        (":method", "HEAD"),
+                        (":scheme", "http"),
+                        (":path", "/about.htm"),
+                    ],
+                ),
+                Frame::new(frame::PING, 0, 0, b"12345678".to_vec()),
+            ],
+        );
+
+        // The body of stream 1 stops at its window while stream 3 is answered
+        let frames = receive(&mut client, &mut buffer, |frame| frame.kind == frame::DATA);
+        assert_eq!(frames[0].kind, frame::SETTINGS);
+        assert!(frames.contains(&Frame::new(frame::SETTINGS, frame::FLAG_ACK, 0, Vec::new())));
+        assert!(frames.contains(&Frame::new(
+            frame::PING,
+            frame::FLAG_ACK,
+            0,
+            b"12345678".to_vec()
+        )));
+        let mut decoder = hpack::Decoder::new(MAX_HEADER_LIST_SIZE);
+        let headers = frames
+            .iter()
+            .find(|frame| frame.kind == frame::HEADERS && frame.stream_id == 1)
+            .unwrap();
+        let fields = decoder.decode(&headers.payload).unwrap();
+        assert_eq!(fields[0], (b":status".to_vec(), b"200".to_vec()));
+        assert!(!fields.iter().any(|field| field.0 == b"connection".to_vec()));
+        let headers = frames
+            .iter()
+            .find(|frame| frame.kind == frame::HEADERS && frame.stream_id == 3)
+            .unwrap();
+        assert!(headers.has_flag(frame::FLAG_END_STREAM));
+        assert_eq!(
+            decoder.decode(&headers.payload).unwrap()[0],
+            (b":status".to_vec(), b"200".to_vec())
+        );
+
+        let mut body = Vec::new();
+        for frame in frames.iter().filter(|frame| frame.kind == frame::DATA) {
+            body.extend_from_slice(&frame.payload);
+        }
+        assert_eq!(body.len(), 100);
+
+        send(&mut client, vec![Frame::window_update(1, 100000)]);
+        let frames = receive(&mut client, &mut buffer, |frame| {
+            frame.kind == frame::DATA && frame.has_flag(frame::FLAG_END_STREAM)
+        });
+        for frame in frames.iter().filter(|frame| frame.kind == frame::DATA) {
+            assert_eq!(frame.stream_id, 1);
+            body.extend_from_slice(&frame.payload);
+        }
+        assert_eq!(body, fs::read("./html/index.htm").unwrap());
+
+        send(&mut client, vec![Frame::goaway(0, ErrorCode::NoError, "")]);
+        server.join().unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        client.shutdown(Shutdown::Both).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        let (mut client, server) = connect();
+        let mut buffer = Vec::new();
+        client.write_all(PREFACE).unwrap();
+        send(
+            &mut client,
+            vec![
+                Frame::settings(&[]),
+                // Requests without :path and with upper-case names are refused
+                get_headers(
+                    1,
+                    frame::FLAG_END_STREAM,
+                    &[(":method", "GET"), (":scheme", "http")],
+                ),
+                get_headers(
+                    3,
+                    frame::FLAG_END_STREAM,
+                    &[
+                        (":method", "GET"),
+                        (":scheme", "http"),
+                        (":path", "/"),
+                        ("Accept", "*/*"),
+                    ],
+                ),
+                // Names that are not tokens would inject header lines into the HTTP/1.x head
+                get_headers(
+                    5,
+                    frame::FLAG_END_STREAM,
+                    &[
+                        (":method", "GET"),
+                        (":scheme", "http"),
+                        (":path", "/"),
+                        ("x: 1\r\ntransfer-encoding", "chunked"),
+                    ],
+                ),
+                Frame::window_update(0, 0),
+            ],
+        );
+        let frames = receive(&mut client, &mut buffer, |frame| {
+            frame.kind == frame::GOAWAY
+        });
+        assert!(frames.contains(&Frame::rst_stream(1, ErrorCode::ProtocolError)));
+        assert!(frames.contains(&Frame::rst_stream(3, ErrorCode::ProtocolError)));
+        assert!(frames.contains(&Frame::rst_stream(5, ErrorCode::ProtocolError)));
+        let goaway = frames.last().unwrap();
+        assert_eq!(
+            frame::read_u32(&goaway.payload[4..]),
+            ErrorCode::ProtocolError as u32
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let (mut client, server) = connect();
+        let mut buffer = Vec::new();
+        client.write_all(PREFACE).unwrap();
+        send(&mut client, vec![Frame::settings(&[])]);
+        let frames = receive(&mut client, &mut buffer, |frame| {
+            frame.kind == frame::GOAWAY
+        });
+        assert_eq!(
+            frames.last().unwrap(),
+            &Frame::goaway(0, ErrorCode::NoError, "")
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn request() {
+        let exchange = Exchange {
+            body: b"a=b".to_vec(),
+            fields: vec![
+                (":method".to_string(), "POST".to_string()),
+                (":path".to_string(), "/form?c=d".to_string()),
+                (":authority".to_string(), "example.com".to_string()),
+                ("cookie".to_string(), "a=1".to_string()),
+                ("if-none-match".to_string(), "\"1\"".to_string()),
+                ("cookie".to_string(), "b=2".to_string()),
+                ("content-length".to_string(), "5".to_string()),
+            ],
+            overflow_bytes: 2,
+            received: true,
+            receive_window: DEFAULT_WINDOW_SIZE,
+            response: None,
+            send_window: DEFAULT_WINDOW_SIZE,
+        };
+        let request = get_request(&exchange);
+        assert_eq!(
+            String::from_utf8(request.head).unwrap(),
+            "POST /form?c=d HTTP/2.0\r\nHost: example.com\r\nCookie: a=1; b=2\r\nIf-None-Match: \"1\"\r\nContent-Length: 5\r\n\r\n"
+        );
+        assert_eq!(request.body, b"a=b".to_vec());
+        assert_eq!(request.overflow_bytes, 2);
+    }
+}
@@ -1,125 +1,1011 @@
 //! # Namespace for TCP responses
 
 pub mod http;
+pub mod http2;
+pub mod sse;
+pub mod websocket;
 
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::net::{SocketAddr, TcpStream};
+use std::io::{BufWriter, ErrorKind};
+use std::net::{Shutdown, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
 use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use response::tcp::http::ResponderInterface;
+use application_layer::http::request;
+use application_layer::http::response;
+use application_layer::http::response::{Body, CHUNK_SIZE};
+use metrics::Timeout;
+use response::tcp::http::reader::{Error as ReaderError, ReadTimeout, Reader, Request};
+use response::tcp::http::{ResponderInterface, Responders};
+use transport_layer::Address;
 
 use Application;
 
+/// How often idle persistent connections check whether shutdown was requested
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Number of file bytes sent between checks of the write deadline
+const SEND_FILE_SLICE: u64 = 1024 * 1024;
+
+/// The operating system socket of a connection
+pub trait Socket {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+    /// Read without removing the data from the receive queue
+    fn peek(&self, buffer: &mut [u8]) -> io::Result<usize>;
+    fn read(&self, buffer: &mut [u8]) -> io::Result<usize>;
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn peek(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        TcpStream::peek(self, buffer)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        (&mut &*self).read(buffer)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+}
+
+impl Socket for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn peek(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        // The standard library has no stable peek for Unix domain sockets
+        let read_size = unsafe {
+            libc::recv(
+                self.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                libc::MSG_PEEK,
+            )
+        };
+        if read_size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(read_size as usize)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        (&mut &*self).read(buffer)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        UnixStream::shutdown(self, how)
+    }
+}
+
+/// A connection to a client that HTTP can be served over
+pub trait Stream: ReadTimeout + Write {
+    /// The socket of the connection, used for time-outs and for waiting on the client
+    fn get_socket(&self) -> &dyn Socket;
+
+    /// Whether received data is waiting to be read without reading from the socket
+    fn has_pending(&mut self) -> bool {
+        false
+    }
+
+    /// Tell the client that nothing more will be written
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.get_socket().shutdown(Shutdown::Write)
+    }
+
+    /// Whether the connection is encrypted with TLS
+    fn is_secure(&self) -> bool {
+        false
+    }
+
+    /// The application protocol agreed on with ALPN
+    fn get_alpn_protocol(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl Stream for TcpStream {
+    fn get_socket(&self) -> &dyn Socket {
+        self
+    }
+}
+
+impl Stream for UnixStream {
+    fn get_socket(&self) -> &dyn Socket {
+        self
+    }
+}
+
+/// Takes over a connection after the head of a response is written, like WebSocket after
+/// `101 Switching Protocols` or a stream of server-sent events
+pub trait Upgrade: Send {
+    /// Serve the connection until it should close, `received` holds the bytes that arrived
+    /// after the request
+    fn serve(
+        self: Box<Self>,
+        stream: &mut dyn Stream,
+        received: Vec<u8>,
+        socket: &Address,
+        application: &Application,
+        shutdown: &AtomicBool,
+    );
+}
+
+impl fmt::Debug for dyn Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Upgrade")
+    }
+}
+
 /// This struct should handle the dispatching of requests to a specific response type
 pub struct Dispatcher {}
 
 impl Dispatcher {
-    /// This method takes a TcpStream and tries to find a appropriate response handler
-    pub fn http(
-        mut stream: TcpStream,
-        socket: SocketAddr,
+    /// This method takes a stream and serves HTTP requests from it in order until the
+    /// connection should no longer persist or shutdown is requested
+    pub fn http<S: Stream>(
+        stream: S,
+        socket: Address,
         application: Application,
-        responders: Vec<Box<ResponderInterface + Send>>,
+        responders: Responders,
+        shutdown: Arc<AtomicBool>,
     ) {
-        // Create a array with 512 elements containing the value 0
-        let mut temp_buffer = [0; 512];
-        let mut buffer: Vec<u8> = Vec::new();
         let config = application.get_config();
-        let mut acc_read_size: u64 = 0;
-        let mut overflow_bytes: u64 = 0;
+        let mut reader = Reader::new(stream);
+        reader.set_timeouts(
+            Dispatcher::get_timeout(config.server_header_timeout),
+            Dispatcher::get_timeout(config.server_body_timeout),
+        );
+        if reader.get_ref().get_alpn_protocol() == Some(&b"h2"[..]) {
+            let (stream, received) = reader.into_parts();
+            return http2::serve(stream, received, socket, application, responders, shutdown);
+        }
+        let secure = reader.get_ref().is_secure();
+        let mut served: usize = 0;
 
         loop {
-            match stream.read(&mut temp_buffer) {
-                Ok(read_size) => {
-                    // Move all non-empty values to new buffer
-                    for value in temp_buffer.iter() {
-                        acc_read_size = acc_read_size + 1;
-                        if value != &0 {
-                            if buffer.len() < config.tcp_limit {
-                                buffer.push(*value);
-                            } else {
-                                overflow_bytes = overflow_bytes + 1;
-                            }
+            if served > 0 {
+                let timeout = Duration::from_secs(config.server_keep_alive_timeout);
+                if !reader.has_buffered() && !reader.get_mut().has_pending() {
+                    let socket = reader.get_ref().get_socket();
+                    match Dispatcher::wait_for_request(socket, timeout, &shutdown) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            application
+                                .get_feedback()
+                                .info(format!("Closing idle TCP stream after {} requests", served));
+                            break;
+                        }
+                        Err(error) => {
+                            application.get_feedback().error(format!(
+                                "Failed to wait for request on TCP stream, error: {}",
+                                error
+                            ));
+                            break;
                         }
                     }
+                }
+            }
 
-                    // Did we reach end of stream?
-                    if read_size < 512 {
-                        break;
+            let request_deadline = Dispatcher::get_timeout(config.server_request_timeout)
+                .map(|timeout| Instant::now() + timeout);
+            reader.set_request_deadline(request_deadline);
+            let request = match reader.read_request(config.tcp_limit) {
+                Ok(request) => request,
+                Err(ReaderError::Closed) => {
+                    if served == 0 {
+                        application
+                            .get_feedback()
+                            .info("TCP stream was empty".to_string());
+                    }
+                    break;
+                }
+                Err(ReaderError::Timeout(timeout)) => {
+                    application.get_metrics().add_timeout(timeout);
+                    application.get_feedback().error(format!(
+                        "HTTP request from {} timed out, time-out: {:?}",
+                        socket, timeout
+                    ));
+                    if let Err(error) = Dispatcher::write_and_close(
+                        reader.get_mut(),
+                        "408 Request Timeout",
+                        HashMap::new(),
+                    ) {
+                        application.get_feedback().error(format!(
+                            "Failed to respond 408 to TCP stream, error: {}",
+                            error
+                        ));
                     }
+                    break;
                 }
                 Err(error) => {
+                    application.get_feedback().error(format!(
+                        "Failed to read HTTP request from TCP stream, error: {}",
+                        error
+                    ));
+                    if let Some(status) = error.get_status() {
+                        if let Err(error) =
+                            Dispatcher::write_and_close(reader.get_mut(), status, HashMap::new())
+                        {
+                            application.get_feedback().error(format!(
+                                "Failed to respond {} to TCP stream, error: {}",
+                                status, error
+                            ));
+                        }
+                    }
+                    break;
+                }
+            };
+            if served == 0 && request.head.starts_with(&http2::PREFACE[..18]) {
+                // HTTP/2 with prior knowledge, the preface looks like a request at first
+                let (stream, buffered) = reader.into_parts();
+                let mut received = request.head;
+                received.extend(buffered);
+                return http2::serve(stream, received, socket, application, responders, shutdown);
+            }
+            served += 1;
+
+            let mut keep_alive = config.server_keep_alive_timeout > 0
+                && served < config.server_keep_alive_max
+                && !shutdown.load(Ordering::SeqCst)
+                && Request::is_keep_alive(&request.head);
+
+            let (mut response, persist, send_body) = Dispatcher::http_response(
+                &request,
+                &socket,
+                &application,
+                &responders,
+                secure,
+                keep_alive,
+                config.server_keep_alive_max.saturating_sub(served),
+            );
+            keep_alive = persist;
+            let writer = TimedWriter {
+                deadline: request_deadline,
+                stream: reader.get_mut(),
+                timeout: Dispatcher::get_timeout(config.server_write_timeout),
+            };
+            if let Err(error) = Dispatcher::write_response(writer, &mut response, send_body) {
+                if error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::TimedOut {
+                    let timeout = match request_deadline {
+                        Some(deadline) if Instant::now() >= deadline => Timeout::Request,
+                        _ => Timeout::Write,
+                    };
+                    application.get_metrics().add_timeout(timeout);
+                    application.get_feedback().error(format!(
+                        "HTTP response to {} timed out, time-out: {:?}",
+                        socket, timeout
+                    ));
+                } else {
                     application
                         .get_feedback()
-                        .error(format!("Failed to read from TCP stream, error: {}", error));
-                    break;
+                        .error(format!("Failed to write to TCP stream, error: {}", error));
                 }
+                break;
+            }
+            if let (Some(upgrade), true) = (response.upgrade.take(), send_body) {
+                // Only requests that take over the connection are parsed again
+                let client = match request::Message::from_tcp_stream(&request.head) {
+                    Some(request_message) => {
+                        http::get_client_address(&request_message, &application, &socket)
+                    }
+                    None => socket.clone(),
+                };
+                let (mut stream, received) = reader.into_parts();
+                upgrade.serve(&mut stream, received, &client, &application, &shutdown);
+                return;
+            }
+
+            if !keep_alive {
+                break;
             }
         }
+    }
 
-        if buffer.len() > 0 {
-            // println!("Found non-empty TCP blog {:?} b= {:?}", str::from_utf8(&buffer), buffer);
-            let mut response = Vec::new();
-            let mut log = String::new();
-            let mut http_dispatcher = http::Dispatcher::new();
+    /// Respond 503 Service Unavailable on a connection the server has no capacity for
+    /// and close it without reading the request
+    pub fn http_unavailable<S: Stream>(mut stream: S, retry_after: u64) -> io::Result<()> {
+        let mut headers = HashMap::new();
+        headers.insert("Retry-After".to_string(), retry_after.to_string());
+        Dispatcher::write_and_close(&mut stream, "503 Service Unavailable", headers)
+    }
 
-            if http_dispatcher.matches(&buffer, &application, &socket, &overflow_bytes) {
-                application
-                    .get_feedback()
-                    .info(format!("Request was successfully decoded as HTTP"));
-                match http_dispatcher.respond(
-                    &buffer,
-                    &application,
-                    &socket,
-                    responders,
-                    &overflow_bytes,
-                ) {
-                    Ok((http_response, http_log)) => {
-                        response = http_response;
-                        log = http_log;
-                        application
-                            .get_feedback()
-                            .info(format!("Found non-empty HTTP response to TCP stream"));
+    /// Build a response without body that closes the connection
+    pub fn get_closing_response(
+        status: &str,
+        mut headers: HashMap<String, String>,
+    ) -> response::Message {
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        headers.insert("Connection".to_string(), "close".to_string());
+        response::Message::new(
+            "HTTP/1.1".to_string(),
+            status.to_string(),
+            headers,
+            Vec::new(),
+        )
+    }
+
+    /// Write a response without body and close the connection
+    fn write_and_close<S: Stream>(
+        stream: &mut S,
+        status: &str,
+        headers: HashMap<String, String>,
+    ) -> io::Result<()> {
+        let mut response = Dispatcher::get_closing_response(status, headers);
+        stream
+            .get_socket()
+            .set_write_timeout(Some(Duration::from_secs(1)))?;
+        stream.write_all(&response.to_bytes()?)?;
+        stream.shutdown_write()?;
+
+        // Discard what the client has sent so far so closing doesn't reset the connection
+        let stream = stream.get_socket();
+        stream.set_nonblocking(true)?;
+        let mut discard = [0; 1024];
+        while let Ok(read_size) = stream.read(&mut discard) {
+            if read_size == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get a time-out from a number of seconds where 0 means no time-out
+    pub fn get_timeout(seconds: u64) -> Option<Duration> {
+        if seconds == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(seconds))
+        }
+    }
+
+    /// Wait until the next request starts to arrive on an idle connection, returns false if
+    /// the connection was closed, timed out or shutdown was requested while waiting
+    fn wait_for_request(
+        stream: &dyn Socket,
+        timeout: Duration,
+        shutdown: &AtomicBool,
+    ) -> io::Result<bool> {
+        let started = Instant::now();
+        let mut peek_buffer = [0; 1];
+        stream.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+        loop {
+            if shutdown.load(Ordering::SeqCst) || started.elapsed() >= timeout {
+                return Ok(false);
+            }
+            match stream.peek(&mut peek_buffer) {
+                Ok(read_size) => return Ok(read_size > 0),
+                Err(ref error)
+                    if error.kind() == ErrorKind::WouldBlock
+                        || error.kind() == ErrorKind::TimedOut
+                        || error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Decode a framed request as HTTP and build the response for it, also returns whether
+    /// the connection can persist after the response and whether the body should be sent.
+    /// Requests that no responder answers get 500 Internal Server Error.
+    pub fn http_response(
+        request: &Request,
+        socket: &Address,
+        application: &Application,
+        responders: &[Box<dyn ResponderInterface>],
+        secure: bool,
+        mut keep_alive: bool,
+        remaining: usize,
+    ) -> (response::Message, bool, bool) {
+        // Announced bodies that exceed the limit are refused by the reader, so only chunked
+        // bodies get here with overflow bytes
+        if request.overflow_bytes > 0 {
+            application.get_feedback().error(format!(
+                "HTTP request from {} exceeded limit of {} bytes by {} bytes",
+                socket,
+                application.get_config().tcp_limit,
+                request.overflow_bytes
+            ));
+            let response =
+                Dispatcher::get_closing_response("413 Payload Too Large", HashMap::new());
+            return (response, false, true);
+        }
+
+        let buffer = request.to_bytes();
+        let mut http_dispatcher = http::Dispatcher::new();
+
+        if !http_dispatcher.matches(&buffer, application, socket) {
+            let status = Request::get_invalid_status(&request.head);
+            application.get_feedback().info(format!(
+                "Request could not be decoded as HTTP, responding {}",
+                status
+            ));
+            let response = Dispatcher::get_closing_response(status, HashMap::new());
+            return (response, false, true);
+        }
+        application
+            .get_feedback()
+            .info("Request was successfully decoded as HTTP".to_string());
+
+        let client = match http_dispatcher.request_message {
+            Some(ref request_message) => {
+                http::get_client_address(request_message, application, socket)
+            }
+            None => socket.clone(),
+        };
+        match http_dispatcher.respond(&buffer, application, &client, responders) {
+            Ok((mut response, log)) => {
+                application.get_feedback().info(log);
+
+                let mut head_request = false;
+                let mut chunked_supported = false;
+                let mut https = secure;
+                if let Some(request_message) = &http_dispatcher.request_message {
+                    head_request = request_message.request_line.method == request::Method::Head;
+                    chunked_supported =
+                        request_message.request_line.protocol == request::Protocol::V1_1;
+                    https = https || http::is_forwarded_https(request_message, application, socket);
+                }
+
+                if let Some(ref hsts) = application.get_config().tls_hsts {
+                    // Clients ignore the policy on insecure connections
+                    if https {
+                        response.headers.insert(
+                            "Strict-Transport-Security".to_string(),
+                            hsts.to_header_value(),
+                        );
                     }
-                    Err(error) => {
-                        application
-                            .get_feedback()
-                            .error(format!("Got empty HTTP response! Error: {}", error));
+                }
+
+                if response.upgrade.is_some() {
+                    // What takes over the connection decides when it closes
+                    return (response, false, !head_request);
+                }
+
+                if response.is_chunked() {
+                    // Clients before HTTP/1.1 get the body delimited by closing the connection
+                    if !chunked_supported {
+                        response
+                            .headers
+                            .retain(|key, _| !key.eq_ignore_ascii_case("Transfer-Encoding"));
+                        keep_alive = false;
+                    }
+                } else if response.get_header("Content-Length").is_none()
+                    && !response.status.starts_with('1')
+                    && !response.status.starts_with("204")
+                    && !response.status.starts_with("304")
+                {
+                    // Persistent connections need every response to be delimited
+                    match response.body.len() {
+                        Some(length) => {
+                            response
+                                .headers
+                                .insert("Content-Length".to_string(), length.to_string());
+                        }
+                        None => {
+                            if chunked_supported {
+                                response.set_chunked();
+                            } else {
+                                keep_alive = false;
+                            }
+                        }
                     }
                 }
-            } else {
+
+                if keep_alive {
+                    response
+                        .headers
+                        .insert("Connection".to_string(), "keep-alive".to_string());
+                    response.headers.insert(
+                        "Keep-Alive".to_string(),
+                        format!(
+                            "timeout={}, max={}",
+                            application.get_config().server_keep_alive_timeout,
+                            remaining
+                        ),
+                    );
+                } else {
+                    response
+                        .headers
+                        .insert("Connection".to_string(), "close".to_string());
+                }
+                (response, keep_alive, !head_request)
+            }
+            Err(error) => {
                 application
                     .get_feedback()
-                    .info(format!("Request could not be decoded as HTTP"));
+                    .error(format!("Got empty HTTP response! Error: {}", error));
+                let response =
+                    Dispatcher::get_closing_response("500 Internal Server Error", HashMap::new());
+                (response, false, true)
             }
+        }
+    }
 
-            if !response.is_empty() {
-                application.get_feedback().info(log);
-                match stream.write(&response) {
-                    Ok(_) => {
-                        if let Err(error) = stream.flush() {
-                            application
-                                .get_feedback()
-                                .info(format!("Failed to flush TCP stream, error: {}", error));
-                        }
-                    }
-                    Err(error) => {
-                        application
-                            .get_feedback()
-                            .error(format!("Failed to write to TCP stream, error: {}", error));
-                    }
+    /// Write a response to the stream, bodies are copied in bounded chunks
+    fn write_response<S: Stream>(
+        mut writer: TimedWriter<S>,
+        response: &mut response::Message,
+        send_body: bool,
+    ) -> io::Result<()> {
+        let mut buffered = BufWriter::with_capacity(CHUNK_SIZE, &mut writer);
+        buffered.write_all(response.header_to_string().as_bytes())?;
+        if send_body {
+            if !response.is_chunked() {
+                if let Body::File(ref file, length) = response.body {
+                    buffered.flush()?;
+                    drop(buffered);
+                    return Dispatcher::send_file(&mut writer, file, length);
                 }
-            } else {
-                application.get_feedback().error(format!(
-                    "Found no response for TCP stream {:?}",
-                    str::from_utf8(&buffer)
+            }
+            response.write_body_to(&mut buffered)?;
+        }
+        buffered.flush()
+    }
+
+    /// Copy a file to the stream, on Linux the standard library does this with `sendfile`
+    /// without copying the file through user-space
+    fn send_file<S: Stream>(
+        writer: &mut TimedWriter<S>,
+        file: &File,
+        length: u64,
+    ) -> io::Result<()> {
+        let mut copied: u64 = 0;
+        while copied < length {
+            writer.set_write_timeout()?;
+            let slice = (length - copied).min(SEND_FILE_SLICE);
+            let slice_copied = io::copy(&mut file.take(slice), writer.stream)?;
+            copied += slice_copied;
+            if slice_copied < slice {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("File ended after {} of {} bytes", copied, length),
                 ));
             }
-        } else {
-            application.get_feedback().info(format!(
-                "TCP stream was empty, accumulated read size: {}",
-                acc_read_size
-            ));
         }
+        Ok(())
+    }
+}
+
+/// Writes to a stream with a time-out for each write that is bounded by an optional deadline
+struct TimedWriter<'a, S: 'a> {
+    deadline: Option<Instant>,
+    stream: &'a mut S,
+    timeout: Option<Duration>,
+}
+
+impl<'a, S: Stream> TimedWriter<'a, S> {
+    fn set_write_timeout(&self) -> io::Result<()> {
+        let mut timeout = self.timeout;
+        if let Some(deadline) = self.deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(
+                    ErrorKind::TimedOut,
+                    "Request deadline has passed",
+                ));
+            }
+            let remaining = deadline - now;
+            timeout = Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)));
+        }
+        self.stream.get_socket().set_write_timeout(timeout)
+    }
+}
+
+impl<'a, S: Stream> Write for TimedWriter<'a, S> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.set_write_timeout()?;
+        self.stream.write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use response::tcp::http::reader::HEAD_LIMIT;
+    use response::tcp::http::{error, filesystem, Context, HandlerInterface};
+    use {test_config, Config, Hsts};
+
+    /// Responds the test argument of the request body as a stream of unknown length
+    struct Echo {}
+
+    impl ResponderInterface for Echo {
+        fn matches(
+            &self,
+            request_message: &request::Message,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<Box<dyn HandlerInterface>> {
+            if request_message.request_line.request_uri_base == "/echo" {
+                return Some(Box::new(Echo {}));
+            }
+            None
+        }
+    }
+
+    impl HandlerInterface for Echo {
+        fn respond(
+            self: Box<Self>,
+            request_message: &request::Message,
+            _context: &Context,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Result<response::Message, String> {
+            let mut body = Vec::new();
+            if let request::BodyContentType::SinglePart(ref arguments) = request_message.body {
+                if let Some(value) = arguments.get("test") {
+                    body = value.as_bytes().to_vec();
+                }
+            }
+            Ok(response::Message::new(
+                request::Message::get_protocol_text(&request_message.request_line.protocol),
+                "200 OK".to_string(),
+                HashMap::new(),
+                Body::Stream(Box::new(Cursor::new(body)), None),
+            ))
+        }
+    }
+
+    /// A body source that fails after its data
+    struct Failing {
+        data: Cursor<Vec<u8>>,
+    }
+
+    impl Read for Failing {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let size = self.data.read(buffer)?;
+            if size > 0 {
+                return Ok(size);
+            }
+            Err(io::Error::new(ErrorKind::ConnectionReset, "Source failed"))
+        }
+    }
+
+    /// Responds to requests for /fail with a body that fails while it is sent
+    struct Fail {}
+
+    impl ResponderInterface for Fail {
+        fn matches(
+            &self,
+            request_message: &request::Message,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<Box<dyn HandlerInterface>> {
+            if request_message.request_line.request_uri_base == "/fail" {
+                return Some(Box::new(Fail {}));
+            }
+            None
+        }
+    }
+
+    impl HandlerInterface for Fail {
+        fn respond(
+            self: Box<Self>,
+            _request_message: &request::Message,
+            _context: &Context,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Result<response::Message, String> {
+            let body = Failing {
+                data: Cursor::new(b"abc".to_vec()),
+            };
+            Ok(response::Message::new(
+                "HTTP/1.1".to_string(),
+                "200 OK".to_string(),
+                HashMap::new(),
+                Body::Stream(Box::new(body), None),
+            ))
+        }
+    }
+
+    /// Panics while responding to requests for /panic
+    struct Panic {}
+
+    impl ResponderInterface for Panic {
+        fn matches(
+            &self,
+            request_message: &request::Message,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<Box<dyn HandlerInterface>> {
+            if request_message.request_line.request_uri_base == "/panic" {
+                return Some(Box::new(Panic {}));
+            }
+            None
+        }
+    }
+
+    impl HandlerInterface for Panic {
+        fn respond(
+            self: Box<Self>,
+            _request_message: &request::Message,
+            _context: &Context,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Result<response::Message, String> {
+            panic!("Responder failed");
+        }
+    }
+
+    fn serve(request: &[u8]) -> String {
+        serve_until(request, get_application(), Arc::new(AtomicBool::new(false)))
+    }
+
+    fn get_application() -> Application {
+        Application::new(Config {
+            server_body_timeout: 1,
+            server_header_timeout: 1,
+            server_keep_alive_max: 3,
+            server_keep_alive_timeout: 1,
+            server_shutdown_timeout: 1,
+            ..test_config()
+        })
+    }
+
+    fn serve_until(request: &[u8], application: Application, shutdown: Arc<AtomicBool>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, socket) = listener.accept().unwrap();
+            let responders: Vec<Box<dyn ResponderInterface>> = vec![
+                Box::new(Echo {}),
+                Box::new(Fail {}),
+                Box::new(Panic {}),
+                Box::new(filesystem::Responder::new()),
+                Box::new(error::Responder::new()),
+            ];
+            Dispatcher::http(
+                stream,
+                socket.into(),
+                application,
+                Arc::new(responders),
+                shutdown,
+            );
+        });
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(request).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        client.shutdown(Shutdown::Both).unwrap();
+        server.join().unwrap();
+        response
+    }
+
+    #[test]
+    fn http_pipelined_requests() {
+        let response = serve(
+            b"GET /index.htm HTTP/1.1\r\n\r\nHEAD /about.htm HTTP/1.1\r\n\r\nGET /index.htm HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 3);
+        assert_eq!(response.matches("Connection: keep-alive").count(), 2);
+        assert_eq!(response.matches("Connection: close").count(), 1);
+        assert_eq!(response.matches("<html").count(), 2);
+    }
+
+    #[test]
+    fn http_keep_alive_limit() {
+        let response = serve(
+            b"GET /index.htm HTTP/1.1\r\n\r\nGET /index.htm HTTP/1.1\r\n\r\nGET /index.htm HTTP/1.1\r\n\r\nGET /index.htm HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 3);
+        assert!(response.contains("Keep-Alive: timeout=1, max=1"));
+        assert_eq!(response.matches("Connection: close").count(), 1);
+    }
+
+    #[test]
+    fn http_idle_timeout() {
+        let response = serve(b"GET /index.htm HTTP/1.1\r\n\r\n");
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 1);
+        assert!(response.contains("Connection: keep-alive"));
+
+        let response = serve(b"GET /index.htm HTTP/1.0\r\n\r\n");
+        assert!(response.contains("Connection: close"));
+    }
+
+    #[test]
+    fn http_chunked() {
+        let response = serve(
+            b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5\r\ntest=\r\n3\r\nabc\r\n0\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\n3\r\nabc\r\n0\r\n\r\n"));
+
+        let response = serve(
+            b"POST /echo HTTP/1.0\r\nConnection: keep-alive\r\nContent-Length: 8\r\n\r\ntest=abc",
+        );
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("\r\n\r\nabc"));
+    }
+
+    #[test]
+    fn http_bodies() {
+        let file = fs::read("./html/index.htm").unwrap();
+        let response = serve(b"GET /index.htm HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.contains(&format!("Content-Length: {}\r\n", file.len())));
+        assert!(response.ends_with(&format!("\r\n\r\n{}", String::from_utf8_lossy(&file))));
+
+        let response =
+            serve(b"GET /index.htm HTTP/1.1\r\nRange: bytes=5-9\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.ends_with(&format!(
+            "\r\n\r\n{}",
+            String::from_utf8_lossy(&file[5..10])
+        )));
+
+        // A body that fails while it is sent ends the connection without its last chunk
+        let response = serve(b"GET /fail HTTP/1.1\r\n\r\nGET /index.htm HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(response.ends_with("\r\n\r\n3\r\nabc\r\n"));
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 1);
+    }
+
+    #[test]
+    fn http_shutdown() {
+        let response = serve_until(
+            b"GET /index.htm HTTP/1.1\r\n\r\nGET /index.htm HTTP/1.1\r\n\r\n",
+            get_application(),
+            Arc::new(AtomicBool::new(true)),
+        );
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 1);
+        assert!(response.contains("Connection: close"));
+    }
+
+    #[test]
+    fn http_hsts() {
+        let request =
+            b"GET /index.htm HTTP/1.1\r\nX-Forwarded-Proto: https\r\nConnection: close\r\n\r\n";
+        let mut config = get_application().get_config().clone();
+        config.tls_hsts = Some(Hsts {
+            max_age: 600,
+            include_subdomains: false,
+            preload: false,
+        });
+        let response = serve_until(
+            request,
+            Application::new(config.clone()),
+            Arc::new(AtomicBool::new(false)),
+        );
+        assert!(!response.contains("Strict-Transport-Security"));
+
+        // Only trusted proxies tell whether a request arrived over HTTPS
+        config.server_trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+        let response = serve_until(
+            request,
+            Application::new(config.clone()),
+            Arc::new(AtomicBool::new(false)),
+        );
+        assert!(response.contains("Strict-Transport-Security: max-age=600\r\n"));
+
+        // The protocol is told by the proxy, not by the client it forwarded
+        let response = serve_until(
+            b"GET /index.htm HTTP/1.1\r\nX-Forwarded-For: 203.0.113.7\r\nX-Forwarded-Proto: https\r\nConnection: close\r\n\r\n",
+            Application::new(config),
+            Arc::new(AtomicBool::new(false)),
+        );
+        assert!(response.contains("Strict-Transport-Security: max-age=600\r\n"));
+    }
+
+    #[test]
+    fn http_responder_panic() {
+        let response = serve(
+            b"GET /panic HTTP/1.1\r\n\r\nGET /index.htm HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 1);
+    }
+
+    #[test]
+    fn http_error_responses() {
+        let response = serve(b"POST /echo HTTP/1.1\r\nContent-Length: 10000000000\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+
+        let mut request =
+            b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n800\r\n".to_vec();
+        request.extend_from_slice(&[b'a'; 2048]);
+        request.extend_from_slice(b"\r\n0\r\n\r\n");
+        let response = serve(&request);
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+
+        let response = serve(b"BREW / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+
+        let response = serve(b"GET / HTTP/3.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+
+        let response = serve(b"GET / FTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        let response = serve(b"POST /echo HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+
+        // A request smuggled in the body is not served after the error
+        let response = serve(
+            b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /index.htm HTTP/1.1\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("200 OK"));
+
+        let mut request = b"GET /".to_vec();
+        request.extend_from_slice(&[b'a'; HEAD_LIMIT]);
+        request.extend_from_slice(b" HTTP/1.1\r\n\r\n");
+        let response = serve(&request);
+        assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"));
+
+        let mut request = b"GET / HTTP/1.1\r\nCookie: ".to_vec();
+        request.extend_from_slice(&[b'a'; HEAD_LIMIT]);
+        request.extend_from_slice(b"\r\n\r\n");
+        let response = serve(&request);
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+
+    #[test]
+    fn http_timeouts() {
+        let application = get_application();
+        let response = serve_until(
+            b"GET /index.htm HTTP/1.1\r\nHost: loc",
+            application.clone(),
+            Arc::new(AtomicBool::new(false)),
+        );
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert_eq!(application.get_metrics().get_timeouts(Timeout::Header), 1);
+
+        let response = serve_until(
+            b"POST /echo HTTP/1.1\r\nContent-Length: 8\r\n\r\ntest",
+            application.clone(),
+            Arc::new(AtomicBool::new(false)),
+        );
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert_eq!(application.get_metrics().get_timeouts(Timeout::Body), 1);
     }
 }
@@ -0,0 +1,490 @@
+//! # TCP Server-Sent Events responses
+//! A `Responder` makes an event stream responder usable among the HTTP responders of an
+//! application, it answers with a `text/event-stream` response and keeps writing events to
+//! the connection until the client disconnects.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::ErrorKind;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use application_layer::http::request;
+use application_layer::http::response;
+use response::tcp::http;
+use response::tcp::{Dispatcher, Stream, Upgrade};
+use thread::get_panic_message;
+use transport_layer::Address;
+use Application;
+
+/// How long a stream may be silent before a heartbeat comment is sent
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often a waiting stream checks whether the client disconnected or shutdown was requested
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An event, fields that are `None` are not sent
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Event {
+    pub event: Option<String>,
+    pub id: Option<String>,
+    pub data: String,
+    /// Milliseconds the client waits before it reconnects
+    pub retry: Option<u64>,
+}
+
+impl Event {
+    pub fn new(data: &str) -> Event {
+        Event {
+            data: data.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Encode the event as fields, every line of the data is sent as a data field
+/// ```rust
+/// use milstian_internet_framework::response::tcp::sse::Event;
+/// let mut event = Event::new("Hello\nworld");
+/// event.event = Some("greeting".to_string());
+/// event.id = Some("1".to_string());
+/// assert_eq!(
+///     event.to_string(),
+///     "event: greeting\nid: 1\ndata: Hello\ndata: world\n\n".to_string()
+/// );
+/// ```
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref event) = self.event {
+            writeln!(f, "event: {}", get_field_value(event))?;
+        }
+        if let Some(ref id) = self.id {
+            // Clients ignore identifiers with NULL characters
+            let id: String = get_field_value(id).chars().filter(|c| *c != '\0').collect();
+            writeln!(f, "id: {}", id)?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry)?;
+        }
+        for line in self.data.replace("\r\n", "\n").split(['\n', '\r']) {
+            writeln!(f, "data: {}", line)?;
+        }
+        writeln!(f)
+    }
+}
+
+/// Errors that can occur while writing events
+#[derive(Debug)]
+pub enum Error {
+    /// The client closed the connection
+    Disconnected,
+    /// Shutdown of the server was requested
+    Shutdown,
+    /// Failed to read from or write to the underlying stream
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Disconnected => write!(f, "Client closed the event stream"),
+            Error::Shutdown => write!(f, "Server is shutting down"),
+            Error::Io(error) => write!(f, "Failed to use event stream, error: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        match error.kind() {
+            ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => {
+                Error::Disconnected
+            }
+            _ => Error::Io(error),
+        }
+    }
+}
+
+/// Writes events to a client, heartbeat comments are sent while waiting so proxies keep the
+/// connection open and disconnects are noticed
+pub struct Writer<'a> {
+    heartbeat: Option<Duration>,
+    last_event_id: Option<String>,
+    last_write: Instant,
+    shutdown: &'a AtomicBool,
+    stream: &'a mut dyn Stream,
+}
+
+impl<'a> Writer<'a> {
+    /// Start writing to a stream of a client that last received the event with
+    /// `last_event_id` before it reconnected
+    pub fn new(
+        stream: &'a mut dyn Stream,
+        last_event_id: Option<String>,
+        shutdown: &'a AtomicBool,
+    ) -> Writer<'a> {
+        Writer {
+            heartbeat: Some(HEARTBEAT_INTERVAL),
+            last_event_id,
+            last_write: Instant::now(),
+            shutdown,
+            stream,
+        }
+    }
+
+    /// The Last-Event-ID header field the client reconnected with
+    pub fn get_last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    /// Set how long the stream may be silent while waiting, `None` disables heartbeats
+    pub fn set_heartbeat(&mut self, interval: Option<Duration>) {
+        self.heartbeat = interval;
+    }
+
+    pub fn send(&mut self, event: &Event) -> Result<(), Error> {
+        self.write(event.to_string().as_bytes())
+    }
+
+    /// Send a comment, which clients ignore
+    pub fn send_comment(&mut self, comment: &str) -> Result<(), Error> {
+        let mut encoded = String::new();
+        for line in comment.replace("\r\n", "\n").split(['\n', '\r']) {
+            encoded.push_str(&format!(": {}\n", line));
+        }
+        encoded.push('\n');
+        self.write(encoded.as_bytes())
+    }
+
+    /// Wait until the next event should be sent, fails when the client disconnects or
+    /// shutdown is requested in the meantime
+    pub fn wait(&mut self, duration: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + duration;
+        let mut buffer = [0; 1024];
+        loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                return Err(Error::Shutdown);
+            }
+            if let Some(heartbeat) = self.heartbeat {
+                if self.last_write.elapsed() >= heartbeat {
+                    self.send_comment("heartbeat")?;
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+
+            // Clients send nothing after the request, so reading only notices disconnects
+            self.stream
+                .set_read_timeout(Some(POLL_INTERVAL.min(deadline - now)))?;
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(Error::Disconnected),
+                Ok(_) => {}
+                Err(ref error)
+                    if error.kind() == ErrorKind::WouldBlock
+                        || error.kind() == ErrorKind::TimedOut
+                        || error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(Error::from(error)),
+            }
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.stream.write_all(bytes)?;
+        self.stream.flush()?;
+        self.last_write = Instant::now();
+        Ok(())
+    }
+}
+
+pub trait ResponderInterface: Send + Sync {
+    /// A handler that sends the events when the responder streams events for a request,
+    /// state of the request is kept in the handler
+    fn matches(
+        &self,
+        request_message: &request::Message,
+        application: &Application,
+        socket: &Address,
+    ) -> Option<Box<dyn HandlerInterface + Send>>;
+}
+
+pub trait HandlerInterface {
+    /// Send events until the client disconnects, the connection is closed when this returns
+    fn serve(self: Box<Self>, writer: &mut Writer, application: &Application, socket: &Address);
+}
+
+/// Answers requests that an event stream responder matches and hands their connections
+/// over to it
+pub struct Responder {
+    responder: Box<dyn ResponderInterface>,
+}
+
+impl Responder {
+    pub fn new(responder: Box<dyn ResponderInterface>) -> Responder {
+        Responder { responder }
+    }
+}
+
+impl http::ResponderInterface for Responder {
+    fn matches(
+        &self,
+        request_message: &request::Message,
+        application: &Application,
+        socket: &Address,
+    ) -> Option<Box<dyn http::HandlerInterface>> {
+        let handler = self
+            .responder
+            .matches(request_message, application, socket)?;
+        Some(Box::new(EventStream { handler }))
+    }
+}
+
+/// Answers a request an event stream responder matched
+struct EventStream {
+    handler: Box<dyn HandlerInterface + Send>,
+}
+
+impl http::HandlerInterface for EventStream {
+    fn respond(
+        self: Box<Self>,
+        request_message: &request::Message,
+        _context: &http::Context,
+        _application: &Application,
+        _socket: &Address,
+    ) -> Result<response::Message, String> {
+        let protocol = request::Message::get_protocol_text(&request_message.request_line.protocol);
+        let mut headers: HashMap<String, String> = HashMap::new();
+        headers.insert("Content-Type".to_string(), "text/event-stream".to_string());
+        headers.insert("Cache-Control".to_string(), "no-cache".to_string());
+        // The body ends when the connection closes
+        headers.insert("Connection".to_string(), "close".to_string());
+        // Stops NGINX from buffering events when it is used as proxy
+        headers.insert("X-Accel-Buffering".to_string(), "no".to_string());
+
+        let mut last_event_id = None;
+        for (key, value) in request_message.headers.iter() {
+            if key.eq_ignore_ascii_case("Last-Event-ID") {
+                last_event_id = Some(value.to_string());
+            }
+        }
+
+        let mut response =
+            response::Message::new(protocol, "200 OK".to_string(), headers, Vec::new());
+        response.upgrade = Some(Box::new(Session {
+            handler: self.handler,
+            last_event_id,
+        }));
+        Ok(response)
+    }
+}
+
+/// Serves a connection with the handler of an event stream responder
+struct Session {
+    handler: Box<dyn HandlerInterface + Send>,
+    last_event_id: Option<String>,
+}
+
+impl Upgrade for Session {
+    fn serve(
+        self: Box<Self>,
+        stream: &mut dyn Stream,
+        _received: Vec<u8>,
+        socket: &Address,
+        application: &Application,
+        shutdown: &AtomicBool,
+    ) {
+        let write_timeout = Dispatcher::get_timeout(application.get_config().server_write_timeout);
+        if let Err(error) = stream.get_socket().set_write_timeout(write_timeout) {
+            application.get_feedback().error(format!(
+                "Failed to set write time-out of event stream, error: {}",
+                error
+            ));
+            return;
+        }
+        application
+            .get_feedback()
+            .info(format!("Event stream to {} was opened", socket));
+
+        let handler = self.handler;
+        let mut writer = Writer::new(stream, self.last_event_id, shutdown);
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| {
+            handler.serve(&mut writer, application, socket)
+        })) {
+            application.get_feedback().error(format!(
+                "Event stream responder panicked, error: {}",
+                get_panic_message(&payload)
+            ));
+        }
+        application
+            .get_feedback()
+            .info(format!("Event stream to {} was closed", socket));
+    }
+}
+
+/// Remove line breaks from a field value since they would end the field
+fn get_field_value(value: &str) -> String {
+    value.replace(['\r', '\n'], "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::prelude::*;
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    use response::tcp::http::{error, ResponderInterface as HttpResponderInterface};
+    use test_config;
+
+    /// Counts from the last event identifier until the client disconnects
+    struct Counter {}
+
+    impl ResponderInterface for Counter {
+        fn matches(
+            &self,
+            request_message: &request::Message,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<Box<dyn HandlerInterface + Send>> {
+            if request_message.request_line.request_uri_base == "/count" {
+                return Some(Box::new(Counter {}));
+            }
+            None
+        }
+    }
+
+    impl HandlerInterface for Counter {
+        fn serve(
+            self: Box<Self>,
+            writer: &mut Writer,
+            _application: &Application,
+            _socket: &Address,
+        ) {
+            let mut count: u64 = writer
+                .get_last_event_id()
+                .and_then(|id| id.parse().ok())
+                .unwrap_or(0);
+            writer.set_heartbeat(Some(Duration::from_millis(50)));
+            loop {
+                count += 1;
+                let mut event = Event::new(&count.to_string());
+                event.id = Some(count.to_string());
+                if writer.send(&event).is_err() || writer.wait(Duration::from_millis(200)).is_err()
+                {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn get_application() -> Application {
+        Application::new(test_config())
+    }
+
+    fn serve(request: &[u8], shutdown: Arc<AtomicBool>) -> (TcpStream, thread::JoinHandle<()>) {
+        serve_application(request, get_application(), shutdown)
+    }
+
+    fn serve_application(
+        request: &[u8],
+        application: Application,
+        shutdown: Arc<AtomicBool>,
+    ) -> (TcpStream, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, socket) = listener.accept().unwrap();
+            let responders: Vec<Box<dyn HttpResponderInterface>> = vec![
+                Box::new(Responder::new(Box::new(Counter {}))),
+                Box::new(error::Responder::new()),
+            ];
+            Dispatcher::http(
+                stream,
+                socket.into(),
+                application,
+                Arc::new(responders),
+                shutdown,
+            );
+        });
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.write_all(request).unwrap();
+        (client, server)
+    }
+
+    /// Read until the received text contains a pattern
+    fn read_until(client: &mut TcpStream, received: &mut String, pattern: &str) {
+        let mut buffer = [0; 1024];
+        while !received.contains(pattern) {
+            let read_size = client.read(&mut buffer).unwrap();
+            assert!(read_size > 0, "Stream closed before {:?}", pattern);
+            received.push_str(&String::from_utf8_lossy(&buffer[..read_size]));
+        }
+    }
+
+    #[test]
+    fn test_event() {
+        let event = Event {
+            event: Some("a\r\nb".to_string()),
+            id: Some("1\0".to_string()),
+            data: "x\r\ny\rz\n".to_string(),
+            retry: Some(1000),
+        };
+        assert_eq!(
+            event.to_string(),
+            "event: ab\nid: 1\nretry: 1000\ndata: x\ndata: y\ndata: z\ndata: \n\n"
+        );
+        assert_eq!(Event::new("").to_string(), "data: \n\n");
+    }
+
+    #[test]
+    fn test_stream() {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (mut client, server) = serve(
+            b"GET /count HTTP/1.1\r\nHost: localhost\r\nLast-Event-ID: 41\r\n\r\n",
+            Arc::clone(&shutdown),
+        );
+        let mut received = String::new();
+        read_until(&mut client, &mut received, "data: 43\n\n");
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(received.contains("Content-Type: text/event-stream\r\n"));
+        assert!(received.contains("Connection: close\r\n"));
+        assert!(!received.contains("Content-Length"));
+        assert!(received.contains("\r\n\r\nid: 42\ndata: 42\n\n"));
+        assert!(received.contains(": heartbeat\n\n"));
+
+        // The responder stops when the client disconnects
+        client.shutdown(Shutdown::Both).unwrap();
+        server.join().unwrap();
+
+        let (mut client, server) = serve(b"GET /count HTTP/1.1\r\n\r\n", Arc::clone(&shutdown));
+        let mut received = String::new();
+        read_until(&mut client, &mut received, "data: 1\n\n");
+        shutdown.store(true, Ordering::SeqCst);
+        client.read_to_string(&mut received).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_head() {
+        let (mut client, server) = serve(
+            b"HEAD /count HTTP/1.1\r\n\r\n",
+            Arc::new(AtomicBool::new(false)),
+        );
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(received.ends_with("\r\n\r\n"));
+        server.join().unwrap();
+    }
+}
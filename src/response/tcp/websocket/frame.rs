@@ -0,0 +1,304 @@
+//! # WebSocket frames
+//! Every frame starts with a 2 to 14 byte header of FIN flag, opcode, payload length and
+//! the masking key that clients have to apply to their payloads.
+
+use std::str;
+
+pub const CONTINUATION: u8 = 0x0;
+pub const TEXT: u8 = 0x1;
+pub const BINARY: u8 = 0x2;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xa;
+
+/// Largest payload of a control frame
+pub const MAX_CONTROL_SIZE: usize = 125;
+
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_UNSUPPORTED_DATA: u16 = 1003;
+/// Reported when a close frame had no status code, never sent
+pub const CLOSE_NO_STATUS: u16 = 1005;
+/// Reported when the connection closed without a close frame, never sent
+pub const CLOSE_ABNORMAL: u16 = 1006;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_POLICY_VIOLATION: u16 = 1008;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+pub const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+/// A frame of a WebSocket connection
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(fin: bool, opcode: u8, payload: Vec<u8>) -> Frame {
+        Frame {
+            fin,
+            opcode,
+            payload,
+        }
+    }
+
+    /// Build a close frame, the reason is shortened to fit in a control frame
+    pub fn close(code: u16, reason: &str) -> Frame {
+        let mut length = reason.len().min(MAX_CONTROL_SIZE - 2);
+        while !reason.is_char_boundary(length) {
+            length -= 1;
+        }
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(&reason.as_bytes()[..length]);
+        Frame::new(true, CLOSE, payload)
+    }
+
+    /// Whether the frame is a close, ping or pong frame
+    pub fn is_control(&self) -> bool {
+        self.opcode & 0x8 != 0
+    }
+
+    /// Parse a frame from the start of a buffer and unmask its payload, returns the frame and
+    /// number of used bytes or `None` if the frame is not complete yet. Fails with the close
+    /// status code the connection should be closed with.
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::websocket::frame::{self, Frame};
+    /// let mut buffer = Vec::new();
+    /// Frame::new(true, frame::TEXT, b"Hello".to_vec()).write_to(&mut buffer, Some([1, 2, 3, 4]));
+    /// assert_eq!(Frame::parse(&buffer[..5], 1024, true), Ok(None));
+    /// assert_eq!(
+    ///     Frame::parse(&buffer, 1024, true),
+    ///     Ok(Some((Frame::new(true, frame::TEXT, b"Hello".to_vec()), 11)))
+    /// );
+    /// assert_eq!(Frame::parse(&buffer, 1024, false), Err(frame::CLOSE_PROTOCOL_ERROR));
+    /// assert_eq!(Frame::parse(&buffer, 4, true), Err(frame::CLOSE_TOO_BIG));
+    /// ```
+    pub fn parse(
+        buffer: &[u8],
+        max_size: usize,
+        masked: bool,
+    ) -> Result<Option<(Frame, usize)>, u16> {
+        if buffer.len() < 2 {
+            return Ok(None);
+        }
+        let fin = buffer[0] & 0x80 != 0;
+        let opcode = buffer[0] & 0x0f;
+        // No extensions are negotiated so the reserved bits have to be zero
+        if buffer[0] & 0x70 != 0 {
+            return Err(CLOSE_PROTOCOL_ERROR);
+        }
+        match opcode {
+            CONTINUATION | TEXT | BINARY | CLOSE | PING | PONG => {}
+            _ => return Err(CLOSE_PROTOCOL_ERROR),
+        }
+        if (buffer[1] & 0x80 != 0) != masked {
+            return Err(CLOSE_PROTOCOL_ERROR);
+        }
+
+        let (length, mut used) = match buffer[1] & 0x7f {
+            126 => {
+                if buffer.len() < 4 {
+                    return Ok(None);
+                }
+                (u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4)
+            }
+            127 => {
+                if buffer.len() < 10 {
+                    return Ok(None);
+                }
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&buffer[2..10]);
+                (u64::from_be_bytes(bytes), 10)
+            }
+            length => (length as u64, 2),
+        };
+        let frame = Frame::new(fin, opcode, Vec::new());
+        if frame.is_control() && (length > MAX_CONTROL_SIZE as u64 || !fin) {
+            return Err(CLOSE_PROTOCOL_ERROR);
+        }
+        if length > max_size as u64 {
+            return Err(CLOSE_TOO_BIG);
+        }
+        let length = length as usize;
+
+        let mut mask = None;
+        if masked {
+            if buffer.len() < used + 4 {
+                return Ok(None);
+            }
+            mask = Some([
+                buffer[used],
+                buffer[used + 1],
+                buffer[used + 2],
+                buffer[used + 3],
+            ]);
+            used += 4;
+        }
+        if buffer.len() < used + length {
+            return Ok(None);
+        }
+
+        let mut payload = buffer[used..used + length].to_vec();
+        if let Some(mask) = mask {
+            apply_mask(&mut payload, mask);
+        }
+        Ok(Some((Frame { payload, ..frame }, used + length)))
+    }
+
+    /// Get the status code and reason of a close frame, fails with the close status code the
+    /// connection should be closed with
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::websocket::frame::{self, Frame};
+    /// assert_eq!(
+    ///     Frame::close(frame::CLOSE_GOING_AWAY, "Bye").get_close(),
+    ///     Ok((frame::CLOSE_GOING_AWAY, "Bye".to_string()))
+    /// );
+    /// assert_eq!(
+    ///     Frame::new(true, frame::CLOSE, Vec::new()).get_close(),
+    ///     Ok((frame::CLOSE_NO_STATUS, String::new()))
+    /// );
+    /// assert_eq!(
+    ///     Frame::close(frame::CLOSE_NO_STATUS, "").get_close(),
+    ///     Err(frame::CLOSE_PROTOCOL_ERROR)
+    /// );
+    /// ```
+    pub fn get_close(&self) -> Result<(u16, String), u16> {
+        if self.payload.is_empty() {
+            return Ok((CLOSE_NO_STATUS, String::new()));
+        }
+        if self.payload.len() < 2 {
+            return Err(CLOSE_PROTOCOL_ERROR);
+        }
+        let code = u16::from_be_bytes([self.payload[0], self.payload[1]]);
+        match code {
+            1000..=1003 | 1007..=1011 | 3000..=4999 => {}
+            _ => return Err(CLOSE_PROTOCOL_ERROR),
+        }
+        match str::from_utf8(&self.payload[2..]) {
+            Ok(reason) => Ok((code, reason.to_string())),
+            Err(_) => Err(CLOSE_INVALID_DATA),
+        }
+    }
+
+    /// Append the encoded frame to a buffer, servers send their frames without mask
+    pub fn write_to(&self, buffer: &mut Vec<u8>, mask: Option<[u8; 4]>) {
+        let fin = if self.fin { 0x80 } else { 0 };
+        buffer.push(fin | self.opcode);
+        let masked = if mask.is_some() { 0x80 } else { 0 };
+        let length = self.payload.len();
+        if length < 126 {
+            buffer.push(masked | length as u8);
+        } else if length <= 0xffff {
+            buffer.push(masked | 126);
+            buffer.extend_from_slice(&(length as u16).to_be_bytes());
+        } else {
+            buffer.push(masked | 127);
+            buffer.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+        match mask {
+            Some(mask) => {
+                buffer.extend_from_slice(&mask);
+                let start = buffer.len();
+                buffer.extend_from_slice(&self.payload);
+                apply_mask(&mut buffer[start..], mask);
+            }
+            None => buffer.extend_from_slice(&self.payload),
+        }
+    }
+}
+
+/// Mask or unmask a payload with a masking key
+pub fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        // Examples from RFC 6455 section 5.7
+        assert_eq!(
+            Frame::parse(&[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f], 1024, false),
+            Ok(Some((Frame::new(true, TEXT, b"Hello".to_vec()), 7)))
+        );
+        let masked = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert_eq!(
+            Frame::parse(&masked, 1024, true),
+            Ok(Some((Frame::new(true, TEXT, b"Hello".to_vec()), 11)))
+        );
+        assert_eq!(
+            Frame::parse(&[0x01, 0x03, 0x48, 0x65, 0x6c], 1024, false),
+            Ok(Some((Frame::new(false, TEXT, b"Hel".to_vec()), 5)))
+        );
+
+        // Extended payload lengths
+        for length in [125, 126, 65535, 65536].iter() {
+            let frame = Frame::new(true, BINARY, vec![7; *length]);
+            let mut buffer = Vec::new();
+            frame.write_to(&mut buffer, Some([9, 8, 7, 6]));
+            assert_eq!(
+                Frame::parse(&buffer, 70000, true),
+                Ok(Some((frame, buffer.len())))
+            );
+            assert_eq!(
+                Frame::parse(&buffer[..buffer.len() - 1], 70000, true),
+                Ok(None)
+            );
+        }
+        assert_eq!(Frame::parse(&[0x82, 0x7f, 0xff], 1024, false), Ok(None));
+    }
+
+    #[test]
+    fn errors() {
+        // Reserved bits and opcodes
+        assert_eq!(
+            Frame::parse(&[0xc1, 0x00], 1024, false),
+            Err(CLOSE_PROTOCOL_ERROR)
+        );
+        assert_eq!(
+            Frame::parse(&[0x83, 0x00], 1024, false),
+            Err(CLOSE_PROTOCOL_ERROR)
+        );
+        // Fragmented and too large control frames
+        assert_eq!(
+            Frame::parse(&[0x09, 0x00], 1024, false),
+            Err(CLOSE_PROTOCOL_ERROR)
+        );
+        assert_eq!(
+            Frame::parse(&[0x89, 0x7e, 0x00, 0x7e], 1024, false),
+            Err(CLOSE_PROTOCOL_ERROR)
+        );
+        assert_eq!(
+            Frame::parse(&[0x82, 0x7f, 0x80, 0, 0, 0, 0, 0, 0, 0], 1024, false),
+            Err(CLOSE_TOO_BIG)
+        );
+
+        assert_eq!(
+            Frame::new(true, CLOSE, vec![3]).get_close(),
+            Err(CLOSE_PROTOCOL_ERROR)
+        );
+        assert_eq!(
+            Frame::new(true, CLOSE, vec![3, 232, 0xff]).get_close(),
+            Err(CLOSE_INVALID_DATA)
+        );
+    }
+
+    #[test]
+    fn close() {
+        let reason = "å".repeat(100);
+        let frame = Frame::close(CLOSE_NORMAL, &reason);
+        assert!(frame.payload.len() <= MAX_CONTROL_SIZE);
+        let (code, shortened) = frame.get_close().unwrap();
+        assert_eq!(code, CLOSE_NORMAL);
+        assert!(reason.starts_with(&shortened));
+        assert_eq!(shortened.chars().count(), 61);
+    }
+}
@@ -0,0 +1,849 @@
+//! # TCP WebSocket responses
+//! Connections are upgraded from HTTP/1.1 with the handshake of RFC 6455, a `Responder` makes a
+//! WebSocket responder usable among the HTTP responders of an application so it is served on
+//! the same listener and by the same workers.
+
+pub mod frame;
+mod sha1;
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::ErrorKind;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use application_layer::http::request;
+use application_layer::http::response;
+use application_layer::http::response::CHUNK_SIZE;
+use response::tcp::http;
+use response::tcp::{Dispatcher, Stream, Upgrade};
+use thread::get_panic_message;
+use transport_layer::Address;
+use Application;
+
+use self::frame::Frame;
+
+/// Appended to the key of a client before it is hashed into the accept key
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only version of the protocol, as sent in the Sec-WebSocket-Version header field
+const VERSION: &str = "13";
+
+/// How often a connection that waits for a message checks whether shutdown was requested
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a closing connection waits for the close frame of the client
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A complete message, fragmented messages are joined before they are received
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Errors that can occur while exchanging messages
+#[derive(Debug)]
+pub enum Error {
+    /// The connection is closed, (status code, reason)
+    Closed(u16, String),
+    /// The client broke the protocol and the connection was closed with a status code
+    Protocol(u16),
+    /// No message arrived within the time-out, the connection is still open
+    Timeout,
+    /// Failed to read from or write to the underlying stream
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Closed(code, reason) => write!(
+                f,
+                "WebSocket connection is closed, status code: {}, reason: {:?}",
+                code, reason
+            ),
+            Error::Protocol(code) => write!(
+                f,
+                "WebSocket client broke the protocol, status code: {}",
+                code
+            ),
+            Error::Timeout => write!(f, "No WebSocket message arrived within the time-out"),
+            Error::Io(error) => write!(f, "Failed to use WebSocket stream, error: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+/// A WebSocket connection with a message-level API, pings are answered and the closing
+/// handshake is completed while receiving
+pub struct Connection<'a> {
+    buffer: Vec<u8>,
+    closed: Option<(u16, String)>,
+    fragments: Option<(u8, Vec<u8>)>,
+    max_size: usize,
+    shutdown: &'a AtomicBool,
+    stream: &'a mut dyn Stream,
+    timeout: Option<Duration>,
+}
+
+impl<'a> Connection<'a> {
+    /// Start a connection on a stream, `received` holds bytes that were read after the
+    /// handshake and messages larger than `max_size` bytes close the connection
+    pub fn new(
+        stream: &'a mut dyn Stream,
+        received: Vec<u8>,
+        max_size: usize,
+        shutdown: &'a AtomicBool,
+    ) -> Connection<'a> {
+        Connection {
+            buffer: received,
+            closed: None,
+            fragments: None,
+            max_size,
+            shutdown,
+            stream,
+            timeout: None,
+        }
+    }
+
+    /// Set how long `receive` waits for a message, `None` waits until the connection closes
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Whether a close frame was sent or received
+    pub fn is_closed(&self) -> bool {
+        self.closed.is_some()
+    }
+
+    /// Wait for the next message from the client
+    pub fn receive(&mut self) -> Result<Message, Error> {
+        if let Some((code, ref reason)) = self.closed {
+            return Err(Error::Closed(code, reason.clone()));
+        }
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let received = self.read_frame(deadline)?;
+            match received.opcode {
+                frame::PING => {
+                    self.write_frame(&Frame::new(true, frame::PONG, received.payload))?;
+                }
+                frame::PONG => {}
+                frame::CLOSE => {
+                    let (code, reason) = match received.get_close() {
+                        Ok(close) => close,
+                        Err(code) => return self.fail(code),
+                    };
+                    // The close frame is echoed to complete the closing handshake
+                    let echo = if code == frame::CLOSE_NO_STATUS {
+                        Frame::new(true, frame::CLOSE, Vec::new())
+                    } else {
+                        Frame::close(code, "")
+                    };
+                    self.closed = Some((code, reason.clone()));
+                    self.write_frame(&echo)?;
+                    return Err(Error::Closed(code, reason));
+                }
+                frame::TEXT | frame::BINARY => {
+                    if self.fragments.is_some() {
+                        return self.fail(frame::CLOSE_PROTOCOL_ERROR);
+                    }
+                    if received.fin {
+                        return self.get_message(received.opcode, received.payload);
+                    }
+                    self.fragments = Some((received.opcode, received.payload));
+                }
+                _ => {
+                    let (opcode, mut payload) = match self.fragments.take() {
+                        Some(fragments) => fragments,
+                        None => return self.fail(frame::CLOSE_PROTOCOL_ERROR),
+                    };
+                    if payload.len() + received.payload.len() > self.max_size {
+                        return self.fail(frame::CLOSE_TOO_BIG);
+                    }
+                    payload.extend(received.payload);
+                    if received.fin {
+                        return self.get_message(opcode, payload);
+                    }
+                    self.fragments = Some((opcode, payload));
+                }
+            }
+        }
+    }
+
+    /// Send a message to the client in one frame
+    pub fn send(&mut self, message: &Message) -> Result<(), Error> {
+        let sent = match message {
+            Message::Text(text) => Frame::new(true, frame::TEXT, text.as_bytes().to_vec()),
+            Message::Binary(bytes) => Frame::new(true, frame::BINARY, bytes.clone()),
+        };
+        self.write_open_frame(&sent)
+    }
+
+    /// Send a ping, its pong is skipped by `receive`
+    pub fn ping(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if payload.len() > frame::MAX_CONTROL_SIZE {
+            return Err(Error::Io(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Ping payload is larger than 125 bytes",
+            )));
+        }
+        self.write_open_frame(&Frame::new(true, frame::PING, payload.to_vec()))
+    }
+
+    /// Close the connection with a status code and reason and wait a moment for the client to
+    /// answer with its close frame
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), Error> {
+        if self.closed.is_some() {
+            return Ok(());
+        }
+        self.closed = Some((code, reason.to_string()));
+        self.write_frame(&Frame::close(code, reason))?;
+
+        let deadline = Some(Instant::now() + CLOSE_TIMEOUT);
+        loop {
+            match self.read_frame(deadline) {
+                Ok(ref received) if received.opcode == frame::CLOSE => return Ok(()),
+                Ok(_) => {}
+                Err(Error::Io(error)) => return Err(Error::Io(error)),
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+
+    /// Build a complete message, text has to be valid UTF-8
+    fn get_message(&mut self, opcode: u8, payload: Vec<u8>) -> Result<Message, Error> {
+        if opcode == frame::BINARY {
+            return Ok(Message::Binary(payload));
+        }
+        match String::from_utf8(payload) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => self.fail(frame::CLOSE_INVALID_DATA),
+        }
+    }
+
+    /// Close the connection because the client broke the protocol
+    fn fail<T>(&mut self, code: u16) -> Result<T, Error> {
+        if self.closed.is_none() {
+            self.closed = Some((code, String::new()));
+            self.write_frame(&Frame::close(code, ""))?;
+        }
+        Err(Error::Protocol(code))
+    }
+
+    /// Read until a frame is complete, the deadline has passed or shutdown is requested
+    fn read_frame(&mut self, deadline: Option<Instant>) -> Result<Frame, Error> {
+        let mut buffer = [0; CHUNK_SIZE];
+        loop {
+            match Frame::parse(&self.buffer, self.max_size, true) {
+                Ok(Some((received, used))) => {
+                    self.buffer.drain(..used);
+                    return Ok(received);
+                }
+                Ok(None) => {}
+                Err(code) => return self.fail(code),
+            }
+
+            if self.closed.is_none() && self.shutdown.load(Ordering::SeqCst) {
+                self.close(frame::CLOSE_GOING_AWAY, "Server is shutting down")?;
+                return Err(Error::Closed(
+                    frame::CLOSE_GOING_AWAY,
+                    "Server is shutting down".to_string(),
+                ));
+            }
+            let mut timeout = POLL_INTERVAL;
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(Error::Timeout);
+                }
+                timeout = timeout.min(deadline - now);
+            }
+
+            self.stream.set_read_timeout(Some(timeout))?;
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    let closed = (frame::CLOSE_ABNORMAL, String::new());
+                    self.closed.get_or_insert(closed.clone());
+                    return Err(Error::Closed(closed.0, closed.1));
+                }
+                Ok(read_size) => self.buffer.extend_from_slice(&buffer[..read_size]),
+                Err(ref error)
+                    if error.kind() == ErrorKind::WouldBlock
+                        || error.kind() == ErrorKind::TimedOut
+                        || error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(Error::Io(error)),
+            }
+        }
+    }
+
+    /// Write a frame unless the connection is closing
+    fn write_open_frame(&mut self, sent: &Frame) -> Result<(), Error> {
+        if let Some((code, ref reason)) = self.closed {
+            return Err(Error::Closed(code, reason.clone()));
+        }
+        self.write_frame(sent)
+    }
+
+    fn write_frame(&mut self, sent: &Frame) -> Result<(), Error> {
+        let mut buffer = Vec::with_capacity(sent.payload.len() + 10);
+        sent.write_to(&mut buffer, None);
+        self.stream.write_all(&buffer)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+pub trait ResponderInterface: Send + Sync {
+    /// A handler that serves the connection when the responder accepts an upgrade request,
+    /// state of the request is kept in the handler
+    fn matches(
+        &self,
+        request_message: &request::Message,
+        application: &Application,
+        socket: &Address,
+    ) -> Option<Box<dyn HandlerInterface + Send>>;
+}
+
+pub trait HandlerInterface {
+    /// Exchange messages with the client, the connection is closed when this returns
+    fn serve(
+        self: Box<Self>,
+        connection: &mut Connection,
+        application: &Application,
+        socket: &Address,
+    );
+}
+
+/// Answers WebSocket upgrade requests that a WebSocket responder matches and hands their
+/// connections over to it
+pub struct Responder {
+    responder: Box<dyn ResponderInterface>,
+}
+
+impl Responder {
+    pub fn new(responder: Box<dyn ResponderInterface>) -> Responder {
+        Responder { responder }
+    }
+
+    /// Calculate the Sec-WebSocket-Accept header field for the key of a client
+    /// ```rust
+    /// use milstian_internet_framework::response::tcp::websocket::Responder;
+    /// assert_eq!(
+    ///     Responder::get_accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+    ///     "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    /// );
+    /// ```
+    pub fn get_accept_key(key: &str) -> String {
+        let mut message = key.trim().as_bytes().to_vec();
+        message.extend_from_slice(GUID.as_bytes());
+        encode_base64(&sha1::digest(&message))
+    }
+
+    /// Whether a request asks to upgrade its connection to WebSocket
+    /// ```rust
+    /// use milstian_internet_framework::application_layer::http::request;
+    /// use milstian_internet_framework::response::tcp::websocket::Responder;
+    /// let request = request::Message::from_tcp_stream(
+    ///     b"GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n"
+    /// ).unwrap();
+    /// assert!(Responder::is_upgrade_request(&request));
+    /// ```
+    pub fn is_upgrade_request(request_message: &request::Message) -> bool {
+        match get_header(request_message, "Upgrade") {
+            Some(upgrade) => has_token(&upgrade, "websocket"),
+            None => false,
+        }
+    }
+
+    /// Get the status and header fields of a response refusing an invalid handshake
+    fn get_handshake_error(
+        request_message: &request::Message,
+    ) -> Option<(&'static str, HashMap<String, String>)> {
+        let mut headers = HashMap::new();
+        let version = get_header(request_message, "Sec-WebSocket-Version");
+        if version.as_ref().map(|version| version.trim()) != Some(VERSION) {
+            headers.insert("Sec-WebSocket-Version".to_string(), VERSION.to_string());
+            return Some(("426 Upgrade Required", headers));
+        }
+        let connection = get_header(request_message, "Connection").unwrap_or_default();
+        let key = get_header(request_message, "Sec-WebSocket-Key").unwrap_or_default();
+        if request_message.request_line.method != request::Method::Get
+            || request_message.request_line.protocol != request::Protocol::V1_1
+            || !has_token(&connection, "upgrade")
+            || !is_valid_key(key.trim())
+        {
+            return Some(("400 Bad Request", headers));
+        }
+        None
+    }
+}
+
+impl http::ResponderInterface for Responder {
+    fn matches(
+        &self,
+        request_message: &request::Message,
+        application: &Application,
+        socket: &Address,
+    ) -> Option<Box<dyn http::HandlerInterface>> {
+        if !Responder::is_upgrade_request(request_message) {
+            return None;
+        }
+        let handler = self
+            .responder
+            .matches(request_message, application, socket)?;
+        Some(Box::new(Handshake { handler }))
+    }
+}
+
+/// Answers the upgrade request of a connection a WebSocket responder accepted
+struct Handshake {
+    handler: Box<dyn HandlerInterface + Send>,
+}
+
+impl http::HandlerInterface for Handshake {
+    fn respond(
+        self: Box<Self>,
+        request_message: &request::Message,
+        _context: &http::Context,
+        _application: &Application,
+        _socket: &Address,
+    ) -> Result<response::Message, String> {
+        let protocol = request::Message::get_protocol_text(&request_message.request_line.protocol);
+        if let Some((status, headers)) = Responder::get_handshake_error(request_message) {
+            return Ok(response::Message::new(
+                protocol,
+                status.to_string(),
+                headers,
+                Vec::new(),
+            ));
+        }
+
+        let key = get_header(request_message, "Sec-WebSocket-Key").unwrap_or_default();
+        let mut headers: HashMap<String, String> = HashMap::new();
+        headers.insert("Upgrade".to_string(), "websocket".to_string());
+        headers.insert("Connection".to_string(), "Upgrade".to_string());
+        headers.insert(
+            "Sec-WebSocket-Accept".to_string(),
+            Responder::get_accept_key(&key),
+        );
+        let mut response = response::Message::new(
+            protocol,
+            "101 Switching Protocols".to_string(),
+            headers,
+            Vec::new(),
+        );
+        response.upgrade = Some(Box::new(Session {
+            handler: self.handler,
+        }));
+        Ok(response)
+    }
+}
+
+/// Serves an upgraded connection with the handler of a WebSocket responder
+struct Session {
+    handler: Box<dyn HandlerInterface + Send>,
+}
+
+impl Upgrade for Session {
+    fn serve(
+        self: Box<Self>,
+        stream: &mut dyn Stream,
+        received: Vec<u8>,
+        socket: &Address,
+        application: &Application,
+        shutdown: &AtomicBool,
+    ) {
+        let config = application.get_config();
+        let write_timeout = Dispatcher::get_timeout(config.server_write_timeout);
+        if let Err(error) = stream.get_socket().set_write_timeout(write_timeout) {
+            application.get_feedback().error(format!(
+                "Failed to set write time-out of WebSocket stream, error: {}",
+                error
+            ));
+            return;
+        }
+        application
+            .get_feedback()
+            .info(format!("WebSocket connection from {} was opened", socket));
+
+        let handler = self.handler;
+        let mut connection = Connection::new(stream, received, config.tcp_limit, shutdown);
+        let (code, reason) = match panic::catch_unwind(AssertUnwindSafe(|| {
+            handler.serve(&mut connection, application, socket)
+        })) {
+            Ok(()) => (frame::CLOSE_NORMAL, ""),
+            Err(payload) => {
+                application.get_feedback().error(format!(
+                    "WebSocket responder panicked, error: {}",
+                    get_panic_message(&payload)
+                ));
+                (frame::CLOSE_INTERNAL_ERROR, "Internal error")
+            }
+        };
+        if let Err(error) = connection.close(code, reason) {
+            application.get_feedback().error(format!(
+                "Failed to close WebSocket stream, error: {}",
+                error
+            ));
+        }
+        application
+            .get_feedback()
+            .info(format!("WebSocket connection from {} was closed", socket));
+    }
+}
+
+/// Find the value of a header field by case-insensitive name
+fn get_header(request_message: &request::Message, name: &str) -> Option<String> {
+    for (key, value) in request_message.headers.iter() {
+        if key.eq_ignore_ascii_case(name) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+/// Whether a comma-separated header field value contains a token
+fn has_token(value: &str, token: &str) -> bool {
+    value
+        .split(',')
+        .any(|option| option.trim().eq_ignore_ascii_case(token))
+}
+
+/// Whether a key is 16 bytes encoded with base64
+fn is_valid_key(key: &str) -> bool {
+    key.len() == 24
+        && key.ends_with("==")
+        && key[..22]
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'+' || byte == b'/')
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let value = (group[0] as u32) << 16
+            | (*group.get(1).unwrap_or(&0) as u32) << 8
+            | *group.get(2).unwrap_or(&0) as u32;
+        for index in 0..4 {
+            if index <= group.len() {
+                encoded.push(ALPHABET[(value >> (18 - index * 6)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::prelude::*;
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    use response::tcp::http::{
+        error, file_not_found, ResponderInterface as HttpResponderInterface,
+    };
+    use test_config;
+
+    /// Echoes messages until the client sends "bye"
+    struct Echo {}
+
+    impl ResponderInterface for Echo {
+        fn matches(
+            &self,
+            request_message: &request::Message,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<Box<dyn HandlerInterface + Send>> {
+            if request_message.request_line.request_uri_base == "/echo" {
+                return Some(Box::new(Echo {}));
+            }
+            None
+        }
+    }
+
+    impl HandlerInterface for Echo {
+        fn serve(
+            self: Box<Self>,
+            connection: &mut Connection,
+            _application: &Application,
+            _socket: &Address,
+        ) {
+            while let Ok(message) = connection.receive() {
+                if message == Message::Text("bye".to_string()) {
+                    return;
+                }
+                if message == Message::Text("panic".to_string()) {
+                    panic!("Asked to panic");
+                }
+                connection.send(&message).unwrap();
+            }
+        }
+    }
+
+    fn get_application() -> Application {
+        Application::new(test_config())
+    }
+
+    /// Serve one connection and return a client connected to it
+    fn connect(shutdown: Arc<AtomicBool>) -> (TcpStream, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, socket) = listener.accept().unwrap();
+            let responders: Vec<Box<dyn HttpResponderInterface>> = vec![
+                Box::new(Responder::new(Box::new(Echo {}))),
+                Box::new(file_not_found::Responder::new()),
+                Box::new(error::Responder::new()),
+            ];
+            Dispatcher::http(
+                stream,
+                socket.into(),
+                get_application(),
+                Arc::new(responders),
+                shutdown,
+            );
+        });
+        let client = TcpStream::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (client, server)
+    }
+
+    /// Read until the end of the response head
+    fn read_head(client: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        let mut byte = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            if client.read(&mut byte).unwrap() == 0 {
+                break;
+            }
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    fn handshake(client: &mut TcpStream) -> String {
+        client
+            .write_all(
+                b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        read_head(client)
+    }
+
+    fn send(client: &mut TcpStream, sent: &Frame) {
+        let mut buffer = Vec::new();
+        sent.write_to(&mut buffer, Some([0x12, 0x34, 0x56, 0x78]));
+        client.write_all(&buffer).unwrap();
+    }
+
+    fn receive(client: &mut TcpStream, buffer: &mut Vec<u8>) -> Frame {
+        loop {
+            if let Some((received, used)) = Frame::parse(buffer, 1 << 20, false).unwrap() {
+                buffer.drain(..used);
+                return received;
+            }
+            let mut chunk = [0; 1024];
+            let read_size = client.read(&mut chunk).unwrap();
+            assert!(read_size > 0, "Connection closed before a frame arrived");
+            buffer.extend_from_slice(&chunk[..read_size]);
+        }
+    }
+
+    #[test]
+    fn test_handshake() {
+        let (mut client, server) = connect(Arc::new(AtomicBool::new(false)));
+        let head = handshake(&mut client);
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Connection: Upgrade\r\n"));
+        assert!(head.contains("Upgrade: websocket\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(!head.contains("Content-Length"));
+        send(&mut client, &Frame::new(true, frame::TEXT, b"bye".to_vec()));
+        let mut buffer = Vec::new();
+        assert_eq!(
+            receive(&mut client, &mut buffer).get_close(),
+            Ok((frame::CLOSE_NORMAL, String::new()))
+        );
+        send(&mut client, &Frame::close(frame::CLOSE_NORMAL, ""));
+        server.join().unwrap();
+
+        // Unsupported versions are told the supported version
+        let (mut client, server) = connect(Arc::new(AtomicBool::new(false)));
+        client
+            .write_all(b"GET /echo HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n")
+            .unwrap();
+        let head = read_head(&mut client);
+        assert!(head.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(head.contains("Sec-WebSocket-Version: 13\r\n"));
+        client.shutdown(Shutdown::Write).unwrap();
+        server.join().unwrap();
+
+        let (mut client, server) = connect(Arc::new(AtomicBool::new(false)));
+        client
+            .write_all(b"GET /echo HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: short\r\nSec-WebSocket-Version: 13\r\n\r\n")
+            .unwrap();
+        assert!(read_head(&mut client).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        client.shutdown(Shutdown::Write).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_messages() {
+        let (mut client, server) = connect(Arc::new(AtomicBool::new(false)));
+        handshake(&mut client);
+        let mut buffer = Vec::new();
+
+        send(
+            &mut client,
+            &Frame::new(true, frame::TEXT, b"Hello".to_vec()),
+        );
+        assert_eq!(
+            receive(&mut client, &mut buffer),
+            Frame::new(true, frame::TEXT, b"Hello".to_vec())
+        );
+
+        // Fragments are joined and pings in between are answered
+        send(&mut client, &Frame::new(false, frame::BINARY, vec![1, 2]));
+        send(
+            &mut client,
+            &Frame::new(true, frame::PING, b"ping".to_vec()),
+        );
+        send(
+            &mut client,
+            &Frame::new(false, frame::CONTINUATION, vec![3]),
+        );
+        send(&mut client, &Frame::new(true, frame::CONTINUATION, vec![4]));
+        assert_eq!(
+            receive(&mut client, &mut buffer),
+            Frame::new(true, frame::PONG, b"ping".to_vec())
+        );
+        assert_eq!(
+            receive(&mut client, &mut buffer),
+            Frame::new(true, frame::BINARY, vec![1, 2, 3, 4])
+        );
+
+        // The close frame of the client is echoed
+        send(
+            &mut client,
+            &Frame::close(frame::CLOSE_GOING_AWAY, "Leaving"),
+        );
+        assert_eq!(
+            receive(&mut client, &mut buffer).get_close(),
+            Ok((frame::CLOSE_GOING_AWAY, String::new()))
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        // Frames from clients have to be masked
+        let (mut client, server) = connect(Arc::new(AtomicBool::new(false)));
+        handshake(&mut client);
+        let mut unmasked = Vec::new();
+        Frame::new(true, frame::TEXT, b"Hello".to_vec()).write_to(&mut unmasked, None);
+        client.write_all(&unmasked).unwrap();
+        let mut buffer = Vec::new();
+        assert_eq!(
+            receive(&mut client, &mut buffer).get_close(),
+            Ok((frame::CLOSE_PROTOCOL_ERROR, String::new()))
+        );
+        client.shutdown(Shutdown::Write).unwrap();
+        server.join().unwrap();
+
+        // Text has to be valid UTF-8
+        let (mut client, server) = connect(Arc::new(AtomicBool::new(false)));
+        handshake(&mut client);
+        send(
+            &mut client,
+            &Frame::new(true, frame::TEXT, vec![0xff, 0xfe]),
+        );
+        let mut buffer = Vec::new();
+        assert_eq!(
+            receive(&mut client, &mut buffer).get_close(),
+            Ok((frame::CLOSE_INVALID_DATA, String::new()))
+        );
+        client.shutdown(Shutdown::Write).unwrap();
+        server.join().unwrap();
+
+        // Messages are limited by the TCP limit
+        let (mut client, server) = connect(Arc::new(AtomicBool::new(false)));
+        handshake(&mut client);
+        send(
+            &mut client,
+            &Frame::new(false, frame::BINARY, vec![0; 1000]),
+        );
+        send(
+            &mut client,
+            &Frame::new(true, frame::CONTINUATION, vec![0; 1000]),
+        );
+        let mut buffer = Vec::new();
+        assert_eq!(
+            receive(&mut client, &mut buffer).get_close(),
+            Ok((frame::CLOSE_TOO_BIG, String::new()))
+        );
+        client.shutdown(Shutdown::Write).unwrap();
+        server.join().unwrap();
+
+        // A panicking responder closes the connection with an internal error
+        let (mut client, server) = connect(Arc::new(AtomicBool::new(false)));
+        handshake(&mut client);
+        send(
+            &mut client,
+            &Frame::new(true, frame::TEXT, b"panic".to_vec()),
+        );
+        let mut buffer = Vec::new();
+        assert_eq!(
+            receive(&mut client, &mut buffer).get_close(),
+            Ok((frame::CLOSE_INTERNAL_ERROR, "Internal error".to_string()))
+        );
+        client.shutdown(Shutdown::Write).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (mut client, server) = connect(Arc::clone(&shutdown));
+        handshake(&mut client);
+        shutdown.store(true, Ordering::SeqCst);
+        let mut buffer = Vec::new();
+        assert_eq!(
+            receive(&mut client, &mut buffer).get_close(),
+            Ok((
+                frame::CLOSE_GOING_AWAY,
+                "Server is shutting down".to_string()
+            ))
+        );
+        send(&mut client, &Frame::close(frame::CLOSE_GOING_AWAY, ""));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_encode_base64() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"f"), "Zg==");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert_eq!(encode_base64(b"foo"), "Zm9v");
+        assert_eq!(encode_base64(b"foobar"), "Zm9vYmFy");
+    }
+}
@@ -0,0 +1,62 @@
+//! # Typed state
+//! Values kept by their type, used for the state that an application shares with every
+//! responder and for the extensions that middleware attaches to a request.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// At most one value of every type, values are shared as `Arc<T>`
+#[derive(Clone, Default)]
+pub struct State {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl State {
+    pub fn new() -> State {
+        State::default()
+    }
+
+    /// Add a value, replaces the value of the same type
+    /// ```rust
+    /// use milstian_internet_framework::state::State;
+    /// struct Pool {
+    ///     size: usize,
+    /// }
+    /// let mut state = State::new();
+    /// state.insert(Pool { size: 4 });
+    /// assert_eq!(state.get::<Pool>().unwrap().size, 4);
+    /// assert!(state.get::<String>().is_none());
+    /// ```
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let value = Arc::clone(self.values.get(&TypeId::of::<T>())?);
+        value.downcast().ok()
+    }
+
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<Arc<T>> {
+        self.values.remove(&TypeId::of::<T>())?.downcast().ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "State {{ {} values }}", self.values.len())
+    }
+}
@@ -1,9 +1,10 @@
 //! # Handles the workers
 
-// TODO Add tests to this file
-
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -11,77 +12,141 @@ use std::thread;
 
 use Application;
 
-pub struct Pool<'a> {
-    application: &'a Application,
+/// How often a shutdown with a time-out checks whether workers have finished
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct Pool {
+    application: Application,
+    queue_depth: Arc<AtomicUsize>,
+    queue_limit: usize,
+    receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
     workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
+    sender: Option<mpsc::SyncSender<Message>>,
 }
 
-impl<'a> Pool<'a> {
-    /// Create a job from a closure and send for execution
-    pub fn execute<F>(&self, f: F)
-    where
-        F: FnOnce() + Send + 'static,
-    {
-        // Place job inside a Box inside a message
-        let message = Message::NewJob(Box::new(f));
+impl Pool {
+    /// Get a submitter that queues jobs for the workers of this pool without borrowing it
+    pub fn get_submitter(&self) -> Submitter {
+        Submitter {
+            queue_depth: Arc::clone(&self.queue_depth),
+            queue_limit: self.queue_limit,
+            sender: self
+                .sender
+                .clone()
+                .expect("Pool has a sender until it is shut down"),
+        }
+    }
 
-        &self
-            .application
-            .get_feedback()
-            .info("Sending job down the channel".to_string());
+    /// Replace workers whose threads have died so the pool keeps its size
+    pub fn respawn(&mut self) {
+        for worker in &mut self.workers {
+            let finished = match worker.thread {
+                Some(ref thread) => thread.is_finished(),
+                None => true,
+            };
+            if !finished {
+                continue;
+            }
+            if let Some(thread) = worker.thread.take() {
+                if let Err(payload) = thread.join() {
+                    self.application.get_feedback().error(format!(
+                        "Worker {} died, error: {}",
+                        worker.id,
+                        get_panic_message(&payload)
+                    ));
+                }
+            }
+            self.application
+                .get_feedback()
+                .info(format!("Starting replacement for worker {}", worker.id));
+            *worker = Worker::new(
+                &self.application,
+                worker.id,
+                Arc::clone(&self.receiver),
+                Arc::clone(&self.queue_depth),
+            );
+        }
+    }
 
-        // Send a NewJob Message down the channel
-        // If it fails thread? will crash deliberately
-        let sender_clone = self.sender.clone();
-        thread::spawn(move || {
-            sender_clone
-                .send(message)
-                .expect("Failed to send job down the channel");
-        });
+    /// Counter of the number of jobs waiting for a worker
+    pub fn get_queue_depth_counter(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.queue_depth)
     }
 
-    /// Create a new mutex channel with specified number of receivers
-    pub fn new(application: &'a Application, size: usize) -> Pool {
+    /// Create a new mutex channel with specified number of receivers and a queue that
+    /// holds at most `queue_limit` jobs
+    pub fn new(application: &Application, size: usize, queue_limit: usize) -> Pool {
         assert!(size > 0);
+        assert!(queue_limit > 0);
         application
             .get_feedback()
             .info(format!("Starting {} new workers", size));
 
-        let (sender, receiver) = mpsc::channel();
+        let (sender, receiver) = mpsc::sync_channel(queue_limit);
 
         let receiver = Arc::new(Mutex::new(receiver));
+        let queue_depth = Arc::new(AtomicUsize::new(0));
 
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(&application, id, Arc::clone(&receiver)));
+            workers.push(Worker::new(
+                application,
+                id,
+                Arc::clone(&receiver),
+                Arc::clone(&queue_depth),
+            ));
         }
 
         Pool {
-            application,
-            sender,
+            application: application.clone(),
+            queue_depth,
+            queue_limit,
+            receiver,
+            sender: Some(sender),
             workers,
         }
     }
 }
 
-impl<'a> Drop for Pool<'a> {
-    fn drop(&mut self) {
+impl Pool {
+    /// Let workers finish the queued jobs and wait at most `timeout` for them to stop,
+    /// workers still busy after that are left detached
+    pub fn shutdown(mut self, timeout: Duration) {
+        self.terminate(Some(Instant::now() + timeout));
+    }
+
+    /// Tell every worker to terminate and join them, optionally before a deadline
+    fn terminate(&mut self, deadline: Option<Instant>) {
+        let sender = match self.sender.take() {
+            Some(sender) => sender,
+            None => return,
+        };
+
         self.application
             .get_feedback()
             .info("Sending terminate message to all workers.".to_string());
 
-        // Identical number of terminate messages and workers assure
-        // all workers will receive the message
-        for _ in &mut self.workers {
-            if let Err(error) = self.sender.send(Message::Terminate) {
+        // Identical number of terminate messages and running workers assure all workers will
+        // receive the message after the queued jobs, submitters may still keep the channel open
+        let running = self
+            .workers
+            .iter()
+            .filter(|worker| match worker.thread {
+                Some(ref thread) => !thread.is_finished(),
+                None => false,
+            })
+            .count();
+        for _ in 0..running {
+            if let Err(error) = Pool::send_terminate(&sender, deadline) {
                 self.application.get_feedback().error(format!(
-                    "Failed to send a termination message, error: {:?}",
+                    "Failed to send a termination message, error: {}",
                     error
                 ));
+                break;
             }
         }
+        drop(sender);
 
         self.application
             .get_feedback()
@@ -94,6 +159,18 @@ impl<'a> Drop for Pool<'a> {
                 .info(format!("Shutting down worker {}", worker.id));
 
             if let Some(thread) = worker.thread.take() {
+                if let Some(deadline) = deadline {
+                    while !thread.is_finished() && Instant::now() < deadline {
+                        thread::sleep(JOIN_POLL_INTERVAL);
+                    }
+                    if !thread.is_finished() {
+                        self.application.get_feedback().error(format!(
+                            "Worker {} did not finish its job before shutdown time-out",
+                            worker.id
+                        ));
+                        continue;
+                    }
+                }
                 if let Err(error) = thread.join() {
                     self.application.get_feedback().info(format!(
                         "Failed to join thread {:?}, error: {:?}",
@@ -110,6 +187,102 @@ impl<'a> Drop for Pool<'a> {
     }
 }
 
+impl Pool {
+    /// Send a terminate message, waiting for room in the queue at most until the deadline
+    fn send_terminate(
+        sender: &mpsc::SyncSender<Message>,
+        deadline: Option<Instant>,
+    ) -> Result<(), String> {
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => {
+                return sender
+                    .send(Message::Terminate)
+                    .map_err(|_| "Channel was closed".to_string())
+            }
+        };
+        loop {
+            match sender.try_send(Message::Terminate) {
+                Ok(()) => return Ok(()),
+                Err(mpsc::TrySendError::Full(_)) if Instant::now() < deadline => {
+                    thread::sleep(JOIN_POLL_INTERVAL);
+                }
+                Err(mpsc::TrySendError::Full(_)) => {
+                    return Err("Queue was full until shutdown time-out".to_string())
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => {
+                    return Err("Channel was closed".to_string())
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.terminate(None);
+    }
+}
+
+/// Queues jobs for the workers of a pool from any thread
+#[derive(Clone)]
+pub struct Submitter {
+    queue_depth: Arc<AtomicUsize>,
+    queue_limit: usize,
+    sender: mpsc::SyncSender<Message>,
+}
+
+impl Submitter {
+    /// Create a job from a closure and send for execution, blocks while the queue is full
+    /// and gives the job back if the pool has shut down
+    pub fn execute<F>(&self, f: F) -> Result<(), Job>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.queue_depth.fetch_add(1, Ordering::SeqCst);
+        match self.sender.send(Message::NewJob(Box::new(f))) {
+            Ok(()) => Ok(()),
+            Err(mpsc::SendError(message)) => {
+                self.queue_depth.fetch_sub(1, Ordering::SeqCst);
+                match message {
+                    Message::NewJob(job) => Err(job),
+                    Message::Terminate => unreachable!("Submitters only send jobs"),
+                }
+            }
+        }
+    }
+
+    /// Whether the queue is full, executing a job would then block until a worker is free
+    pub fn is_full(&self) -> bool {
+        self.queue_depth.load(Ordering::SeqCst) >= self.queue_limit
+    }
+
+    /// Create a job from a closure and send for execution, the job is given back if the
+    /// queue is full
+    pub fn try_execute<F>(&self, f: F) -> Result<(), Job>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_send(Box::new(f))
+    }
+
+    /// Send a job for execution, the job is given back if the queue is full
+    pub fn try_send(&self, job: Job) -> Result<(), Job> {
+        self.queue_depth.fetch_add(1, Ordering::SeqCst);
+        match self.sender.try_send(Message::NewJob(job)) {
+            Ok(()) => Ok(()),
+            Err(mpsc::TrySendError::Full(message))
+            | Err(mpsc::TrySendError::Disconnected(message)) => {
+                self.queue_depth.fetch_sub(1, Ordering::SeqCst);
+                match message {
+                    Message::NewJob(job) => Err(job),
+                    Message::Terminate => unreachable!("Submitters only send jobs"),
+                }
+            }
+        }
+    }
+}
+
 pub struct Worker {
     id: usize,
     thread: Option<thread::JoinHandle<()>>,
@@ -121,19 +294,33 @@ impl<'a> Worker {
         application: &'a Application,
         id: usize,
         receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+        queue_depth: Arc<AtomicUsize>,
     ) -> Worker {
         let application_clone = application.clone();
 
         let thread = thread::spawn(move || {
             loop {
-                let message = receiver.lock().unwrap().recv().unwrap();
+                // A poisoned lock only means another worker panicked while waiting
+                let message = match receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(poisoned) => poisoned.into_inner().recv(),
+                };
                 match message {
-                    Message::NewJob(job) => {
+                    Ok(Message::NewJob(job)) => {
+                        queue_depth.fetch_sub(1, Ordering::SeqCst);
                         let start = SystemTime::now();
                         application_clone
                             .get_feedback()
                             .info(format!("Worker {} started executing job from channel", id));
-                        job.call_box();
+                        if let Err(payload) =
+                            panic::catch_unwind(AssertUnwindSafe(|| job.call_box()))
+                        {
+                            application_clone.get_feedback().error(format!(
+                                "Worker {} caught a panic in job, error: {}",
+                                id,
+                                get_panic_message(&payload)
+                            ));
+                        }
 
                         // TODO Add time-out for process?
 
@@ -148,7 +335,7 @@ impl<'a> Worker {
                             id, elapsed_secs, elapsed_millis
                         ));
                     }
-                    Message::Terminate => {
+                    Ok(Message::Terminate) | Err(_) => {
                         application_clone
                             .get_feedback()
                             .info(format!("Worker {} was told to terminate", id));
@@ -159,25 +346,175 @@ impl<'a> Worker {
         });
 
         Worker {
-            id: id,
+            id,
             thread: Some(thread),
         }
     }
 }
 
+/// Get the message a panic was started with
+pub fn get_panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+    "Unknown panic".to_string()
+}
+
 enum Message {
     NewJob(Job),
     Terminate,
 }
 
-trait FnBox {
+pub trait FnBox {
     fn call_box(self: Box<Self>);
 }
 
-type Job = Box<FnBox + Send + 'static>;
+pub type Job = Box<dyn FnBox + Send + 'static>;
 
 impl<F: FnOnce()> FnBox for F {
     fn call_box(self: Box<F>) {
         (*self)()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_config;
+
+    #[test]
+    fn queue_depth() {
+        let application = Application::new(test_config());
+        let pool = Pool::new(&application, 1, 1);
+        let queue_depth = pool.get_queue_depth_counter();
+        let submitter = pool.get_submitter();
+
+        // Occupy the only worker until the test releases it
+        let (started_sender, started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        assert!(submitter
+            .execute(move || {
+                started_sender.send(()).unwrap();
+                released.recv().unwrap();
+            })
+            .is_ok());
+        started.recv().unwrap();
+        assert_eq!(queue_depth.load(Ordering::SeqCst), 0);
+        assert!(!submitter.is_full());
+
+        let (finished_sender, finished) = mpsc::channel();
+        assert!(submitter
+            .execute(move || {
+                finished_sender.send(()).unwrap();
+            })
+            .is_ok());
+        assert_eq!(queue_depth.load(Ordering::SeqCst), 1);
+        assert!(submitter.is_full());
+
+        release.send(()).unwrap();
+        finished.recv().unwrap();
+        drop(submitter);
+        pool.shutdown(Duration::from_secs(1));
+        assert_eq!(queue_depth.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn panic_isolation() {
+        let application = Application::new(test_config());
+        let mut pool = Pool::new(&application, 1, 4);
+        let submitter = pool.get_submitter();
+
+        // The only worker survives a panicking job
+        assert!(submitter.execute(|| panic!("Job failed")).is_ok());
+        let (sender, receiver) = mpsc::channel();
+        let sender_clone = sender.clone();
+        assert!(submitter
+            .execute(move || sender_clone.send(1).unwrap())
+            .is_ok());
+        assert_eq!(receiver.recv().unwrap(), 1);
+
+        // A worker thread that died is replaced before the next job
+        let dead = thread::spawn(|| panic!("Worker failed"));
+        while !dead.is_finished() {
+            thread::sleep(JOIN_POLL_INTERVAL);
+        }
+        let alive = pool.workers[0].thread.replace(dead);
+        pool.respawn();
+        assert!(submitter.execute(move || sender.send(2).unwrap()).is_ok());
+        assert_eq!(receiver.recv().unwrap(), 2);
+        assert_eq!(pool.workers.len(), 1);
+        assert!(!pool.workers[0].thread.as_ref().unwrap().is_finished());
+
+        drop(submitter);
+        pool.shutdown(Duration::from_secs(1));
+        alive.unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn submitter() {
+        let application = Application::new(test_config());
+        let pool = Pool::new(&application, 1, 1);
+        let submitter = pool.get_submitter();
+
+        let (started_sender, started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        assert!(submitter
+            .try_execute(move || {
+                started_sender.send(()).unwrap();
+                released.recv().unwrap();
+            })
+            .is_ok());
+        started.recv().unwrap();
+
+        // The second job fills the queue and the third one is given back
+        let (sender, receiver) = mpsc::channel();
+        let sender_clone = sender.clone();
+        assert!(submitter
+            .try_execute(move || sender_clone.send(1).unwrap())
+            .is_ok());
+        assert!(submitter.is_full());
+        let job = match submitter.try_execute(move || sender.send(2).unwrap()) {
+            Err(job) => job,
+            Ok(()) => panic!("Expected full queue to give back the job"),
+        };
+        assert!(submitter.is_full());
+
+        // Executing waits for room in the queue instead of giving the job back
+        let blocked = submitter.clone();
+        let (blocked_sender, blocked_receiver) = mpsc::channel();
+        let executing = thread::spawn(move || {
+            blocked
+                .execute(move || blocked_sender.send(3).unwrap())
+                .is_ok()
+        });
+        release.send(()).unwrap();
+        assert_eq!(receiver.recv().unwrap(), 1);
+        assert!(executing.join().unwrap());
+        assert_eq!(blocked_receiver.recv().unwrap(), 3);
+        assert!(submitter.try_send(job).is_ok());
+        assert_eq!(receiver.recv().unwrap(), 2);
+        drop(submitter);
+        pool.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn drop_with_submitter() {
+        let application = Application::new(test_config());
+        let pool = Pool::new(&application, 2, 1);
+        let submitter = pool.get_submitter();
+        let submitter_clone = submitter.clone();
+
+        // A submitter that outlives the pool does not keep its workers waiting for jobs
+        let (dropped_sender, dropped) = mpsc::channel();
+        thread::spawn(move || {
+            drop(pool);
+            dropped_sender.send(()).unwrap();
+        });
+        assert!(dropped.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert!(submitter_clone.try_execute(|| {}).is_err());
+        drop(submitter);
+    }
+}
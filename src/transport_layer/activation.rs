@@ -0,0 +1,247 @@
+//! # Socket activation
+//! Listening sockets can be passed to a process by the `LISTEN_FDS` protocol of systemd: they
+//! start at file descriptor 3, `LISTEN_FDS` tells how many there are and `LISTEN_PID` which
+//! process they are meant for. A restart passes the sockets of a server on the same way.
+
+use std::env;
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::mem;
+use std::net::TcpListener;
+use std::os::raw::c_char;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::os::unix::process::CommandExt;
+use std::process::{self, Child, Command};
+use std::ptr;
+
+use libc;
+
+use super::Acceptor;
+
+/// First file descriptor of the passed sockets, after standard input, output and error
+const FIRST_FD: RawFd = 3;
+
+/// Take the listening sockets passed to this process, the variables of the protocol are
+/// removed from the environment so processes started by this one do not take them as well.
+/// Changing the environment is not safe while other threads read it, so this has to run
+/// before the process starts any other thread.
+pub fn take_listeners() -> io::Result<Vec<Acceptor>> {
+    let (pid, fds) = match (env::var("LISTEN_PID"), env::var("LISTEN_FDS")) {
+        (Ok(pid), Ok(fds)) => (pid, fds),
+        _ => return Ok(Vec::new()),
+    };
+    // Sockets meant for another process are left to it
+    if pid.parse::<u32>() != Ok(process::id()) {
+        return Ok(Vec::new());
+    }
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let fds: RawFd = match fds.parse() {
+        Ok(fds) if fds >= 0 => fds,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid number of passed sockets: {}", fds),
+            ))
+        }
+    };
+
+    let mut listeners = Vec::with_capacity(fds as usize);
+    for fd in FIRST_FD..FIRST_FD + fds {
+        listeners.push(take_listener(fd)?);
+    }
+    Ok(listeners)
+}
+
+/// Take ownership of a passed socket after checking that it is a listening stream socket
+fn take_listener(fd: RawFd) -> io::Result<Acceptor> {
+    let mut address: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut length = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let mut listening: libc::c_int = 0;
+    let mut option_length = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    unsafe {
+        if libc::getsockname(
+            fd,
+            &mut address as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut length,
+        ) < 0
+            || libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_ACCEPTCONN,
+                &mut listening as *mut libc::c_int as *mut libc::c_void,
+                &mut option_length,
+            ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if listening == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Passed file descriptor {} is not a listening socket", fd),
+        ));
+    }
+
+    // Passed sockets should not leak into processes started by this one
+    unsafe {
+        if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    match libc::c_int::from(address.ss_family) {
+        libc::AF_INET | libc::AF_INET6 => {
+            Ok(Acceptor::Tcp(unsafe { TcpListener::from_raw_fd(fd) }))
+        }
+        // The socket file belongs to whoever created the socket
+        libc::AF_UNIX => Ok(Acceptor::Unix(
+            unsafe { UnixListener::from_raw_fd(fd) },
+            None,
+        )),
+        family => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Passed file descriptor {} has unsupported address family {}",
+                fd, family
+            ),
+        )),
+    }
+}
+
+/// Start a command that takes the listening sockets by the `LISTEN_FDS` protocol. The program
+/// is executed by its path with the environment of this process and the changes of the command.
+pub fn spawn(mut command: Command, fds: &[RawFd]) -> io::Result<Child> {
+    let mut exec = Exec::new(&command, fds)?;
+    // The process ID of the child is only known after forking, so the child executes the
+    // program itself with an environment that was prepared before forking
+    unsafe {
+        command.pre_exec(move || exec.run());
+    }
+    command.spawn()
+}
+
+/// Everything the forked child needs to execute the program without allocating
+struct Exec {
+    arguments: Vec<CString>,
+    argument_pointers: Vec<*const c_char>,
+    environment: Vec<CString>,
+    environment_pointers: Vec<*const c_char>,
+    fds: Vec<RawFd>,
+    /// `LISTEN_PID=` followed by room for the digits of the process ID
+    pid: Vec<u8>,
+    program: CString,
+    /// Duplicates of the sockets above the range they are moved to
+    temporary_fds: Vec<RawFd>,
+}
+
+// The pointers point into the strings owned by the struct
+unsafe impl Send for Exec {}
+unsafe impl Sync for Exec {}
+
+impl Exec {
+    fn new(command: &Command, fds: &[RawFd]) -> io::Result<Exec> {
+        let program = get_c_string(command.get_program())?;
+        let mut arguments = vec![program.clone()];
+        for argument in command.get_args() {
+            arguments.push(get_c_string(argument)?);
+        }
+
+        let mut variables: Vec<(OsString, OsString)> = env::vars_os()
+            .filter(|(key, _)| !key.to_string_lossy().starts_with("LISTEN_"))
+            .collect();
+        for (key, value) in command.get_envs() {
+            variables.retain(|(existing, _)| existing != key);
+            if let Some(value) = value {
+                variables.push((key.to_os_string(), value.to_os_string()));
+            }
+        }
+        let mut environment = Vec::with_capacity(variables.len() + 1);
+        for (key, value) in variables {
+            let mut variable = key;
+            variable.push("=");
+            variable.push(value);
+            environment.push(get_c_string(&variable)?);
+        }
+        let count = format!("LISTEN_FDS={}", fds.len());
+        environment.push(get_c_string(OsStr::new(&count))?);
+
+        let mut pid = b"LISTEN_PID=".to_vec();
+        pid.resize(32, 0);
+        let mut exec = Exec {
+            arguments,
+            argument_pointers: Vec::new(),
+            environment,
+            environment_pointers: Vec::new(),
+            fds: fds.to_vec(),
+            pid,
+            program,
+            temporary_fds: vec![0; fds.len()],
+        };
+        exec.argument_pointers = exec.arguments.iter().map(|a| a.as_ptr()).collect();
+        exec.argument_pointers.push(ptr::null());
+        exec.environment_pointers = exec.environment.iter().map(|v| v.as_ptr()).collect();
+        exec.environment_pointers
+            .push(exec.pid.as_ptr() as *const c_char);
+        exec.environment_pointers.push(ptr::null());
+        Ok(exec)
+    }
+
+    /// Move the sockets to the start of the range, set the process ID and execute the program,
+    /// only returns on failure
+    fn run(&mut self) -> io::Result<()> {
+        let count = self.fds.len() as RawFd;
+        unsafe {
+            // Duplicate first so sockets that are already in the range are not overwritten
+            for (index, fd) in self.fds.iter().enumerate() {
+                let temporary = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, FIRST_FD + count);
+                if temporary < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                self.temporary_fds[index] = temporary;
+            }
+            // Duplicated file descriptors stay open in the executed program
+            for (index, fd) in self.temporary_fds.iter().enumerate() {
+                if libc::dup2(*fd, FIRST_FD + index as RawFd) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            // Digits of the process ID in reverse order
+            let mut pid = libc::getpid() as u32;
+            let mut digits = [0u8; 10];
+            let mut length = 0;
+            loop {
+                digits[length] = b'0' + (pid % 10) as u8;
+                length += 1;
+                pid /= 10;
+                if pid == 0 {
+                    break;
+                }
+            }
+            let start = b"LISTEN_PID=".len();
+            for index in 0..length {
+                self.pid[start + index] = digits[length - 1 - index];
+            }
+
+            libc::execve(
+                self.program.as_ptr(),
+                self.argument_pointers.as_ptr(),
+                self.environment_pointers.as_ptr(),
+            );
+        }
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn get_c_string(value: &OsStr) -> io::Result<CString> {
+    CString::new(value.as_bytes()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Command contains a null character",
+        )
+    })
+}
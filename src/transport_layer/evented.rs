@@ -0,0 +1,728 @@
+//! # Event-driven transport
+//! Multiplexes connections over a few event loops with non-blocking sockets, workers are only
+//! occupied while responders build a response for a framed request. Response bodies are read
+//! by the event loops, so responders should not return streams that block for long.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::net::{Shutdown, SocketAddr, TcpListener as StdTcpListener};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token, Waker};
+
+use application_layer::http::response;
+use application_layer::http::response::CHUNK_SIZE;
+use metrics::Timeout;
+use response::tcp::http::reader::{Framer, Request};
+use response::tcp::http::Responders;
+use response::tcp::Dispatcher;
+use thread::{Job, Pool, Submitter};
+use transport_layer::{Address, Error, Handle};
+use {Application, OverloadPolicy};
+
+/// How long an event loop waits for events before it checks deadlines and shutdown
+const TICK: Duration = Duration::from_millis(100);
+
+/// Token of the listener in the accepting loop
+const LISTENER: Token = Token(0);
+
+/// Token of the waker in the event loops, connections get the following tokens
+const WAKER: Token = Token(0);
+
+/// Accept connections and spread them over `loops` event loops until shutdown, then let the
+/// event loops finish their in-flight requests
+pub fn serve(
+    application: &Application,
+    handle: &Handle,
+    listener: &StdTcpListener,
+    pool: &mut Pool,
+    responders: &Responders,
+    loops: usize,
+) -> Result<(), Error> {
+    let config = application.get_config();
+    let mut poll = Poll::new().map_err(Error::EventLoop)?;
+    listener.set_nonblocking(true).map_err(Error::EventLoop)?;
+    let mut listener = TcpListener::from_std(listener.try_clone().map_err(Error::EventLoop)?);
+    poll.registry()
+        .register(&mut listener, LISTENER, Interest::READABLE)
+        .map_err(Error::EventLoop)?;
+
+    let mut event_loops = Vec::with_capacity(loops.max(1));
+    for id in 0..loops.max(1) {
+        event_loops.push(
+            EventLoop::spawn(
+                id,
+                application,
+                Arc::clone(&handle.shutdown),
+                pool.get_submitter(),
+                Arc::clone(responders),
+            )
+            .map_err(Error::EventLoop)?,
+        );
+    }
+    application
+        .get_feedback()
+        .info(format!("Started {} event loops", event_loops.len()));
+
+    let mut events = Events::with_capacity(128);
+    let mut next = 0;
+    while !handle.is_shutdown() {
+        if let Err(error) = poll.poll(&mut events, Some(TICK)) {
+            if error.kind() != ErrorKind::Interrupted {
+                application
+                    .get_feedback()
+                    .error(format!("Failed to poll listener, error: {}", error));
+            }
+            continue;
+        }
+        pool.respawn();
+        loop {
+            match listener.accept() {
+                Ok((stream, socket)) => {
+                    if handle.is_shutdown() {
+                        break;
+                    }
+                    application
+                        .get_feedback()
+                        .info(format!("Received new TCP stream from {}", socket));
+                    event_loops[next].accept(stream, socket);
+                    next = (next + 1) % event_loops.len();
+                }
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => {
+                    application.get_feedback().error(format!(
+                        "Failed to accept a incoming stream, error: {}",
+                        error
+                    ));
+                    break;
+                }
+            }
+        }
+    }
+
+    // Event loops stop by themselves when their connections are done or time is up
+    drop(listener);
+    for event_loop in event_loops {
+        event_loop.wake();
+        if event_loop.thread.join().is_err() {
+            application
+                .get_feedback()
+                .error("Event loop panicked before shutdown".to_string());
+        }
+    }
+    application.get_feedback().info(format!(
+        "Event loops stopped within shutdown time-out of {}s",
+        config.server_shutdown_timeout
+    ));
+    Ok(())
+}
+
+/// Messages from the accepting loop and the workers to an event loop
+enum Event {
+    Accepted(TcpStream, SocketAddr),
+    Responded(usize, (response::Message, bool, bool)),
+}
+
+/// The accepting side of an event loop
+struct EventLoopHandle {
+    sender: mpsc::Sender<Event>,
+    thread: thread::JoinHandle<()>,
+    waker: Arc<Waker>,
+}
+
+impl EventLoopHandle {
+    fn accept(&self, stream: TcpStream, socket: SocketAddr) {
+        if self.sender.send(Event::Accepted(stream, socket)).is_ok() {
+            self.wake();
+        }
+    }
+
+    fn wake(&self) {
+        let _ = self.waker.wake();
+    }
+}
+
+/// What a connection is waiting for
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// A persistent connection waits for its next request
+    Idle,
+    /// A request is being received, the time-out is for its head or its body
+    Reading(Timeout),
+    /// A worker is building the response
+    Processing,
+    /// The response is being sent
+    Writing,
+}
+
+struct Connection {
+    body: Option<Box<dyn Read + Send>>,
+    chunked: bool,
+    deadline: Option<Instant>,
+    framer: Framer,
+    keep_alive: bool,
+    output: Vec<u8>,
+    pending: Option<Job>,
+    request_deadline: Option<Instant>,
+    served: usize,
+    socket: Address,
+    state: State,
+    stream: TcpStream,
+    written: usize,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, socket: SocketAddr) -> Connection {
+        Connection {
+            body: None,
+            chunked: false,
+            deadline: None,
+            framer: Framer::new(),
+            keep_alive: false,
+            output: Vec::new(),
+            pending: None,
+            request_deadline: None,
+            served: 0,
+            socket: Address::Tcp(socket),
+            state: State::Idle,
+            stream,
+            written: 0,
+        }
+    }
+
+    /// Enter a state that has to be left within a number of seconds, 0 means no time-out
+    fn set_state(&mut self, state: State, seconds: u64) {
+        self.state = state;
+        self.deadline = Dispatcher::get_timeout(seconds).map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Read the next bytes that have arrived into the framer, returns number of read bytes
+    fn read_input(&mut self) -> io::Result<usize> {
+        let mut temp_buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut temp_buffer) {
+                Ok(read_size) => {
+                    self.framer.push(&temp_buffer[..read_size]);
+                    return Ok(read_size);
+                }
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Read the next part of the body into the output, returns false when nothing is left
+    fn fill_output(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; CHUNK_SIZE];
+        let read_size = match self.body {
+            Some(ref mut body) => loop {
+                match body.read(&mut chunk) {
+                    Ok(read_size) => break read_size,
+                    Err(ref error) if error.kind() == ErrorKind::Interrupted => {}
+                    Err(error) => return Err(error),
+                }
+            },
+            None => return Ok(false),
+        };
+        if read_size == 0 {
+            self.body = None;
+            if self.chunked {
+                self.output.extend_from_slice(b"0\r\n\r\n");
+            }
+            return Ok(!self.output.is_empty());
+        }
+        if self.chunked {
+            self.output
+                .extend_from_slice(format!("{:X}\r\n", read_size).as_bytes());
+            self.output.extend_from_slice(&chunk[..read_size]);
+            self.output.extend_from_slice(b"\r\n");
+        } else {
+            self.output.extend_from_slice(&chunk[..read_size]);
+        }
+        Ok(true)
+    }
+
+    /// Write as much of the response as the socket accepts, returns whether it is complete
+    fn write_output(&mut self, write_timeout: u64) -> io::Result<bool> {
+        loop {
+            if self.written == self.output.len() {
+                self.output.clear();
+                self.written = 0;
+                if !self.fill_output()? {
+                    return Ok(true);
+                }
+            }
+            match self.stream.write(&self.output[self.written..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        ErrorKind::WriteZero,
+                        "Stream accepted no bytes",
+                    ))
+                }
+                Ok(written) => {
+                    self.written += written;
+                    self.set_state(State::Writing, write_timeout);
+                }
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Reads, frames and writes the connections it was given
+struct EventLoop {
+    application: Application,
+    connections: HashMap<usize, Connection>,
+    id: usize,
+    next_token: usize,
+    poll: Poll,
+    receiver: mpsc::Receiver<Event>,
+    responders: Responders,
+    sender: mpsc::Sender<Event>,
+    shutdown: Arc<AtomicBool>,
+    submitter: Submitter,
+    waker: Arc<Waker>,
+}
+
+impl EventLoop {
+    fn spawn(
+        id: usize,
+        application: &Application,
+        shutdown: Arc<AtomicBool>,
+        submitter: Submitter,
+        responders: Responders,
+    ) -> io::Result<EventLoopHandle> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let (sender, receiver) = mpsc::channel();
+        let event_loop = EventLoop {
+            application: application.clone(),
+            connections: HashMap::new(),
+            id,
+            next_token: WAKER.0 + 1,
+            poll,
+            receiver,
+            responders,
+            sender: sender.clone(),
+            shutdown,
+            submitter,
+            waker: Arc::clone(&waker),
+        };
+        let thread = thread::spawn(move || event_loop.run());
+        Ok(EventLoopHandle {
+            sender,
+            thread,
+            waker,
+        })
+    }
+
+    fn run(mut self) {
+        let mut events = Events::with_capacity(1024);
+        let mut swept = Instant::now();
+        let mut stopping: Option<Instant> = None;
+        loop {
+            if let Err(error) = self.poll.poll(&mut events, Some(TICK)) {
+                if error.kind() != ErrorKind::Interrupted {
+                    self.application.get_feedback().error(format!(
+                        "Event loop {} failed to poll, error: {}",
+                        self.id, error
+                    ));
+                    break;
+                }
+            }
+            for event in events.iter() {
+                match event.token() {
+                    WAKER => self.receive(),
+                    Token(token) => self.with_connection(token, EventLoop::ready),
+                }
+            }
+            self.retry_pending();
+
+            let now = Instant::now();
+            if now.duration_since(swept) >= TICK {
+                self.sweep(now);
+                swept = now;
+            }
+
+            if self.shutdown.load(Ordering::SeqCst) {
+                let timeout = self.application.get_config().server_shutdown_timeout;
+                let deadline = *stopping.get_or_insert(now + Duration::from_secs(timeout));
+                self.connections
+                    .retain(|_, connection| connection.state != State::Idle);
+                if self.connections.is_empty() {
+                    break;
+                }
+                if now >= deadline {
+                    self.application.get_feedback().error(format!(
+                        "Event loop {} closed {} connections at shutdown time-out",
+                        self.id,
+                        self.connections.len()
+                    ));
+                    break;
+                }
+            }
+        }
+        self.application
+            .get_feedback()
+            .info(format!("Event loop {} stopped", self.id));
+    }
+
+    /// Take a connection out of the loop while it is handled, it is put back if it should persist
+    fn with_connection<F>(&mut self, token: usize, handler: F)
+    where
+        F: FnOnce(&mut EventLoop, usize, &mut Connection) -> bool,
+    {
+        if let Some(mut connection) = self.connections.remove(&token) {
+            if handler(self, token, &mut connection) {
+                self.connections.insert(token, connection);
+            }
+        }
+    }
+
+    /// Handle new connections and finished responses
+    fn receive(&mut self) {
+        while let Ok(event) = self.receiver.try_recv() {
+            match event {
+                Event::Accepted(mut stream, socket) => {
+                    let token = self.next_token;
+                    self.next_token += 1;
+                    if let Err(error) =
+                        self.poll
+                            .registry()
+                            .register(&mut stream, Token(token), Interest::READABLE)
+                    {
+                        self.application.get_feedback().error(format!(
+                            "Failed to register TCP stream from {}, error: {}",
+                            socket, error
+                        ));
+                        continue;
+                    }
+                    let mut connection = Connection::new(stream, socket);
+                    self.begin_request(&mut connection);
+                    self.connections.insert(token, connection);
+                    self.with_connection(token, EventLoop::read);
+                }
+                Event::Responded(token, (response, keep_alive, send_body)) => {
+                    self.with_connection(token, |event_loop, token, connection| {
+                        event_loop
+                            .start_response(token, connection, response, keep_alive, send_body)
+                    });
+                }
+            }
+        }
+    }
+
+    /// Continue with a connection whose socket is ready
+    fn ready(&mut self, token: usize, connection: &mut Connection) -> bool {
+        match connection.state {
+            State::Idle | State::Reading(_) => self.read(token, connection),
+            State::Writing => self.write(token, connection),
+            State::Processing => true,
+        }
+    }
+
+    /// Start receiving a request, its head has to arrive within the header time-out
+    fn begin_request(&self, connection: &mut Connection) {
+        let config = self.application.get_config();
+        connection.set_state(
+            State::Reading(Timeout::Header),
+            config.server_header_timeout,
+        );
+        connection.request_deadline = Dispatcher::get_timeout(config.server_request_timeout)
+            .map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Frame what has arrived and hand a complete request to the workers, the socket is
+    /// not read further until the response has been sent
+    fn read(&mut self, token: usize, connection: &mut Connection) -> bool {
+        let config = self.application.get_config();
+        loop {
+            if connection.state == State::Idle && !connection.framer.is_empty() {
+                self.begin_request(connection);
+            }
+            match connection.framer.next(config.tcp_limit) {
+                Ok(Some(request)) => return self.dispatch(token, connection, request),
+                Ok(None) => {}
+                Err(error) => {
+                    self.application.get_feedback().error(format!(
+                        "Failed to read HTTP request from TCP stream, error: {}",
+                        error
+                    ));
+                    return match error.get_status() {
+                        Some(status) => {
+                            let response = Dispatcher::get_closing_response(status, HashMap::new());
+                            connection.framer = Framer::new();
+                            self.start_response(token, connection, response, false, true)
+                        }
+                        None => false,
+                    };
+                }
+            }
+            if connection.state == State::Reading(Timeout::Header) && connection.framer.has_head() {
+                connection.set_state(State::Reading(Timeout::Body), config.server_body_timeout);
+            }
+
+            match connection.read_input() {
+                Ok(0) => {
+                    if connection.served == 0 && connection.framer.is_empty() {
+                        self.application
+                            .get_feedback()
+                            .info("TCP stream was empty".to_string());
+                    } else if !connection.framer.is_empty() {
+                        self.application.get_feedback().error(format!(
+                            "TCP stream was closed after {} bytes of a request",
+                            connection.framer.get_received()
+                        ));
+                    }
+                    return false;
+                }
+                Ok(_) => {}
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => return true,
+                Err(error) => {
+                    self.application
+                        .get_feedback()
+                        .error(format!("Failed to read from TCP stream, error: {}", error));
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Queue a framed request for the workers
+    fn dispatch(&mut self, token: usize, connection: &mut Connection, request: Request) -> bool {
+        let config = self.application.get_config();
+        connection.served += 1;
+        connection.state = State::Processing;
+        connection.deadline = None;
+
+        let keep_alive = config.server_keep_alive_timeout > 0
+            && connection.served < config.server_keep_alive_max
+            && !self.shutdown.load(Ordering::SeqCst)
+            && Request::is_keep_alive(&request.head);
+        let remaining = config
+            .server_keep_alive_max
+            .saturating_sub(connection.served);
+        let application = self.application.clone();
+        let responders = Arc::clone(&self.responders);
+        let sender = self.sender.clone();
+        let socket = connection.socket.clone();
+        let waker = Arc::clone(&self.waker);
+        let job = move || {
+            // The connection waits for an answer even if a responder panics
+            let response = panic::catch_unwind(AssertUnwindSafe(|| {
+                Dispatcher::http_response(
+                    &request,
+                    &socket,
+                    &application,
+                    &responders,
+                    false,
+                    keep_alive,
+                    remaining,
+                )
+            }))
+            .unwrap_or_else(|_| {
+                let response =
+                    Dispatcher::get_closing_response("500 Internal Server Error", HashMap::new());
+                (response, false, true)
+            });
+            let response = match response {
+                (ref response, _, _) if response.upgrade.is_some() => {
+                    // Connections can not be handed over from an event loop to a worker
+                    application.get_feedback().error(format!(
+                        "Taking over the connection from {} is not supported by the evented transport",
+                        socket
+                    ));
+                    let response =
+                        Dispatcher::get_closing_response("501 Not Implemented", HashMap::new());
+                    (response, false, true)
+                }
+                response => response,
+            };
+            if sender.send(Event::Responded(token, response)).is_ok() {
+                let _ = waker.wake();
+            }
+        };
+
+        match self.submitter.try_execute(job) {
+            Ok(()) => true,
+            Err(job) => self.overload(token, connection, job),
+        }
+    }
+
+    /// Apply the overload policy to a request the job queue had no room for
+    fn overload(&mut self, token: usize, connection: &mut Connection, job: Job) -> bool {
+        match self.application.get_config().server_overload_policy {
+            OverloadPolicy::Block => {
+                self.application.get_feedback().error(format!(
+                    "Job queue is full, waiting for a worker to queue request from {}",
+                    connection.socket
+                ));
+                connection.pending = Some(job);
+                true
+            }
+            OverloadPolicy::ServiceUnavailable(retry_after) => {
+                self.application.get_feedback().error(format!(
+                    "Job queue is full, responding 503 to {}",
+                    connection.socket
+                ));
+                let mut headers = HashMap::new();
+                headers.insert("Retry-After".to_string(), retry_after.to_string());
+                let response = Dispatcher::get_closing_response("503 Service Unavailable", headers);
+                self.start_response(token, connection, response, false, true)
+            }
+            OverloadPolicy::Close => {
+                self.application.get_feedback().error(format!(
+                    "Job queue is full, closing stream from {}",
+                    connection.socket
+                ));
+                false
+            }
+        }
+    }
+
+    /// Queue requests that waited for room in the job queue, in no particular order
+    fn retry_pending(&mut self) {
+        for connection in self.connections.values_mut() {
+            if let Some(job) = connection.pending.take() {
+                if let Err(job) = self.submitter.try_send(job) {
+                    connection.pending = Some(job);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Start sending a response
+    fn start_response(
+        &mut self,
+        token: usize,
+        connection: &mut Connection,
+        response: response::Message,
+        keep_alive: bool,
+        send_body: bool,
+    ) -> bool {
+        connection.output = response.header_to_string().into_bytes();
+        connection.written = 0;
+        connection.chunked = send_body && response.is_chunked();
+        connection.body = if send_body {
+            Some(response.body.into_reader())
+        } else {
+            None
+        };
+        connection.keep_alive = keep_alive;
+        connection.set_state(
+            State::Writing,
+            self.application.get_config().server_write_timeout,
+        );
+        if let Err(error) = self.poll.registry().reregister(
+            &mut connection.stream,
+            Token(token),
+            Interest::WRITABLE,
+        ) {
+            self.application
+                .get_feedback()
+                .error(format!("Failed to register TCP stream, error: {}", error));
+            return false;
+        }
+        self.write(token, connection)
+    }
+
+    /// Write what the socket accepts, a persistent connection continues with the next request
+    fn write(&mut self, token: usize, connection: &mut Connection) -> bool {
+        let config = self.application.get_config();
+        match connection.write_output(config.server_write_timeout) {
+            Ok(false) => true,
+            Ok(true) => {
+                if !connection.keep_alive {
+                    let _ = connection.stream.shutdown(Shutdown::Write);
+                    return false;
+                }
+                connection.set_state(State::Idle, config.server_keep_alive_timeout);
+                connection.request_deadline = None;
+                if let Err(error) = self.poll.registry().reregister(
+                    &mut connection.stream,
+                    Token(token),
+                    Interest::READABLE,
+                ) {
+                    self.application
+                        .get_feedback()
+                        .error(format!("Failed to register TCP stream, error: {}", error));
+                    return false;
+                }
+                // Pipelined requests may already have been received
+                self.read(token, connection)
+            }
+            Err(error) => {
+                self.application
+                    .get_feedback()
+                    .error(format!("Failed to write to TCP stream, error: {}", error));
+                false
+            }
+        }
+    }
+
+    /// Close idle connections and answer requests that were not received in time
+    fn sweep(&mut self, now: Instant) {
+        let mut expired = Vec::new();
+        for (token, connection) in &self.connections {
+            let request_expired = match connection.request_deadline {
+                Some(deadline) => now >= deadline,
+                None => false,
+            };
+            let state_expired = match connection.deadline {
+                Some(deadline) => now >= deadline,
+                None => false,
+            };
+            let timeout = match connection.state {
+                State::Idle if state_expired => None,
+                State::Reading(_) | State::Writing if request_expired => Some(Timeout::Request),
+                State::Reading(timeout) if state_expired => Some(timeout),
+                State::Writing if state_expired => Some(Timeout::Write),
+                _ => continue,
+            };
+            expired.push((*token, timeout));
+        }
+
+        for (token, timeout) in expired {
+            self.with_connection(token, |event_loop, token, connection| {
+                let timeout = match timeout {
+                    Some(timeout) => timeout,
+                    None => {
+                        event_loop.application.get_feedback().info(format!(
+                            "Closing idle TCP stream after {} requests",
+                            connection.served
+                        ));
+                        return false;
+                    }
+                };
+                event_loop.application.get_metrics().add_timeout(timeout);
+                if connection.state == State::Writing {
+                    event_loop.application.get_feedback().error(format!(
+                        "HTTP response to {} timed out, time-out: {:?}",
+                        connection.socket, timeout
+                    ));
+                    return false;
+                }
+                event_loop.application.get_feedback().error(format!(
+                    "HTTP request from {} timed out, time-out: {:?}",
+                    connection.socket, timeout
+                ));
+                connection.request_deadline = None;
+                let response =
+                    Dispatcher::get_closing_response("408 Request Timeout", HashMap::new());
+                event_loop.start_response(token, connection, response, false, true)
+            });
+        }
+    }
+}
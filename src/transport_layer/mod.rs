@@ -1,71 +1,1955 @@
 //! # Supported transport layers
 //! Binds to the transport layer socket and spawns new threads for dispatching responses.
 
-use std::net::TcpListener;
+mod activation;
+mod evented;
+pub mod proxy;
+mod tls;
 
-use response::tcp::http::ResponderInterface;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::fs::{chown, FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use libc;
+
+use rustls::ServerConfig;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR2};
+use signal_hook::iterator::Signals;
+use signal_hook::low_level::emulate_default_handler;
+
+use self::tls::Certificates;
+use response::tcp::http;
+use response::tcp::http::{ResponderInterface, Responders};
 use response::tcp::Dispatcher;
-use thread::Pool;
-use Application;
+use thread::{Pool, Submitter};
+use {Application, Config, OverloadPolicy, Transport};
+
+/// How long a listener waits for a connection before it checks shutdown
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a listener at its connection limit checks whether a connection has closed
+const LIMIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often the pool is checked for workers that died
+const RESPAWN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Errors that can occur when starting a server
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to bind a listener to the address
+    Bind(String, io::Error),
+    /// Failed to set up the event loops of the evented transport
+    EventLoop(io::Error),
+    /// Failed to take the listening sockets passed to the process
+    Inherit(io::Error),
+    /// Failed to get the address a listener was bound to
+    LocalAddress(io::Error),
+    /// Failed to set the mode or owner of a Unix domain socket
+    Permissions(String, io::Error),
+    /// Failed to start a new process with the listening sockets
+    Restart(io::Error),
+    /// Failed to register handlers for termination signals
+    Signal(io::Error),
+    /// Failed to load certificates or set up TLS
+    Tls(String),
+}
+
+impl Error {
+    /// Whether the address was already in use by another socket
+    pub fn is_address_in_use(&self) -> bool {
+        match self {
+            Error::Bind(_, error) => error.kind() == io::ErrorKind::AddrInUse,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Bind(address, error) => write!(
+                f,
+                "Failed to bind to server and port: {}, error: {}",
+                address, error
+            ),
+            Error::EventLoop(error) => {
+                write!(f, "Failed to set up event loop, error: {}", error)
+            }
+            Error::Inherit(error) => {
+                write!(f, "Failed to inherit listening sockets, error: {}", error)
+            }
+            Error::LocalAddress(error) => {
+                write!(f, "Failed to get address of listener, error: {}", error)
+            }
+            Error::Permissions(path, error) => write!(
+                f,
+                "Failed to set permissions of socket: {}, error: {}",
+                path, error
+            ),
+            Error::Restart(error) => write!(f, "Failed to restart server, error: {}", error),
+            Error::Signal(error) => {
+                write!(f, "Failed to register signal handlers, error: {}", error)
+            }
+            Error::Tls(error) => write!(f, "Failed to set up TLS, error: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// The address of a peer or listener, clients of a Unix domain socket are usually unnamed and
+/// the port of clients forwarded by a reverse proxy is usually not known
+/// ```rust
+/// use milstian_internet_framework::transport_layer::Address;
+/// use std::path::PathBuf;
+/// let address = Address::Tcp("127.0.0.1:8080".parse().unwrap());
+/// assert_eq!(address.ip(), Some("127.0.0.1".parse().unwrap()));
+/// assert_eq!(address.to_string(), "127.0.0.1:8080");
+/// let address = Address::Unix(Some(PathBuf::from("/run/milstian.sock")));
+/// assert_eq!(address.ip(), None);
+/// assert_eq!(address.to_string(), "unix:/run/milstian.sock");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Unix(Option<PathBuf>),
+    /// A client whose address a trusted reverse proxy told in a header field, its requests
+    /// arrive through that proxy
+    Forwarded(IpAddr),
+}
+
+impl Address {
+    /// The IP address of a TCP peer or forwarded client
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Address::Tcp(address) => Some(address.ip()),
+            Address::Unix(_) => None,
+            Address::Forwarded(ip) => Some(*ip),
+        }
+    }
+
+    /// The IP address and port of a TCP peer
+    pub fn get_socket_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Address::Tcp(address) => Some(*address),
+            Address::Unix(_) | Address::Forwarded(_) => None,
+        }
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(address: SocketAddr) -> Address {
+        Address::Tcp(address)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => write!(f, "{}", address),
+            Address::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+            Address::Unix(None) => write!(f, "unix:"),
+            Address::Forwarded(ip) => write!(f, "{}", ip),
+        }
+    }
+}
+
+/// What a listener binds to
+#[derive(Clone, Debug, PartialEq)]
+pub enum Endpoint {
+    /// HTTP over TCP on a host and port like `0.0.0.0:80`, IPv6 addresses like `[::]:80` only
+    /// accept IPv6 so the same port can be bound for IPv4 as well
+    Tcp(String),
+    /// HTTP over TLS on a host and port with the certificates of the configuration
+    Tls(String),
+    /// HTTP over the Unix domain socket at a path with the mode and owner of the configuration
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(address) | Endpoint::Tls(address) => write!(f, "{}", address),
+            Endpoint::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// An endpoint with the responders and limits its connections are served with, all listeners
+/// of a server share its worker pool
+/// ```rust
+/// use milstian_internet_framework::response::tcp::http::error;
+/// use milstian_internet_framework::transport_layer::{Endpoint, Listener};
+/// let mut listener = Listener::new(
+///     Endpoint::Tcp("[::]:8080".to_string()),
+///     vec![Box::new(error::Responder::new())],
+/// );
+/// listener.connection_limit = Some(64);
+/// listener.tcp_limit = Some(4096);
+/// ```
+pub struct Listener {
+    /// Most connections of the listener being served or waiting for a worker at once, the
+    /// overload policy applies to further connections, not enforced by the evented transport
+    pub connection_limit: Option<usize>,
+    pub endpoint: Endpoint,
+    /// Whether connections start with a PROXY protocol header that tells the client address,
+    /// not supported by the evented transport
+    pub proxy_protocol: bool,
+    pub responders: Vec<Box<dyn ResponderInterface>>,
+    /// Maximum size of a request in bytes instead of `tcp_limit`
+    pub tcp_limit: Option<usize>,
+}
+
+impl Listener {
+    pub fn new(endpoint: Endpoint, responders: Vec<Box<dyn ResponderInterface>>) -> Listener {
+        Listener {
+            connection_limit: None,
+            endpoint,
+            proxy_protocol: false,
+            responders,
+            tcp_limit: None,
+        }
+    }
+}
+
+/// A listening socket of a server
+enum Acceptor {
+    Tcp(TcpListener),
+    /// The socket file at the path is removed when the server stops, inherited sockets have
+    /// no path to remove
+    Unix(UnixListener, Option<PathBuf>),
+}
+
+/// A connection accepted by a listener
+enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Acceptor {
+    /// Wait a while for a connection and accept it, `None` when there was none. Listening
+    /// sockets are non-blocking since another process may accept a connection first after a
+    /// restart passed them on.
+    fn accept(&self) -> io::Result<Option<(Connection, Address)>> {
+        let mut poll = libc::pollfd {
+            fd: self.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = ACCEPT_POLL_INTERVAL.as_millis() as libc::c_int;
+        if unsafe { libc::poll(&mut poll, 1, timeout) } < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                return Ok(None);
+            }
+            return Err(error);
+        }
+        if poll.revents == 0 {
+            return Ok(None);
+        }
+
+        let accepted = match self {
+            Acceptor::Tcp(listener) => listener.accept().and_then(|(stream, socket)| {
+                stream.set_nonblocking(false)?;
+                Ok((Connection::Tcp(stream), Address::Tcp(socket)))
+            }),
+            Acceptor::Unix(listener, _) => listener.accept().and_then(|(stream, socket)| {
+                stream.set_nonblocking(false)?;
+                let path = socket.as_pathname().map(PathBuf::from);
+                Ok((Connection::Unix(stream), Address::Unix(path)))
+            }),
+        };
+        match accepted {
+            Ok(accepted) => Ok(Some(accepted)),
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The address the socket is bound to
+    fn get_local_addr(&self) -> io::Result<Address> {
+        match self {
+            Acceptor::Tcp(listener) => Ok(Address::Tcp(listener.local_addr()?)),
+            Acceptor::Unix(listener, _) => Ok(Address::Unix(
+                listener.local_addr()?.as_pathname().map(PathBuf::from),
+            )),
+        }
+    }
+
+    /// Whether an inherited socket is bound to the address of an endpoint
+    fn is_bound_to(&self, endpoint: &Endpoint) -> bool {
+        match (self, endpoint, self.get_local_addr()) {
+            (Acceptor::Tcp(_), Endpoint::Tcp(address), Ok(Address::Tcp(local_addr)))
+            | (Acceptor::Tcp(_), Endpoint::Tls(address), Ok(Address::Tcp(local_addr))) => {
+                match address.to_socket_addrs() {
+                    Ok(mut sockets) => sockets.any(|socket| socket == local_addr),
+                    Err(_) => false,
+                }
+            }
+            (Acceptor::Unix(_, _), Endpoint::Unix(path), Ok(Address::Unix(Some(local_path)))) => {
+                *path == local_path
+            }
+            _ => false,
+        }
+    }
+
+    /// Name of the transport protocol for feedback
+    fn get_protocol(&self) -> &str {
+        match self {
+            Acceptor::Tcp(_) => "TCP",
+            Acceptor::Unix(_, _) => "Unix domain socket",
+        }
+    }
+}
+
+impl AsRawFd for Acceptor {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Acceptor::Tcp(listener) => listener.as_raw_fd(),
+            Acceptor::Unix(listener, _) => listener.as_raw_fd(),
+        }
+    }
+}
+
+impl Connection {
+    /// Read the PROXY protocol header of the connection within a time-out
+    fn read_proxy_header(&mut self, timeout: Option<Duration>) -> io::Result<Option<SocketAddr>> {
+        let result = match self {
+            Connection::Tcp(stream) => proxy::read_header(stream, timeout),
+            Connection::Unix(stream) => proxy::read_header(stream, timeout),
+        };
+        match self {
+            Connection::Tcp(stream) => stream.set_read_timeout(None)?,
+            Connection::Unix(stream) => stream.set_read_timeout(None)?,
+        }
+        result
+    }
+}
+
+/// Counts an open connection of a listener until it is dropped
+struct ConnectionCount(Arc<AtomicUsize>);
+
+impl ConnectionCount {
+    fn new(connections: &Arc<AtomicUsize>) -> ConnectionCount {
+        connections.fetch_add(1, Ordering::SeqCst);
+        ConnectionCount(Arc::clone(connections))
+    }
+}
+
+impl Drop for ConnectionCount {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A bound listener
+struct Bound {
+    acceptor: Acceptor,
+    /// The application with the limits of the listener
+    application: Application,
+    connection_limit: Option<usize>,
+    connections: Arc<AtomicUsize>,
+    local_addr: Address,
+    proxy_protocol: bool,
+    responders: Responders,
+    tls: Option<Arc<ServerConfig>>,
+}
+
+impl Bound {
+    /// Bind a listener or take the inherited socket that is bound to its endpoint
+    fn bind(
+        application: &Application,
+        listener: Listener,
+        tls: &Option<Arc<ServerConfig>>,
+        inherited: &mut Vec<Acceptor>,
+    ) -> Result<Bound, Error> {
+        let mut config = application.get_config().clone();
+        let position = inherited
+            .iter()
+            .position(|acceptor| acceptor.is_bound_to(&listener.endpoint));
+        let (acceptor, local_addr) = match position {
+            Some(index) => {
+                let acceptor = inherited.remove(index);
+                let local_addr = match acceptor.get_local_addr() {
+                    Ok(local_addr) => local_addr,
+                    Err(e) => return Err(Error::LocalAddress(e)),
+                };
+                application.get_feedback().info(format!(
+                    "Inherited {} socket bound to {}",
+                    acceptor.get_protocol(),
+                    &local_addr
+                ));
+                (acceptor, local_addr)
+            }
+            None => Bound::bind_endpoint(&listener.endpoint, &config)?,
+        };
+        let nonblocking = match acceptor {
+            Acceptor::Tcp(ref listener) => listener.set_nonblocking(true),
+            Acceptor::Unix(ref listener, _) => listener.set_nonblocking(true),
+        };
+        if let Err(e) = nonblocking {
+            return Err(Error::Bind(listener.endpoint.to_string(), e));
+        }
+        let tls = match listener.endpoint {
+            Endpoint::Tls(_) => tls.clone(),
+            _ => None,
+        };
+        application.get_feedback().info(format!(
+            "Listening on HTTP requests via {}{} to {}",
+            acceptor.get_protocol(),
+            if tls.is_some() { " with TLS" } else { "" },
+            &local_addr
+        ));
+
+        if let Some(tcp_limit) = listener.tcp_limit {
+            config.tcp_limit = tcp_limit;
+        }
+        Ok(Bound {
+            acceptor,
+            application: application.with_config(config),
+            connection_limit: listener.connection_limit,
+            connections: Arc::new(AtomicUsize::new(0)),
+            local_addr,
+            proxy_protocol: listener.proxy_protocol,
+            responders: Arc::new(listener.responders),
+            tls,
+        })
+    }
+
+    /// Bind a new socket to an endpoint
+    fn bind_endpoint(endpoint: &Endpoint, config: &Config) -> Result<(Acceptor, Address), Error> {
+        match endpoint {
+            Endpoint::Tcp(address) | Endpoint::Tls(address) => {
+                let tcp_listener = match bind_tcp(address) {
+                    Ok(tcp_listener) => tcp_listener,
+                    Err(e) => return Err(Error::Bind(address.clone(), e)),
+                };
+                let local_addr = match tcp_listener.local_addr() {
+                    Ok(local_addr) => local_addr,
+                    Err(e) => return Err(Error::LocalAddress(e)),
+                };
+                Ok((Acceptor::Tcp(tcp_listener), Address::Tcp(local_addr)))
+            }
+            Endpoint::Unix(path) => {
+                let display = path.display().to_string();
+                if let Err(e) = Unix::remove_stale_socket(path) {
+                    return Err(Error::Bind(display, e));
+                }
+                let unix_listener = match UnixListener::bind(path) {
+                    Ok(unix_listener) => unix_listener,
+                    Err(e) => return Err(Error::Bind(display, e)),
+                };
+                if let Err(e) = Unix::set_permissions(path, config) {
+                    let _ = fs::remove_file(path);
+                    return Err(Error::Permissions(display, e));
+                }
+                Ok((
+                    Acceptor::Unix(unix_listener, Some(path.clone())),
+                    Address::Unix(Some(path.clone())),
+                ))
+            }
+        }
+    }
+
+    /// Stop listening, the socket file of a Unix domain socket is removed unless the socket
+    /// was passed on to another process
+    fn close(self, passed: bool) {
+        self.application.get_feedback().info(format!(
+            "Stopped listening on HTTP requests via {} to {}",
+            self.acceptor.get_protocol(),
+            self.local_addr
+        ));
+        match self.acceptor {
+            Acceptor::Unix(_, Some(ref path)) if !passed => {
+                if let Err(e) = fs::remove_file(path) {
+                    self.application.get_feedback().error(format!(
+                        "Failed to remove socket {}, error: {}",
+                        path.display(),
+                        e
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether the listener has more open connections than its limit
+    fn is_over_limit(&self) -> bool {
+        match self.connection_limit {
+            Some(limit) => self.connections.load(Ordering::SeqCst) > limit,
+            None => false,
+        }
+    }
+}
+
+/// Bind a TCP listener to the first address a host and port resolves to that can be bound
+fn bind_tcp(address: &str) -> io::Result<TcpListener> {
+    let mut error = io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Could not resolve to any addresses",
+    );
+    for socket in address.to_socket_addrs()? {
+        let result = match socket {
+            SocketAddr::V4(_) => TcpListener::bind(socket),
+            SocketAddr::V6(ref socket) => bind_tcp_v6_only(socket),
+        };
+        match result {
+            Ok(listener) => return Ok(listener),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Bind a TCP listener that only accepts IPv6, the standard library binds IPv6 sockets that
+/// accept IPv4 as well on most systems
+fn bind_tcp_v6_only(socket: &SocketAddrV6) -> io::Result<TcpListener> {
+    let enabled: libc::c_int = 1;
+    let mut address: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+    address.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+    address.sin6_port = socket.port().to_be();
+    address.sin6_flowinfo = socket.flowinfo();
+    address.sin6_addr.s6_addr = socket.ip().octets();
+    address.sin6_scope_id = socket.scope_id();
+    unsafe {
+        let fd = libc::socket(libc::AF_INET6, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The listener closes the socket if any of the following calls fail
+        let listener = TcpListener::from_raw_fd(fd);
+        for option in &[
+            (libc::SOL_SOCKET, libc::SO_REUSEADDR),
+            (libc::IPPROTO_IPV6, libc::IPV6_V6ONLY),
+        ] {
+            if libc::setsockopt(
+                fd,
+                option.0,
+                option.1,
+                &enabled as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            ) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if libc::bind(
+            fd,
+            &address as *const libc::sockaddr_in6 as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+        ) < 0
+            || libc::listen(fd, 128) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(listener)
+    }
+}
+
+/// The listening sockets of a server that are still open
+#[derive(Debug)]
+struct Listening {
+    fds: Vec<RawFd>,
+    /// Whether the sockets were passed on to another process by a restart
+    passed: bool,
+}
+
+/// A cloneable handle to a bound server that can be used to stop it from any thread
+#[derive(Clone, Debug)]
+pub struct Handle {
+    addresses: Vec<Address>,
+    certificates: Option<Arc<Certificates>>,
+    listening: Arc<Mutex<Listening>>,
+    queue_depth: Arc<AtomicUsize>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Handle {
+    /// Number of accepted connections waiting for a worker
+    pub fn get_queue_depth(&self) -> usize {
+        self.queue_depth.load(Ordering::SeqCst)
+    }
+
+    /// The address the first listener is bound to
+    pub fn get_address(&self) -> &Address {
+        &self.addresses[0]
+    }
+
+    /// The addresses the listeners are bound to in the order they were given
+    pub fn get_addresses(&self) -> &[Address] {
+        &self.addresses
+    }
+
+    /// The IP address and port the first TCP listener is bound to, `None` if the server only
+    /// listens on Unix domain sockets
+    pub fn get_local_addr(&self) -> Option<SocketAddr> {
+        self.addresses
+            .iter()
+            .filter_map(Address::get_socket_addr)
+            .next()
+    }
+
+    /// Read the TLS certificates of the server from their files again, connections that are
+    /// already established keep their certificate, does nothing for servers without TLS
+    pub fn reload_certificates(&self) -> Result<(), Error> {
+        match self.certificates {
+            Some(ref certificates) => certificates.reload(),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether shutdown of the server has been requested
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Make the server stop accepting new connections, `run` returns when the workers
+    /// have finished their jobs or the shutdown time-out has passed
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    /// Start a new process that takes over the listening sockets by the `LISTEN_FDS` protocol
+    /// and shut this server down, connections already being served finish within the shutdown
+    /// time-out. New connections wait in the queue of the sockets until the new process
+    /// accepts them. The program of the command is executed by its path.
+    pub fn restart(&self, command: Command) -> Result<Child, Error> {
+        let mut listening = match self.listening.lock() {
+            Ok(listening) => listening,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if self.is_shutdown() {
+            return Err(Error::Restart(io::Error::other("Server is shutting down")));
+        }
+        let child = match activation::spawn(command, &listening.fds) {
+            Ok(child) => child,
+            Err(e) => return Err(Error::Restart(e)),
+        };
+        listening.passed = true;
+        self.shutdown();
+        Ok(child)
+    }
+
+    /// Close a listener that stopped accepting, a restart can not pass it on after that
+    fn close(&self, listener: Bound) {
+        let mut listening = match self.listening.lock() {
+            Ok(listening) => listening,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let fd = listener.acceptor.as_raw_fd();
+        listening.fds.retain(|listening| *listening != fd);
+        listener.close(listening.passed);
+    }
+}
+
+/// A bound HTTP server with one or more listeners over TCP, TLS or Unix domain sockets
+pub struct Server {
+    application: Application,
+    handle: Handle,
+    listeners: Vec<Bound>,
+    pool: Pool,
+}
+
+impl Server {
+    /// Bind every listener of a server, the evented transport supports a single TCP listener.
+    /// Listening sockets passed by socket activation or a restart are taken from the
+    /// environment, so a process that inherits sockets has to bind before it starts other
+    /// threads.
+    /// ```rust
+    /// use milstian_internet_framework::{Application, Config};
+    /// use milstian_internet_framework::response::tcp::http::{error, https_redirect};
+    /// use milstian_internet_framework::transport_layer::{Endpoint, Listener, Server};
+    /// let config = Config::from_env_args(vec![
+    ///     "".to_string(),
+    ///     "127.0.0.1".to_string(),
+    ///     "0".to_string(),
+    ///     "4".to_string(),
+    ///     "index.htm".to_string(),
+    ///     "./html/".to_string(),
+    ///     "404.htm".to_string(),
+    ///     "1024".to_string(),
+    /// ]).unwrap();
+    /// let application = Application::new(config);
+    /// let server = Server::bind(
+    ///     &application,
+    ///     vec![
+    ///         Listener::new(
+    ///             Endpoint::Tcp("127.0.0.1:0".to_string()),
+    ///             vec![Box::new(error::Responder::new())],
+    ///         ),
+    ///         Listener::new(
+    ///             Endpoint::Tcp("[::1]:0".to_string()),
+    ///             vec![
+    ///                 Box::new(https_redirect::Responder::new()),
+    ///                 Box::new(error::Responder::new()),
+    ///             ],
+    ///         ),
+    ///     ],
+    /// ).unwrap();
+    /// assert_eq!(server.get_handle().get_addresses().len(), 2);
+    /// server.shutdown();
+    /// assert!(server.run().is_ok());
+    /// ```
+    pub fn bind(application: &Application, listeners: Vec<Listener>) -> Result<Server, Error> {
+        let config = application.get_config();
+        if listeners.is_empty() {
+            return Err(Error::Bind(
+                String::new(),
+                io::Error::new(io::ErrorKind::InvalidInput, "No listeners to bind"),
+            ));
+        }
+        if let Transport::Evented(_) = config.server_transport {
+            for listener in &listeners {
+                if listener.proxy_protocol {
+                    return Err(Error::Bind(
+                        listener.endpoint.to_string(),
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "The PROXY protocol is only supported by the blocking transport",
+                        ),
+                    ));
+                }
+                match listener.endpoint {
+                    Endpoint::Tcp(_) => {}
+                    Endpoint::Tls(_) => {
+                        return Err(Error::Tls(
+                            "TLS is only supported by the blocking transport".to_string(),
+                        ))
+                    }
+                    Endpoint::Unix(ref path) => {
+                        return Err(Error::Bind(
+                            path.display().to_string(),
+                            io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                "Unix domain sockets are only supported by the blocking transport",
+                            ),
+                        ))
+                    }
+                }
+            }
+            if listeners.len() > 1 {
+                return Err(Error::Bind(
+                    listeners[1].endpoint.to_string(),
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Several listeners are only supported by the blocking transport",
+                    ),
+                ));
+            }
+        }
+
+        let mut tls = None;
+        let mut certificates = None;
+        let has_tls = listeners
+            .iter()
+            .any(|listener| matches!(listener.endpoint, Endpoint::Tls(_)));
+        if has_tls {
+            let (server_config, server_certificates) = tls::get_server_config(config)?;
+            tls = Some(server_config);
+            certificates = Some(server_certificates);
+        }
+
+        let mut inherited = match activation::take_listeners() {
+            Ok(inherited) => inherited,
+            Err(e) => return Err(Error::Inherit(e)),
+        };
+        let mut bound: Vec<Bound> = Vec::with_capacity(listeners.len());
+        for listener in listeners {
+            match Bound::bind(application, listener, &tls, &mut inherited) {
+                Ok(listener) => bound.push(listener),
+                Err(error) => {
+                    for listener in bound {
+                        listener.close(false);
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        for acceptor in inherited {
+            application.get_feedback().error(format!(
+                "Closing inherited {} socket bound to {} that matches no listener",
+                acceptor.get_protocol(),
+                match acceptor.get_local_addr() {
+                    Ok(local_addr) => local_addr.to_string(),
+                    Err(_) => "an unknown address".to_string(),
+                }
+            ));
+        }
+
+        let pool = Pool::new(application, config.server_limit, config.server_queue_limit);
+        Ok(Server {
+            application: application.clone(),
+            handle: Handle {
+                addresses: bound
+                    .iter()
+                    .map(|listener| listener.local_addr.clone())
+                    .collect(),
+                certificates,
+                listening: Arc::new(Mutex::new(Listening {
+                    fds: bound
+                        .iter()
+                        .map(|listener| listener.acceptor.as_raw_fd())
+                        .collect(),
+                    passed: false,
+                })),
+                queue_depth: pool.get_queue_depth_counter(),
+                shutdown: Arc::new(AtomicBool::new(false)),
+            },
+            listeners: bound,
+            pool,
+        })
+    }
+
+    /// Get a handle that can shut down the server from another thread
+    pub fn get_handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    /// The address the first listener is bound to
+    pub fn get_address(&self) -> &Address {
+        self.handle.get_address()
+    }
+
+    /// The IP address and port the first TCP listener is bound to, useful when binding to port 0
+    pub fn get_local_addr(&self) -> Option<SocketAddr> {
+        self.handle.get_local_addr()
+    }
+
+    /// Accept connections and dispatch them to the worker pool until shutdown
+    pub fn run(self) -> Result<(), Error> {
+        let Server {
+            application,
+            handle,
+            mut listeners,
+            mut pool,
+        } = self;
+        let config = application.get_config();
+        if let Transport::Evented(loops) = config.server_transport {
+            let listener = listeners.remove(0);
+            if let Acceptor::Tcp(ref tcp_listener) = listener.acceptor {
+                evented::serve(
+                    &listener.application,
+                    &handle,
+                    tcp_listener,
+                    &mut pool,
+                    &listener.responders,
+                    loops,
+                )?;
+            }
+            handle.close(listener);
+        } else {
+            // Every listener accepts in its own thread and queues jobs in the shared pool, this
+            // thread replaces the workers that died meanwhile
+            thread::scope(|scope| {
+                for listener in listeners {
+                    let handle = &handle;
+                    let submitter = pool.get_submitter();
+                    scope.spawn(move || {
+                        Server::accept(&listener, handle, submitter);
+                        handle.close(listener);
+                    });
+                }
+                while !handle.is_shutdown() {
+                    thread::sleep(RESPAWN_INTERVAL);
+                    pool.respawn();
+                }
+            });
+        }
+        pool.shutdown(Duration::from_secs(config.server_shutdown_timeout));
+        Ok(())
+    }
+
+    /// Accept connections of a listener and dispatch each of them to the worker pool until
+    /// shutdown, a full queue blocks this listener alone
+    fn accept(listener: &Bound, handle: &Handle, submitter: Submitter) {
+        let application = &listener.application;
+        let config = application.get_config();
+        while !handle.is_shutdown() {
+            match listener.acceptor.accept() {
+                Ok(None) => {}
+                Ok(Some((mut connection, mut socket))) => {
+                    application.get_feedback().info(format!(
+                        "Received new {} stream from {}",
+                        listener.acceptor.get_protocol(),
+                        socket
+                    ));
+
+                    let count = ConnectionCount::new(&listener.connections);
+                    let over_limit = listener.is_over_limit();
+                    if over_limit && config.server_overload_policy == OverloadPolicy::Block {
+                        application.get_feedback().error(format!(
+                            "Connection limit of listener is reached, waiting for a connection to close to queue stream from {}",
+                            socket
+                        ));
+                        while listener.is_over_limit() && !handle.is_shutdown() {
+                            thread::sleep(LIMIT_POLL_INTERVAL);
+                        }
+                    }
+
+                    let full = submitter.is_full();
+                    let reason = if full {
+                        "Job queue is full"
+                    } else {
+                        "Connection limit of listener is reached"
+                    };
+                    if full
+                        || (over_limit && config.server_overload_policy != OverloadPolicy::Block)
+                    {
+                        match config.server_overload_policy {
+                            OverloadPolicy::Block => {
+                                application.get_feedback().error(format!(
+                                    "{}, waiting for a worker to queue stream from {}",
+                                    reason, socket
+                                ));
+                            }
+                            OverloadPolicy::ServiceUnavailable(retry_after)
+                                if listener.tls.is_none() =>
+                            {
+                                application
+                                    .get_feedback()
+                                    .error(format!("{}, responding 503 to {}", reason, socket));
+                                let result = match connection {
+                                    Connection::Tcp(stream) => {
+                                        Dispatcher::http_unavailable(stream, retry_after)
+                                    }
+                                    Connection::Unix(stream) => {
+                                        Dispatcher::http_unavailable(stream, retry_after)
+                                    }
+                                };
+                                if let Err(e) = result {
+                                    application.get_feedback().error(format!(
+                                        "Failed to respond 503 to stream, error: {}",
+                                        e
+                                    ));
+                                }
+                                continue;
+                            }
+                            // Responding before the TLS handshake is not possible
+                            OverloadPolicy::ServiceUnavailable(_) | OverloadPolicy::Close => {
+                                application
+                                    .get_feedback()
+                                    .error(format!("{}, closing stream from {}", reason, socket));
+                                continue;
+                            }
+                        }
+                    }
+
+                    let application = application.clone();
+                    let responders = Arc::clone(&listener.responders);
+                    application
+                        .get_feedback()
+                        .info("Sending stream as HTTP job to pool".to_string());
+                    let shutdown = Arc::clone(&handle.shutdown);
+                    let tls = listener.tls.clone();
+                    let proxy_protocol = listener.proxy_protocol;
+                    let result = submitter.execute(move || {
+                        // The connection counts against the limit until it is served
+                        let _count = count;
+                        if proxy_protocol {
+                            let config = application.get_config();
+                            let timeout = match config.server_header_timeout {
+                                0 => None,
+                                seconds => Some(Duration::from_secs(seconds)),
+                            };
+                            match connection.read_proxy_header(timeout) {
+                                Ok(Some(client)) => socket = Address::Tcp(client),
+                                Ok(None) => {}
+                                Err(error) => {
+                                    application.get_feedback().error(format!(
+                                        "Failed to read PROXY protocol header from {}, error: {}",
+                                        socket, error
+                                    ));
+                                    return;
+                                }
+                            }
+                        }
+                        match (connection, tls) {
+                            (Connection::Tcp(stream), Some(tls)) => {
+                                tls::http(tls, stream, socket, application, responders, shutdown)
+                            }
+                            (Connection::Tcp(stream), None) => {
+                                Dispatcher::http(stream, socket, application, responders, shutdown)
+                            }
+                            (Connection::Unix(stream), _) => {
+                                Dispatcher::http(stream, socket, application, responders, shutdown)
+                            }
+                        }
+                    });
+                    if result.is_err() {
+                        listener
+                            .application
+                            .get_feedback()
+                            .error("Failed to send job down the channel".to_string());
+                    }
+                }
+                Err(e) => {
+                    application
+                        .get_feedback()
+                        .error(format!("Failed to accept a incoming stream, error: {}", e));
+                }
+            }
+        }
+    }
+
+    /// Make the server stop accepting new connections
+    pub fn shutdown(&self) {
+        self.handle.shutdown();
+    }
+
+    /// Shut down the server gracefully when the process receives SIGTERM or SIGINT and restart
+    /// it in a new process of the same program when it receives SIGUSR2, servers with TLS also
+    /// reload their certificates when the process receives SIGHUP. A second SIGTERM or SIGINT
+    /// terminates the process without waiting for connections to drain
+    pub fn shutdown_on_signals(&self) -> Result<(), Error> {
+        let mut signal_numbers = vec![SIGTERM, SIGINT, SIGUSR2];
+        if self.handle.certificates.is_some() {
+            signal_numbers.push(SIGHUP);
+        }
+        let mut signals = match Signals::new(signal_numbers) {
+            Ok(signals) => signals,
+            Err(e) => return Err(Error::Signal(e)),
+        };
+        let application = self.application.clone();
+        let handle = self.get_handle();
+        thread::spawn(move || {
+            let mut shutting_down = false;
+            for signal in signals.forever() {
+                if signal == SIGHUP {
+                    match handle.reload_certificates() {
+                        Ok(()) => application
+                            .get_feedback()
+                            .info("Reloaded TLS certificates".to_string()),
+                        Err(error) => application.get_feedback().error(format!(
+                            "Failed to reload TLS certificates, error: {}",
+                            error
+                        )),
+                    }
+                    continue;
+                }
+                if signal == SIGUSR2 {
+                    let command = env::current_exe().map(|program| {
+                        let mut command = Command::new(program);
+                        command.args(env::args_os().skip(1));
+                        command
+                    });
+                    let result = match command {
+                        Ok(command) => handle.restart(command),
+                        Err(e) => Err(Error::Restart(e)),
+                    };
+                    match result {
+                        Ok(child) => application.get_feedback().info(format!(
+                            "Received signal {}, restarting in process {}",
+                            signal,
+                            child.id()
+                        )),
+                        Err(error) => application.get_feedback().error(format!(
+                            "Received signal {}, failed to restart, error: {}",
+                            signal, error
+                        )),
+                    }
+                    continue;
+                }
+                if shutting_down {
+                    application.get_feedback().error(format!(
+                        "Received signal {} again, exiting without draining connections",
+                        signal
+                    ));
+                    if let Err(error) = emulate_default_handler(signal) {
+                        application.get_feedback().error(format!(
+                            "Failed to terminate on signal {}, error: {}",
+                            signal, error
+                        ));
+                    }
+                    process::exit(1);
+                }
+                shutting_down = true;
+                application.get_feedback().info(format!(
+                    "Received signal {}, shutting down gracefully",
+                    signal
+                ));
+                handle.shutdown();
+            }
+        });
+        Ok(())
+    }
+}
 
 pub struct TCP {}
 
 impl TCP {
-    /// This method creates a new HTTP over TCP application based on configuration
+    /// This method binds a new HTTP over TCP server based on configuration
+    /// ```rust
+    /// use milstian_internet_framework::{Application, Config};
+    /// use milstian_internet_framework::response::tcp::http::{error, filesystem, ResponderInterface};
+    /// use milstian_internet_framework::transport_layer;
+    /// use std::thread;
+    /// let config = Config::from_env_args(vec![
+    ///     "".to_string(),
+    ///     "127.0.0.1".to_string(),
+    ///     "0".to_string(),
+    ///     "4".to_string(),
+    ///     "index.htm".to_string(),
+    ///     "./html/".to_string(),
+    ///     "404.htm".to_string(),
+    ///     "1024".to_string(),
+    /// ]).unwrap();
+    /// let application = Application::new(config);
+    /// let responders: Vec<Box<dyn ResponderInterface>> = vec![
+    ///     Box::new(filesystem::Responder::new()),
+    ///     Box::new(error::Responder::new()),
+    /// ];
+    /// let server = transport_layer::TCP::bind(&application, responders).unwrap();
+    /// assert_ne!(server.get_local_addr().unwrap().port(), 0);
+    /// let handle = server.get_handle();
+    /// let thread = thread::spawn(move || server.run());
+    /// handle.shutdown();
+    /// assert!(thread.join().unwrap().is_ok());
+    /// ```
+    pub fn bind(
+        application: &Application,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<Server, Error> {
+        let config = application.get_config();
+        let address = format!("{}:{}", &config.server_host, &config.server_port);
+        let mut listener = Listener::new(Endpoint::Tcp(address), responders);
+        listener.proxy_protocol = config.server_proxy_protocol;
+        Server::bind(application, vec![listener])
+    }
+
+    /// This method creates a new HTTP over TCP application based on configuration and
+    /// serves it until shutdown, SIGTERM and SIGINT shut it down gracefully
+    /// ```rust,should_panic
+    /// use milstian_internet_framework::{Application, Config};
+    /// use milstian_internet_framework::response::tcp::http::{error, file_not_found, filesystem, ResponderInterface};
+    /// use milstian_internet_framework::transport_layer;
+    /// let config = Config::from_env().expect("Failed to get configuration from environment");
+    /// let application = Application::new(config);
+    /// let responders: Vec<Box<dyn ResponderInterface>> = vec![
+    ///     Box::new(filesystem::Responder::new()),
+    ///     Box::new(file_not_found::Responder::new()),
+    ///     Box::new(error::Responder::new()),
+    /// ];
+    /// transport_layer::TCP::http(&application, responders).unwrap();
+    /// ```
+    pub fn http(
+        application: &Application,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<(), Error> {
+        let server = TCP::bind(application, responders)?;
+        server.shutdown_on_signals()?;
+        server.run()
+    }
+
+    /// This method binds a new HTTP over TCP server that redirects every request to the
+    /// same URL over HTTPS on the configured `tls_port`
+    /// ```rust
+    /// use milstian_internet_framework::{Application, Config};
+    /// use milstian_internet_framework::transport_layer;
+    /// let config = Config::from_env_args(vec![
+    ///     "".to_string(),
+    ///     "127.0.0.1".to_string(),
+    ///     "0".to_string(),
+    ///     "4".to_string(),
+    ///     "index.htm".to_string(),
+    ///     "./html/".to_string(),
+    ///     "404.htm".to_string(),
+    ///     "1024".to_string(),
+    /// ]).unwrap();
+    /// let application = Application::new(config);
+    /// let server = transport_layer::TCP::bind_https_redirect(&application).unwrap();
+    /// assert_ne!(server.get_local_addr().unwrap().port(), 0);
+    /// ```
+    pub fn bind_https_redirect(application: &Application) -> Result<Server, Error> {
+        TCP::bind(
+            application,
+            vec![
+                Box::new(http::https_redirect::Responder::new()),
+                Box::new(http::error::Responder::new()),
+            ],
+        )
+    }
+
+    /// This method creates a new HTTP over TCP application that only redirects to HTTPS
+    /// and serves it until shutdown
+    pub fn https_redirect(application: &Application) -> Result<(), Error> {
+        let server = TCP::bind_https_redirect(application)?;
+        server.shutdown_on_signals()?;
+        server.run()
+    }
+}
+
+pub struct TLS {}
+
+impl TLS {
+    /// This method binds a new HTTP over TLS server based on configuration, the certificates
+    /// are loaded from the PEM files in `tls_certificates`
+    /// ```rust
+    /// use milstian_internet_framework::{Application, Config};
+    /// use milstian_internet_framework::response::tcp::http::{error, ResponderInterface};
+    /// use milstian_internet_framework::transport_layer;
+    /// let config = Config::from_env_args(vec![
+    ///     "".to_string(),
+    ///     "127.0.0.1".to_string(),
+    ///     "0".to_string(),
+    ///     "4".to_string(),
+    ///     "index.htm".to_string(),
+    ///     "./html/".to_string(),
+    ///     "404.htm".to_string(),
+    ///     "1024".to_string(),
+    /// ]).unwrap();
+    /// let application = Application::new(config);
+    /// let responders: Vec<Box<dyn ResponderInterface>> =
+    ///     vec![Box::new(error::Responder::new())];
+    /// // Expected fail since no certificates are configured
+    /// assert!(transport_layer::TLS::bind(&application, responders).is_err());
+    /// ```
+    pub fn bind(
+        application: &Application,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<Server, Error> {
+        let config = application.get_config();
+        let address = format!("{}:{}", &config.server_host, &config.server_port);
+        let mut listener = Listener::new(Endpoint::Tls(address), responders);
+        listener.proxy_protocol = config.server_proxy_protocol;
+        Server::bind(application, vec![listener])
+    }
+
+    /// This method creates a new HTTP over TLS application based on configuration and
+    /// serves it until shutdown, SIGTERM and SIGINT shut it down gracefully and SIGHUP
+    /// reloads the certificates
     /// ```rust,should_panic
     /// use milstian_internet_framework::{Application, Config};
     /// use milstian_internet_framework::response::tcp::http::{error, file_not_found, filesystem, ResponderInterface};
     /// use milstian_internet_framework::transport_layer;
     /// let config = Config::from_env().expect("Failed to get configuration from environment");
     /// let application = Application::new(config);
-    /// let responders: Vec<Box<ResponderInterface + Send>> = vec![
+    /// let responders: Vec<Box<dyn ResponderInterface>> = vec![
     ///     Box::new(filesystem::Responder::new()),
     ///     Box::new(file_not_found::Responder::new()),
     ///     Box::new(error::Responder::new()),
     /// ];
-    /// transport_layer::TCP::http(&application, responders)
+    /// transport_layer::TLS::http(&application, responders).unwrap();
+    /// ```
+    pub fn http(
+        application: &Application,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<(), Error> {
+        let server = TLS::bind(application, responders)?;
+        server.shutdown_on_signals()?;
+        server.run()
+    }
+}
+
+pub struct Unix {}
+
+impl Unix {
+    /// This method binds a new HTTP server to the Unix domain socket at `unix_socket_path`,
+    /// a socket file left behind by a stopped server is replaced and the socket gets the
+    /// configured mode and owner
+    /// ```rust
+    /// use milstian_internet_framework::{Application, Config};
+    /// use milstian_internet_framework::response::tcp::http::{error, ResponderInterface};
+    /// use milstian_internet_framework::transport_layer::{self, Address};
+    /// use std::env;
+    /// let mut config = Config::from_env_args(vec![
+    ///     "".to_string(),
+    ///     "127.0.0.1".to_string(),
+    ///     "0".to_string(),
+    ///     "4".to_string(),
+    ///     "index.htm".to_string(),
+    ///     "./html/".to_string(),
+    ///     "404.htm".to_string(),
+    ///     "1024".to_string(),
+    /// ]).unwrap();
+    /// let path = env::temp_dir().join("milstian-doctest.sock");
+    /// config.unix_socket_path = Some(path.clone());
+    /// let application = Application::new(config);
+    /// let responders: Vec<Box<dyn ResponderInterface>> =
+    ///     vec![Box::new(error::Responder::new())];
+    /// let server = transport_layer::Unix::bind(&application, responders).unwrap();
+    /// assert_eq!(server.get_address(), &Address::Unix(Some(path.clone())));
+    /// server.shutdown();
+    /// assert!(server.run().is_ok());
+    /// assert!(!path.exists());
     /// ```
-    // TODO Add example here that does not panic
-    pub fn http(application: &Application, responders: Vec<Box<ResponderInterface + Send>>) {
+    pub fn bind(
+        application: &Application,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<Server, Error> {
         let config = application.get_config();
-        let path = format!("{}:{}", &config.server_host, &config.server_port);
-        let listener = TcpListener::bind(&path);
-        application
-            .get_feedback()
-            .info(format!("Listening on HTTP requests via TCP to {}", &path));
-
-        match listener {
-            Ok(listener) => {
-                let pool = Pool::new(&application, config.server_limit);
-                loop {
-                    match listener.accept() {
-                        Ok((stream, socket)) => {
-                            application
-                                .get_feedback()
-                                .info(format!("Received new TCP stream from {}", socket));
-                            let application = application.clone();
-                            let responders = responders.clone();
-                            application
-                                .get_feedback()
-                                .info("Sending stream as HTTP job to pool".to_string());
-                            pool.execute(move || {
-                                Dispatcher::http(stream, socket, application, responders);
-                            });
-                        }
-                        Err(e) => {
-                            application
-                                .get_feedback()
-                                .error(format!("Failed to accept a incoming stream, error: {}", e));
-                        }
-                    }
-                }
+        match config.unix_socket_path {
+            Some(ref path) => {
+                let mut listener = Listener::new(Endpoint::Unix(path.clone()), responders);
+                listener.proxy_protocol = config.server_proxy_protocol;
+                Server::bind(application, vec![listener])
             }
-            Err(e) => {
-                panic!(format!(
-                    "Failed to bind to server and port: {}, error: {}",
-                    &path, e
-                ));
+            None => Err(Error::Bind(
+                String::new(),
+                io::Error::new(io::ErrorKind::InvalidInput, "No socket path is configured"),
+            )),
+        }
+    }
+
+    /// This method creates a new HTTP application on a Unix domain socket and serves it
+    /// until shutdown, SIGTERM and SIGINT shut it down gracefully
+    pub fn http(
+        application: &Application,
+        responders: Vec<Box<dyn ResponderInterface>>,
+    ) -> Result<(), Error> {
+        let server = Unix::bind(application, responders)?;
+        server.shutdown_on_signals()?;
+        server.run()
+    }
+
+    /// Remove a socket that no server accepts connections on anymore, a socket in use and
+    /// files that are not sockets are left alone
+    fn remove_stale_socket(path: &Path) -> io::Result<()> {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "Path exists and is not a socket",
+            ));
+        }
+        match UnixStream::connect(path) {
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "Socket is in use by another server",
+            )),
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn set_permissions(path: &Path, config: &Config) -> io::Result<()> {
+        if let Some(mode) = config.unix_socket_mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        if config.unix_socket_user.is_some() || config.unix_socket_group.is_some() {
+            chown(path, config.unix_socket_user, config.unix_socket_group)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use application_layer::http::request;
+    use application_layer::http::response;
+    use metrics::Timeout;
+    use response::tcp::http::{error, Context, HandlerInterface};
+    use signal_hook;
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::prelude::*;
+    use std::io::BufReader;
+    use std::process::{self, Stdio};
+    use std::time::Instant;
+    use {test_config, Config, OverloadPolicy, Transport};
+
+    fn get_application(port: u32) -> Application {
+        Application::new(get_config(port))
+    }
+
+    fn get_config(port: u32) -> Config {
+        Config {
+            server_limit: 2,
+            server_port: port,
+            server_shutdown_timeout: 1,
+            ..test_config()
+        }
+    }
+
+    #[test]
+    fn https_redirect() {
+        let mut config = get_config(0);
+        config.tls_port = 8443;
+        let application = Application::new(config);
+        let server = TCP::bind_https_redirect(&application).unwrap();
+        let address = server.get_local_addr().unwrap();
+        let handle = server.get_handle();
+        let thread = thread::spawn(move || server.run());
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .write_all(b"GET /about.htm HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(response.contains("Location: https://example.com:8443/about.htm\r\n"));
+        assert!(!response.contains("Strict-Transport-Security"));
+
+        handle.shutdown();
+        assert!(thread.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn bind() {
+        let application = get_application(0);
+        let server = TCP::bind(&application, vec![Box::new(error::Responder::new())]).unwrap();
+        let port = server.get_local_addr().unwrap().port();
+        assert_ne!(port, 0);
+
+        // Binding the same port again should fail with a typed error
+        let application = get_application(port as u32);
+        match TCP::bind(&application, vec![Box::new(error::Responder::new())]) {
+            Err(error) => assert!(error.is_address_in_use()),
+            Ok(_) => panic!("Expected bind to fail when port is in use"),
+        }
+
+        // Shutdown before run makes run return directly
+        server.shutdown();
+        assert!(server.get_handle().is_shutdown());
+        assert!(server.run().is_ok());
+    }
+
+    /// Serve one persistent request and return the client with the rest of the stream
+    fn request(address: SocketAddr) -> BufReader<TcpStream> {
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        let mut client = BufReader::new(client);
+        let mut line = String::new();
+        client.read_line(&mut line).unwrap();
+        assert_eq!(line, "HTTP/1.1 500 Internal Server Error\r\n");
+        while line != "\r\n" {
+            line.clear();
+            client.read_line(&mut line).unwrap();
+        }
+        client
+    }
+
+    #[test]
+    fn shutdown_drains_idle_connections() {
+        let application = get_application(0);
+        let server = TCP::bind(&application, vec![Box::new(error::Responder::new())]).unwrap();
+        let address = server.get_local_addr().unwrap();
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        // The persistent connection is closed as soon as shutdown is requested
+        let mut client = request(address);
+        let started = Instant::now();
+        handle.shutdown();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        assert!(server.join().unwrap().is_ok());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(TcpStream::connect(address).is_err());
+    }
+
+    #[test]
+    fn shutdown_on_signals() {
+        let application = get_application(0);
+        let server = TCP::bind(&application, vec![Box::new(error::Responder::new())]).unwrap();
+        server.shutdown_on_signals().unwrap();
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        signal_hook::low_level::raise(SIGTERM).unwrap();
+        assert!(server.join().unwrap().is_ok());
+        assert!(handle.is_shutdown());
+    }
+
+    #[test]
+    fn overload_service_unavailable() {
+        let mut config = get_config(0);
+        config.server_limit = 1;
+        config.server_queue_limit = 1;
+        config.server_overload_policy = OverloadPolicy::ServiceUnavailable(7);
+        let application = Application::new(config);
+        let server = TCP::bind(&application, vec![Box::new(error::Responder::new())]).unwrap();
+        let address = server.get_local_addr().unwrap();
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        // The first connection occupies the only worker, the second one waits in the queue
+        let busy = TcpStream::connect(address).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        let queued = TcpStream::connect(address).unwrap();
+        let started = Instant::now();
+        while handle.get_queue_depth() < 1 && started.elapsed() < Duration::from_secs(2) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(handle.get_queue_depth(), 1);
+
+        let mut rejected = TcpStream::connect(address).unwrap();
+        let mut response = String::new();
+        rejected.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("Retry-After: 7\r\n"));
+
+        drop(busy);
+        drop(queued);
+        handle.shutdown();
+        assert!(server.join().unwrap().is_ok());
+        assert_eq!(handle.get_queue_depth(), 0);
+    }
+
+    #[test]
+    fn evented_multiplexing() {
+        let mut config = get_config(0);
+        config.server_transport = Transport::Evented(2);
+        let application = Application::new(config);
+        let server = TCP::bind(&application, vec![Box::new(error::Responder::new())]).unwrap();
+        let address = server.get_local_addr().unwrap();
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        // Far more open connections than workers are served, each with pipelined requests
+        let mut clients: Vec<TcpStream> = (0..64)
+            .map(|_| TcpStream::connect(address).unwrap())
+            .collect();
+        for client in &mut clients {
+            client
+                .write_all(b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .unwrap();
+        }
+        for client in &mut clients {
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert_eq!(
+                response
+                    .matches("HTTP/1.1 500 Internal Server Error\r\n")
+                    .count(),
+                2
+            );
+            assert!(response.contains("Connection: keep-alive\r\n"));
+            assert!(response.contains("Connection: close\r\n"));
+        }
+
+        // Idle persistent connections are closed at shutdown
+        let mut client = request(address);
+        handle.shutdown();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        assert!(server.join().unwrap().is_ok());
+        assert_eq!(handle.get_queue_depth(), 0);
+    }
+
+    fn get_unix_config(test: &str) -> Config {
+        let path =
+            ::std::env::temp_dir().join(format!("milstian-{}-{}.sock", test, ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut config = get_config(0);
+        config.unix_socket_path = Some(path);
+        config
+    }
+
+    #[test]
+    fn unix_socket() {
+        let mut config = get_unix_config("unix-socket");
+        config.unix_socket_mode = Some(0o660);
+        let path = config.unix_socket_path.clone().unwrap();
+        let application = Application::new(config);
+        let server = Unix::bind(&application, vec![Box::new(error::Responder::new())]).unwrap();
+        assert_eq!(server.get_local_addr(), None);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+
+        // The socket file is removed when the server stops
+        handle.shutdown();
+        assert!(server.join().unwrap().is_ok());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unix_stale_socket() {
+        let config = get_unix_config("unix-stale");
+        let path = config.unix_socket_path.clone().unwrap();
+        let application = Application::new(config);
+
+        // A socket file without a server is replaced
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let server = Unix::bind(&application, vec![Box::new(error::Responder::new())]).unwrap();
+
+        // A socket with a server is not
+        match Unix::bind(&application, vec![Box::new(error::Responder::new())]) {
+            Err(error) => assert!(error.is_address_in_use()),
+            Ok(_) => panic!("Expected bind to fail when socket is in use"),
+        }
+        server.shutdown();
+        assert!(server.run().is_ok());
+
+        // Other files are never removed
+        File::create(&path).unwrap();
+        assert!(Unix::bind(&application, vec![Box::new(error::Responder::new())]).is_err());
+        assert!(path.is_file());
+        fs::remove_file(&path).unwrap();
+
+        let mut config = get_unix_config("unix-evented");
+        config.server_transport = Transport::Evented(1);
+        let application = Application::new(config);
+        assert!(Unix::bind(&application, vec![Box::new(error::Responder::new())]).is_err());
+    }
+
+    /// Make a request that closes the connection and return the response
+    fn request_once(address: SocketAddr, request: &[u8]) -> String {
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(request).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn listeners() {
+        let mut config = get_config(0);
+        config.tls_port = 8443;
+        let application = Application::new(config);
+        let port = TCP::bind(&application, vec![Box::new(error::Responder::new())])
+            .unwrap()
+            .get_local_addr()
+            .unwrap()
+            .port();
+
+        // IPv4 and IPv6 on the same port with their own responders and limits
+        let mut limited = Listener::new(
+            Endpoint::Tcp(format!("[::]:{}", port)),
+            vec![
+                Box::new(http::https_redirect::Responder::new()),
+                Box::new(error::Responder::new()),
+            ],
+        );
+        limited.tcp_limit = Some(64);
+        let server = Server::bind(
+            &application,
+            vec![
+                Listener::new(
+                    Endpoint::Tcp(format!("0.0.0.0:{}", port)),
+                    vec![Box::new(error::Responder::new())],
+                ),
+                limited,
+            ],
+        )
+        .unwrap();
+        let addresses = server.get_handle().get_addresses().to_vec();
+        assert_eq!(addresses.len(), 2);
+        let ipv4 = addresses[0].get_socket_addr().unwrap();
+        let ipv6 = addresses[1].get_socket_addr().unwrap();
+        assert_eq!(ipv4.port(), port);
+        assert_eq!(ipv6.port(), port);
+        assert!(ipv6.is_ipv6());
+        assert_eq!(server.listeners[0].application.get_config().tcp_limit, 1024);
+        assert_eq!(server.listeners[1].application.get_config().tcp_limit, 64);
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        let request = b"GET / HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n";
+        assert!(request_once(ipv4, request).starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(request_once(ipv6, request).starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+
+        // Every listener stops at shutdown
+        handle.shutdown();
+        assert!(server.join().unwrap().is_ok());
+        assert!(TcpStream::connect(ipv4).is_err());
+        assert!(TcpStream::connect(ipv6).is_err());
+    }
+
+    #[test]
+    fn listener_connection_limit() {
+        let mut config = get_config(0);
+        config.server_limit = 4;
+        config.server_overload_policy = OverloadPolicy::ServiceUnavailable(3);
+        let application = Application::new(config);
+        let mut listener = Listener::new(
+            Endpoint::Tcp("127.0.0.1:0".to_string()),
+            vec![Box::new(error::Responder::new())],
+        );
+        listener.connection_limit = Some(1);
+        let server = Server::bind(&application, vec![listener]).unwrap();
+        let address = server.get_local_addr().unwrap();
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        // The idle connection occupies the only connection of the listener, not the pool
+        let busy = request(address);
+        let mut rejected = TcpStream::connect(address).unwrap();
+        let mut response = String::new();
+        rejected.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("Retry-After: 3\r\n"));
+
+        drop(busy);
+        let started = Instant::now();
+        loop {
+            let response = request_once(address, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+            if response.starts_with("HTTP/1.1 500") {
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(2));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        handle.shutdown();
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    /// Responds with the address of the client
+    struct Client {}
+
+    impl ResponderInterface for Client {
+        fn matches(
+            &self,
+            _request_message: &request::Message,
+            _application: &Application,
+            _socket: &Address,
+        ) -> Option<Box<dyn HandlerInterface>> {
+            Some(Box::new(Client {}))
+        }
+    }
+
+    impl HandlerInterface for Client {
+        fn respond(
+            self: Box<Self>,
+            _request_message: &request::Message,
+            _context: &Context,
+            _application: &Application,
+            socket: &Address,
+        ) -> Result<response::Message, String> {
+            Ok(response::Message::new(
+                "HTTP/1.1".to_string(),
+                "200 OK".to_string(),
+                HashMap::new(),
+                socket.to_string().into_bytes(),
+            ))
+        }
+    }
+
+    #[test]
+    fn proxy_protocol() {
+        let mut config = get_config(0);
+        config.server_proxy_protocol = true;
+        config.server_trusted_proxies = vec!["203.0.113.7".parse().unwrap()];
+        let application = Application::new(config);
+        let server = TCP::bind(&application, vec![Box::new(Client {})]).unwrap();
+        let address = server.get_local_addr().unwrap();
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        let response = request_once(
+            address,
+            b"PROXY TCP4 192.0.2.1 10.0.0.1 56324 80\r\nGET / HTTP/1.1\r\nX-Forwarded-For: 198.51.100.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.ends_with("\r\n\r\n192.0.2.1:56324"));
+
+        // Forwarding header fields are resolved when the proxy of the PROXY protocol is trusted
+        let response = request_once(
+            address,
+            b"PROXY TCP4 203.0.113.7 10.0.0.1 56324 80\r\nGET / HTTP/1.1\r\nX-Forwarded-For: 198.51.100.1, 203.0.113.7\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.ends_with("\r\n\r\n198.51.100.1"));
+
+        // Health checks keep the address of the connection
+        let response = request_once(
+            address,
+            b"PROXY UNKNOWN\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.contains("\r\n\r\n127.0.0.1:"));
+
+        // Connections without a header are closed without a response
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        let _ = client.read_to_string(&mut response);
+        assert!(response.is_empty());
+
+        handle.shutdown();
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    /// Serves the socket passed by `inherited_socket` and `restart` in a process of its own
+    #[test]
+    #[ignore]
+    fn inherited_child() {
+        let address = match env::var("MILSTIAN_TEST_ADDRESS") {
+            Ok(address) => address,
+            Err(_) => return,
+        };
+        assert_eq!(env::var("LISTEN_FDS").unwrap(), "1");
+        assert_eq!(env::var("LISTEN_PID").unwrap(), process::id().to_string());
+        let application = get_application(0);
+        let server = Server::bind(
+            &application,
+            vec![Listener::new(
+                Endpoint::Tcp(address.clone()),
+                vec![Box::new(error::Responder::new())],
+            )],
+        )
+        .unwrap();
+        assert_eq!(server.get_address().to_string(), address);
+        assert!(env::var("LISTEN_FDS").is_err());
+        server.shutdown_on_signals().unwrap();
+        server.run().unwrap();
+    }
+
+    fn get_child_command(address: SocketAddr) -> Command {
+        let mut command = Command::new(env::current_exe().unwrap());
+        command
+            .args([
+                "transport_layer::tests::inherited_child",
+                "--exact",
+                "--ignored",
+            ])
+            .env("MILSTIAN_TEST_ADDRESS", address.to_string())
+            .stdout(Stdio::null());
+        command
+    }
+
+    /// Wait until the child serves a request and stop it
+    fn stop_child(address: SocketAddr, mut child: Child) {
+        let started = Instant::now();
+        loop {
+            if let Ok(mut client) = TcpStream::connect(address) {
+                client
+                    .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+                    .unwrap();
+                let mut response = String::new();
+                let _ = client.read_to_string(&mut response);
+                if response.starts_with("HTTP/1.1 500") {
+                    break;
+                }
             }
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
         }
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn inherited_socket() {
+        // The parent keeps its socket open, so the child can only serve it by inheriting it
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let child = activation::spawn(get_child_command(address), &[listener.as_raw_fd()]).unwrap();
+        stop_child(address, child);
+    }
+
+    #[test]
+    fn restart() {
+        let application = get_application(0);
+        let server = TCP::bind(&application, vec![Box::new(Client {})]).unwrap();
+        let address = server.get_local_addr().unwrap();
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+        let response = request_once(address, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let child = handle.restart(get_child_command(address)).unwrap();
+        assert!(server.join().unwrap().is_ok());
+        assert!(handle.restart(get_child_command(address)).is_err());
+        stop_child(address, child);
+    }
+
+    #[test]
+    fn evented_timeouts() {
+        let mut config = get_config(0);
+        config.server_header_timeout = 1;
+        config.server_transport = Transport::Evented(1);
+        let application = Application::new(config);
+        let server = TCP::bind(&application, vec![Box::new(error::Responder::new())]).unwrap();
+        let address = server.get_local_addr().unwrap();
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: loc").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert_eq!(application.get_metrics().get_timeouts(Timeout::Header), 1);
+
+        handle.shutdown();
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn evented_limits() {
+        let mut config = get_config(0);
+        config.server_transport = Transport::Evented(1);
+        let application = Application::new(config);
+        let server = TCP::bind(&application, vec![Box::new(error::Responder::new())]).unwrap();
+        let address = server.get_local_addr().unwrap();
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        // A body far beyond the TCP limit is refused before any of it is sent
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 10000000000\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+        // A chunked body beyond the TCP limit is received without being kept
+        let length = 2 * 1024 * 1024;
+        let mut client = TcpStream::connect(address).unwrap();
+        let mut writer = client.try_clone().unwrap();
+        let sender = thread::spawn(move || {
+            writer
+                .write_all(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n")
+                .unwrap();
+            writer
+                .write_all(format!("{:x}\r\n", length).as_bytes())
+                .unwrap();
+            writer.write_all(&vec![b'a'; length]).unwrap();
+            writer.write_all(b"\r\n0\r\n\r\n").unwrap();
+        });
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        sender.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+        // A head is rejected as soon as it exceeds the limit, without waiting for more
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        client
+            .write_all(&vec![b'a'; http::reader::HEAD_LIMIT])
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+
+        handle.shutdown();
+        assert!(server.join().unwrap().is_ok());
     }
 }
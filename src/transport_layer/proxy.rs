@@ -0,0 +1,374 @@
+//! # PROXY protocol
+//! Reverse proxies like HAProxy send the address of the client before the data of a
+//! connection, as a line of text in version 1 and as a binary header in version 2.
+
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::str;
+use std::time::{Duration, Instant};
+
+use response::tcp::http::reader::ReadTimeout;
+use response::tcp::Socket;
+
+/// Longest header of version 1 including the line break
+const MAX_V1_SIZE: usize = 107;
+
+/// Start of every header of version 2
+const V2_SIGNATURE: [u8; 12] = [
+    0x0d, 0x0a, 0x0d, 0x0a, 0x00, 0x0d, 0x0a, 0x51, 0x55, 0x49, 0x54, 0x0a,
+];
+
+/// A stream that the header can be looked at in without consuming what follows it
+pub trait Peek: ReadTimeout {
+    /// Read without removing the data from the stream
+    fn peek(&mut self, buffer: &mut [u8]) -> io::Result<usize>;
+}
+
+impl Peek for TcpStream {
+    fn peek(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        TcpStream::peek(self, buffer)
+    }
+}
+
+impl Peek for UnixStream {
+    fn peek(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        Socket::peek(&*self, buffer)
+    }
+}
+
+impl<T: AsRef<[u8]>> Peek for Cursor<T> {
+    fn peek(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let position = self.position();
+        let size = self.read(buffer)?;
+        self.set_position(position);
+        Ok(size)
+    }
+}
+
+/// Read the PROXY protocol header at the start of a stream, returns the address of the client
+/// or `None` for health checks of the proxy and clients of unknown protocols, the address of
+/// the connection applies to those. Nothing after the header is read and the whole header has
+/// to be received within the time-out.
+/// ```rust
+/// use milstian_internet_framework::transport_layer::proxy;
+/// use std::io::Cursor;
+/// let mut stream = Cursor::new(b"PROXY TCP4 203.0.113.7 10.0.0.1 56324 443\r\nGET /".to_vec());
+/// assert_eq!(
+///     proxy::read_header(&mut stream, None).unwrap(),
+///     Some("203.0.113.7:56324".parse().unwrap())
+/// );
+/// assert_eq!(stream.position(), 43);
+/// let mut stream = Cursor::new(b"PROXY UNKNOWN\r\n".to_vec());
+/// assert_eq!(proxy::read_header(&mut stream, None).unwrap(), None);
+/// assert!(
+///     proxy::read_header(&mut Cursor::new(b"GET / HTTP/1.1\r\n\r\n".to_vec()), None).is_err()
+/// );
+/// ```
+pub fn read_header<S: Peek>(
+    stream: &mut S,
+    timeout: Option<Duration>,
+) -> io::Result<Option<SocketAddr>> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    // Both versions are longer than the start that tells them apart
+    let mut start = [0; 6];
+    read_exact(stream, &mut start, deadline)?;
+    if &start == b"PROXY " {
+        read_v1(stream, deadline)
+    } else if start[..] == V2_SIGNATURE[..6] {
+        read_v2(stream, deadline)
+    } else {
+        Err(get_error(
+            "Stream does not start with a PROXY protocol header",
+        ))
+    }
+}
+
+/// Read the rest of a line like `PROXY TCP4 203.0.113.7 10.0.0.1 56324 443`
+fn read_v1<S: Peek>(stream: &mut S, deadline: Option<Instant>) -> io::Result<Option<SocketAddr>> {
+    // What has arrived is looked at and only consumed up to the line break, so nothing after
+    // the line is read
+    let mut line = Vec::with_capacity(MAX_V1_SIZE);
+    let mut peeked = [0; MAX_V1_SIZE];
+    loop {
+        let room = MAX_V1_SIZE - 6 - line.len();
+        if room == 0 {
+            return Err(get_error("PROXY protocol header is too long"));
+        }
+        set_remaining_timeout(stream, deadline)?;
+        let size = match stream.peek(&mut peeked[..room]) {
+            Ok(0) => return Err(get_closed_error()),
+            Ok(size) => size,
+            Err(ref error) if is_retryable(error, deadline) => continue,
+            Err(error) => return Err(error),
+        };
+
+        // The line break may start in what was consumed before
+        let searched = line.len().saturating_sub(1);
+        let consumed = line.len();
+        line.extend_from_slice(&peeked[..size]);
+        let end = line[searched..]
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .map(|position| searched + position + 2);
+        if let Some(end) = end {
+            line.truncate(end);
+        }
+        read_exact(stream, &mut peeked[..line.len() - consumed], deadline)?;
+        if end.is_some() {
+            break;
+        }
+    }
+    let line = match str::from_utf8(&line[..line.len() - 2]) {
+        Ok(line) => line,
+        Err(_) => return Err(get_error("PROXY protocol header is not text")),
+    };
+
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields[0] {
+        "UNKNOWN" => Ok(None),
+        "TCP4" | "TCP6" if fields.len() == 5 => {
+            let ip: IpAddr = match (fields[0], fields[1].parse()) {
+                ("TCP4", Ok(IpAddr::V4(ip))) => IpAddr::V4(ip),
+                ("TCP6", Ok(IpAddr::V6(ip))) => IpAddr::V6(ip),
+                _ => return Err(get_error("Invalid source address in PROXY protocol header")),
+            };
+            match fields[3].parse() {
+                Ok(port) => Ok(Some(SocketAddr::new(ip, port))),
+                Err(_) => Err(get_error("Invalid source port in PROXY protocol header")),
+            }
+        }
+        _ => Err(get_error("Invalid PROXY protocol header")),
+    }
+}
+
+/// Read the rest of a binary header after the first six bytes of its signature
+fn read_v2<S: Peek>(stream: &mut S, deadline: Option<Instant>) -> io::Result<Option<SocketAddr>> {
+    let mut header = [0; 10];
+    read_exact(stream, &mut header, deadline)?;
+    if header[..6] != V2_SIGNATURE[6..] {
+        return Err(get_error("Invalid PROXY protocol signature"));
+    }
+    let command = header[6];
+    let family = header[7];
+    let length = u16::from_be_bytes([header[8], header[9]]) as usize;
+    let mut addresses = vec![0; length];
+    read_exact(stream, &mut addresses, deadline)?;
+
+    match command {
+        // LOCAL is sent by the proxy itself, for example for health checks
+        0x20 => return Ok(None),
+        0x21 => {}
+        _ => return Err(get_error("Unsupported PROXY protocol version or command")),
+    }
+    match family {
+        // TCP over IPv4, source and destination address followed by their ports
+        0x11 if length >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // TCP over IPv6
+        0x21 if length >= 36 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        0x11 | 0x21 => Err(get_error("PROXY protocol addresses are too short")),
+        // Unspecified, datagram and Unix domain socket clients have no useful address
+        _ => Ok(None),
+    }
+}
+
+/// Fill the buffer from the stream before the deadline
+fn read_exact<S: Peek>(
+    stream: &mut S,
+    mut buffer: &mut [u8],
+    deadline: Option<Instant>,
+) -> io::Result<()> {
+    while !buffer.is_empty() {
+        set_remaining_timeout(stream, deadline)?;
+        match stream.read(buffer) {
+            Ok(0) => return Err(get_closed_error()),
+            Ok(size) => {
+                let rest = buffer;
+                buffer = &mut rest[size..];
+            }
+            Err(ref error) if is_retryable(error, deadline) => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+/// Let the next read block at most until the deadline
+fn set_remaining_timeout<S: Peek>(stream: &mut S, deadline: Option<Instant>) -> io::Result<()> {
+    if let Some(deadline) = deadline {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "PROXY protocol header was not received before time-out",
+            ));
+        }
+        stream.set_read_timeout(Some(deadline - now))?;
+    }
+    Ok(())
+}
+
+/// Whether a failed read is tried again, reads that timed out are until the deadline
+fn is_retryable(error: &io::Error, deadline: Option<Instant>) -> bool {
+    match error.kind() {
+        io::ErrorKind::Interrupted => true,
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => deadline.is_some(),
+        _ => false,
+    }
+}
+
+fn get_closed_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Stream was closed before end of PROXY protocol header",
+    )
+}
+
+fn get_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn get_v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(command);
+        header.push(family);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[test]
+    fn test_v1() {
+        let mut stream = Cursor::new(
+            b"PROXY TCP6 2001:db8::7 2001:db8::1 56324 443\r\nGET / HTTP/1.1\r\n\r\n".to_vec(),
+        );
+        assert_eq!(
+            read_header(&mut stream, None).unwrap(),
+            Some("[2001:db8::7]:56324".parse().unwrap())
+        );
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "GET / HTTP/1.1\r\n\r\n");
+
+        for invalid in [
+            &b"PROXY TCP4 2001:db8::7 10.0.0.1 56324 443\r\n"[..],
+            &b"PROXY TCP4 203.0.113.7 10.0.0.1 port 443\r\n"[..],
+            &b"PROXY TCP4 203.0.113.7\r\n"[..],
+            &b"PROXY UDP4 203.0.113.7 10.0.0.1 56324 443\r\n"[..],
+            &b"PROXY TCP4 203.0.113.7 10.0.0.1 56324 443"[..],
+        ]
+        .iter()
+        {
+            assert!(read_header(&mut Cursor::new(invalid.to_vec()), None).is_err());
+        }
+        let long = format!("PROXY UNKNOWN {}\r\n", "1".repeat(100));
+        assert!(read_header(&mut Cursor::new(long.into_bytes()), None).is_err());
+    }
+
+    #[test]
+    fn test_v2() {
+        let mut addresses = vec![203, 0, 113, 7, 10, 0, 0, 1];
+        addresses.extend_from_slice(&56324u16.to_be_bytes());
+        addresses.extend_from_slice(&443u16.to_be_bytes());
+        let mut header = get_v2(0x21, 0x11, &addresses);
+        header.extend_from_slice(b"GET /");
+        let mut stream = Cursor::new(header);
+        assert_eq!(
+            read_header(&mut stream, None).unwrap(),
+            Some("203.0.113.7:56324".parse().unwrap())
+        );
+        assert_eq!(stream.position(), 28);
+
+        let mut addresses = "2001:db8::7".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        addresses.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        addresses.extend_from_slice(&56324u16.to_be_bytes());
+        addresses.extend_from_slice(&443u16.to_be_bytes());
+        // Type-length-value fields after the addresses are skipped
+        addresses.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        let mut stream = Cursor::new(get_v2(0x21, 0x21, &addresses));
+        assert_eq!(
+            read_header(&mut stream, None).unwrap(),
+            Some("[2001:db8::7]:56324".parse().unwrap())
+        );
+        assert_eq!(stream.position(), 56);
+
+        assert_eq!(
+            read_header(&mut Cursor::new(get_v2(0x20, 0x00, &[])), None).unwrap(),
+            None
+        );
+        assert_eq!(
+            read_header(&mut Cursor::new(get_v2(0x21, 0x31, &[0; 216])), None).unwrap(),
+            None
+        );
+        assert!(read_header(&mut Cursor::new(get_v2(0x22, 0x11, &[0; 12])), None).is_err());
+        assert!(read_header(&mut Cursor::new(get_v2(0x21, 0x11, &[0; 4])), None).is_err());
+        let mut truncated = get_v2(0x21, 0x11, &[0; 12]);
+        truncated.truncate(20);
+        assert!(read_header(&mut Cursor::new(truncated), None).is_err());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+
+        // A client that drips the header is cut off by the time-out of the whole header
+        let dripper = thread::spawn(move || {
+            for byte in b"PROXY TCP4 203.0.113.7 10.0.0.1 56324 443\r\n".iter() {
+                if client.write_all(&[*byte]).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        let started = Instant::now();
+        match read_header(&mut stream, Some(Duration::from_millis(200))) {
+            Err(ref error) if error.kind() == io::ErrorKind::TimedOut => {}
+            other => panic!("Expected time-out, got {:?}", other),
+        }
+        assert!(started.elapsed() < Duration::from_millis(600));
+        drop(stream);
+        dripper.join().unwrap();
+
+        // A header that arrives in segments within the time-out is read without what follows
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let sender = thread::spawn(move || {
+            client.write_all(b"PROXY TCP4 203.0.113.7 ").unwrap();
+            thread::sleep(Duration::from_millis(20));
+            client.write_all(b"10.0.0.1 56324 443\r").unwrap();
+            thread::sleep(Duration::from_millis(20));
+            client.write_all(b"\nGET / HTTP/1.1\r\n\r\n").unwrap();
+        });
+        assert_eq!(
+            read_header(&mut stream, Some(Duration::from_secs(5))).unwrap(),
+            Some("203.0.113.7:56324".parse().unwrap())
+        );
+        sender.join().unwrap();
+        let mut rest = [0; 18];
+        stream.read_exact(&mut rest).unwrap();
+        assert_eq!(&rest, b"GET / HTTP/1.1\r\n\r\n");
+    }
+}
@@ -0,0 +1,588 @@
+//! # TLS for HTTPS
+//! Certificates are chosen by the SNI name of the client and can be reloaded from their files
+//! while the server is running.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use rustls_pemfile;
+
+use response::tcp::http::reader::ReadTimeout;
+use response::tcp::http::Responders;
+use response::tcp::{Dispatcher, Socket, Stream};
+use transport_layer::{Address, Error};
+use {Application, Config, TlsCertificate};
+
+/// The certificates of a server by name, reloadable while serving
+pub struct Certificates {
+    files: Vec<TlsCertificate>,
+    keys: RwLock<Keys>,
+    provider: Arc<CryptoProvider>,
+}
+
+struct Keys {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    default: Arc<CertifiedKey>,
+}
+
+impl Certificates {
+    /// Load the certificates of a configuration
+    pub fn load(config: &Config, provider: Arc<CryptoProvider>) -> Result<Certificates, Error> {
+        let keys = Certificates::read(&config.tls_certificates, &provider)?;
+        Ok(Certificates {
+            files: config.tls_certificates.clone(),
+            keys: RwLock::new(keys),
+            provider,
+        })
+    }
+
+    /// Read the certificate and key files again, new connections get the new certificates
+    /// and the old ones stay in use if reading fails
+    pub fn reload(&self) -> Result<(), Error> {
+        let keys = Certificates::read(&self.files, &self.provider)?;
+        match self.keys.write() {
+            Ok(mut current) => *current = keys,
+            Err(poisoned) => *poisoned.into_inner() = keys,
+        }
+        Ok(())
+    }
+
+    fn read(files: &[TlsCertificate], provider: &CryptoProvider) -> Result<Keys, Error> {
+        let mut by_name = HashMap::new();
+        let mut default = None;
+        for file in files {
+            let key = Arc::new(Certificates::read_key(file, provider)?);
+            by_name.insert(file.server_name.to_lowercase(), Arc::clone(&key));
+            default.get_or_insert(key);
+        }
+        match default {
+            Some(default) => Ok(Keys { by_name, default }),
+            None => Err(Error::Tls("No TLS certificates are configured".to_string())),
+        }
+    }
+
+    fn read_key(file: &TlsCertificate, provider: &CryptoProvider) -> Result<CertifiedKey, Error> {
+        let mut reader = Certificates::open(&file.certificate_file)?;
+        let chain = match rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>() {
+            Ok(chain) => chain,
+            Err(error) => {
+                return Err(Error::Tls(format!(
+                    "Failed to read certificate chain {}, error: {}",
+                    file.certificate_file, error
+                )))
+            }
+        };
+        if chain.is_empty() {
+            return Err(Error::Tls(format!(
+                "Found no certificates in {}",
+                file.certificate_file
+            )));
+        }
+
+        let mut reader = Certificates::open(&file.key_file)?;
+        let key = match rustls_pemfile::private_key(&mut reader) {
+            Ok(Some(key)) => key,
+            Ok(None) => {
+                return Err(Error::Tls(format!(
+                    "Found no private key in {}",
+                    file.key_file
+                )))
+            }
+            Err(error) => {
+                return Err(Error::Tls(format!(
+                    "Failed to read private key {}, error: {}",
+                    file.key_file, error
+                )))
+            }
+        };
+
+        CertifiedKey::from_der(chain, key, provider).map_err(|error| {
+            Error::Tls(format!(
+                "Failed to use certificate {} with key {}, error: {}",
+                file.certificate_file, file.key_file, error
+            ))
+        })
+    }
+
+    fn open(path: &str) -> Result<BufReader<File>, Error> {
+        match File::open(path) {
+            Ok(file) => Ok(BufReader::new(file)),
+            Err(error) => Err(Error::Tls(format!(
+                "Failed to open {}, error: {}",
+                path, error
+            ))),
+        }
+    }
+
+    /// Find the certificate for a server name, a wildcard matches the first label
+    fn get(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        let keys = match self.keys.read() {
+            Ok(keys) => keys,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(server_name) = server_name {
+            let server_name = server_name.to_lowercase();
+            if let Some(key) = keys.by_name.get(&server_name) {
+                return Arc::clone(key);
+            }
+            if let Some(position) = server_name.find('.') {
+                let wildcard = format!("*{}", &server_name[position..]);
+                if let Some(key) = keys.by_name.get(&wildcard) {
+                    return Arc::clone(key);
+                }
+            }
+        }
+        Arc::clone(&keys.default)
+    }
+}
+
+impl fmt::Debug for Certificates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Certificates")
+            .field("files", &self.files)
+            .finish()
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.get(client_hello.server_name()))
+    }
+}
+
+/// Build the TLS configuration of a server with its certificates and ALPN protocols
+pub fn get_server_config(config: &Config) -> Result<(Arc<ServerConfig>, Arc<Certificates>), Error> {
+    let provider = Arc::new(ring::default_provider());
+    let certificates = Arc::new(Certificates::load(config, Arc::clone(&provider))?);
+    let mut server_config =
+        match ServerConfig::builder_with_provider(provider).with_safe_default_protocol_versions() {
+            Ok(builder) => builder
+                .with_no_client_auth()
+                .with_cert_resolver(certificates.clone()),
+            Err(error) => return Err(Error::Tls(error.to_string())),
+        };
+    server_config.alpn_protocols = config
+        .tls_alpn_protocols
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+    Ok((Arc::new(server_config), certificates))
+}
+
+/// Serve HTTP over a new TLS connection, the handshake is done when the request is read
+pub fn http(
+    tls: Arc<ServerConfig>,
+    stream: TcpStream,
+    socket: Address,
+    application: Application,
+    responders: Responders,
+    shutdown: Arc<AtomicBool>,
+) {
+    match ServerConnection::new(tls) {
+        Ok(connection) => {
+            // The protocol agreed on with ALPN is known after the handshake
+            let mut stream = TlsStream::new(connection, stream);
+            let timeout = Dispatcher::get_timeout(application.get_config().server_header_timeout);
+            if let Err(error) = stream.handshake(timeout) {
+                application.get_feedback().error(format!(
+                    "TLS handshake with {} failed, error: {}",
+                    socket, error
+                ));
+                return;
+            }
+            Dispatcher::http(stream, socket, application, responders, shutdown)
+        }
+        Err(error) => application.get_feedback().error(format!(
+            "Failed to start TLS connection from {}, error: {}",
+            socket, error
+        )),
+    }
+}
+
+/// A TLS connection over a TCP socket
+pub struct TlsStream {
+    stream: StreamOwned<ServerConnection, TcpStream>,
+}
+
+impl TlsStream {
+    pub fn new(connection: ServerConnection, socket: TcpStream) -> TlsStream {
+        TlsStream {
+            stream: StreamOwned::new(connection, socket),
+        }
+    }
+
+    /// Complete the handshake, the time-out applies to the whole handshake so a client can not
+    /// hold the connection by sending it slowly
+    pub fn handshake(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        self.stream.sock.set_read_timeout(None)?;
+        loop {
+            while self.stream.conn.wants_write() {
+                self.stream.conn.write_tls(&mut self.stream.sock)?;
+            }
+            if !self.stream.conn.is_handshaking() {
+                return Ok(());
+            }
+
+            // Every read blocks at most until the deadline
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "TLS handshake was not completed before time-out",
+                    ));
+                }
+                self.stream.sock.set_read_timeout(Some(deadline - now))?;
+            }
+            match self.stream.conn.read_tls(&mut self.stream.sock) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Stream was closed during TLS handshake",
+                    ))
+                }
+                Ok(_) => {
+                    if let Err(error) = self.stream.conn.process_new_packets() {
+                        // Let the client know why the handshake failed
+                        let _ = self.stream.conn.write_tls(&mut self.stream.sock);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+                    }
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref error)
+                    if deadline.is_some()
+                        && (error.kind() == io::ErrorKind::WouldBlock
+                            || error.kind() == io::ErrorKind::TimedOut) => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self.stream.read(buffer) {
+            // Clients often close the socket without sending close_notify first
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+            result => result,
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.stream.write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl ReadTimeout for TlsStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.sock.set_read_timeout(timeout)
+    }
+}
+
+impl Stream for TlsStream {
+    fn get_socket(&self) -> &dyn Socket {
+        &self.stream.sock
+    }
+
+    fn has_pending(&mut self) -> bool {
+        match self.stream.conn.process_new_packets() {
+            Ok(state) => state.plaintext_bytes_to_read() > 0,
+            Err(_) => false,
+        }
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.stream.conn.send_close_notify();
+        self.stream.flush()?;
+        self.stream.sock.shutdown(Shutdown::Write)
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+
+    fn get_alpn_protocol(&self) -> Option<&[u8]> {
+        self.stream.conn.alpn_protocol()
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        // Let the client know the response was not truncated
+        self.stream.conn.send_close_notify();
+        let _ = self.stream.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rcgen;
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use rustls::{ClientConfig, ClientConnection, RootCertStore};
+    use std::convert::TryFrom;
+    use std::env;
+    use std::fs;
+    use std::net::{SocketAddr, TcpListener};
+    use std::path::{Path, PathBuf};
+    use std::thread;
+
+    use response::tcp::http::error;
+    use response::tcp::http2;
+    use response::tcp::http2::frame::{self, Frame};
+    use response::tcp::http2::hpack;
+    use transport_layer::TLS;
+    use {test_config, Hsts, Transport};
+
+    fn get_directory(test: &str) -> PathBuf {
+        let directory =
+            env::temp_dir().join(format!("milstian-tls-{}-{}", test, ::std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    /// Write a new self-signed certificate for a name, returns its DER encoding too
+    fn write_certificate(
+        directory: &Path,
+        name: &str,
+    ) -> (TlsCertificate, CertificateDer<'static>) {
+        let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let file_name = name.replace('*', "wildcard");
+        let certificate_file = directory.join(format!("{}.crt", file_name));
+        let key_file = directory.join(format!("{}.key", file_name));
+        fs::write(&certificate_file, generated.cert.pem()).unwrap();
+        fs::write(&key_file, generated.signing_key.serialize_pem()).unwrap();
+        (
+            TlsCertificate {
+                server_name: name.to_string(),
+                certificate_file: certificate_file.to_str().unwrap().to_string(),
+                key_file: key_file.to_str().unwrap().to_string(),
+            },
+            generated.cert.der().clone(),
+        )
+    }
+
+    fn get_config(certificates: Vec<TlsCertificate>) -> Config {
+        let mut config = test_config();
+        config.server_shutdown_timeout = 1;
+        config.tls_certificates = certificates;
+        config
+    }
+
+    /// Connect with SNI and offer HTTP/2 and HTTP/1.1 with ALPN
+    fn connect(
+        address: SocketAddr,
+        server_name: &str,
+        root: &CertificateDer<'static>,
+    ) -> StreamOwned<ClientConnection, TcpStream> {
+        let mut roots = RootCertStore::empty();
+        roots.add(root.clone()).unwrap();
+        let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+        let connection = ClientConnection::new(Arc::new(config), server_name).unwrap();
+        StreamOwned::new(connection, TcpStream::connect(address).unwrap())
+    }
+
+    /// Make a request with SNI, returns the certificate of the server, the protocol agreed
+    /// on with ALPN and the response
+    fn request(
+        address: SocketAddr,
+        server_name: &str,
+        root: &CertificateDer<'static>,
+    ) -> (CertificateDer<'static>, Option<Vec<u8>>, String) {
+        let mut stream = connect(address, server_name, root);
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let certificate = stream.conn.peer_certificates().unwrap()[0].clone();
+        let protocol = stream
+            .conn
+            .alpn_protocol()
+            .map(|protocol| protocol.to_vec());
+        (certificate, protocol, response)
+    }
+
+    #[test]
+    fn sni_alpn_and_reload() {
+        let directory = get_directory("sni");
+        let (localhost, localhost_der) = write_certificate(&directory, "localhost");
+        let (example, example_der) = write_certificate(&directory, "example.test");
+        let (wildcard, wildcard_der) = write_certificate(&directory, "*.wildcard.test");
+        let mut config = get_config(vec![localhost, example.clone(), wildcard]);
+        config.tls_hsts = Some(Hsts {
+            max_age: 31536000,
+            include_subdomains: true,
+            preload: false,
+        });
+        let application = Application::new(config);
+        let server = TLS::bind(&application, vec![Box::new(error::Responder::new())]).unwrap();
+        let address = server.get_local_addr().unwrap();
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        let (certificate, protocol, response) = request(address, "localhost", &localhost_der);
+        assert_eq!(certificate, localhost_der);
+        assert_eq!(protocol, Some(b"http/1.1".to_vec()));
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(
+            response.contains("Strict-Transport-Security: max-age=31536000; includeSubDomains\r\n")
+        );
+
+        let (certificate, _, _) = request(address, "example.test", &example_der);
+        assert_eq!(certificate, example_der);
+        let (certificate, _, _) = request(address, "www.wildcard.test", &wildcard_der);
+        assert_eq!(certificate, wildcard_der);
+
+        // New connections get the renewed certificate after a reload
+        let (renewed, renewed_der) = write_certificate(&directory, "example.test");
+        assert_eq!(renewed, example);
+        handle.reload_certificates().unwrap();
+        let (certificate, _, response) = request(address, "example.test", &renewed_der);
+        assert_eq!(certificate, renewed_der);
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+
+        // A failed reload keeps the current certificates
+        fs::write(&example.key_file, "").unwrap();
+        assert!(handle.reload_certificates().is_err());
+        let (certificate, _, _) = request(address, "example.test", &renewed_der);
+        assert_eq!(certificate, renewed_der);
+
+        handle.shutdown();
+        assert!(server.join().unwrap().is_ok());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn alpn_http2() {
+        let directory = get_directory("http2");
+        let (localhost, localhost_der) = write_certificate(&directory, "localhost");
+        let mut config = get_config(vec![localhost]);
+        config.tls_alpn_protocols = vec!["h2".to_string(), "http/1.1".to_string()];
+        let application = Application::new(config);
+        let server = TLS::bind(&application, vec![Box::new(error::Responder::new())]).unwrap();
+        let address = server.get_local_addr().unwrap();
+        let handle = server.get_handle();
+        let server = thread::spawn(move || server.run());
+
+        let mut stream = connect(address, "localhost", &localhost_der);
+        let mut output = http2::PREFACE.to_vec();
+        Frame::settings(&[]).write_to(&mut output);
+        let fields = vec![
+            (":method".to_string(), "GET".to_string()),
+            (":scheme".to_string(), "https".to_string()),
+            (":path".to_string(), "/".to_string()),
+        ];
+        Frame::new(
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM,
+            1,
+            hpack::encode(&fields),
+        )
+        .write_to(&mut output);
+        stream.write_all(&output).unwrap();
+        assert_eq!(stream.conn.alpn_protocol(), Some(&b"h2"[..]));
+
+        let mut input = Vec::new();
+        let mut buffer = [0; 4096];
+        let headers = loop {
+            if let Some((frame, used)) = Frame::parse(&input, frame::MAX_MAX_SIZE).unwrap() {
+                input.drain(..used);
+                if frame.kind == frame::HEADERS {
+                    break frame;
+                }
+                continue;
+            }
+            let read_size = stream.read(&mut buffer).unwrap();
+            assert!(read_size > 0);
+            input.extend_from_slice(&buffer[..read_size]);
+        };
+        assert_eq!(headers.stream_id, 1);
+        let fields = hpack::Decoder::new(16384).decode(&headers.payload).unwrap();
+        assert_eq!(fields[0], (b":status".to_vec(), b"500".to_vec()));
+
+        handle.shutdown();
+        assert!(server.join().unwrap().is_ok());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn bind_errors() {
+        let directory = get_directory("errors");
+        let (certificate, _) = write_certificate(&directory, "localhost");
+
+        let mut missing = certificate.clone();
+        missing.key_file = directory.join("missing.key").to_str().unwrap().to_string();
+        let application = Application::new(get_config(vec![missing]));
+        match TLS::bind(&application, vec![Box::new(error::Responder::new())]) {
+            Err(Error::Tls(error)) => assert!(error.contains("missing.key")),
+            _ => panic!("Expected missing key to fail"),
+        }
+
+        let mut config = get_config(vec![certificate]);
+        config.server_transport = Transport::Evented(1);
+        let application = Application::new(config);
+        match TLS::bind(&application, vec![Box::new(error::Responder::new())]) {
+            Err(Error::Tls(_)) => {}
+            _ => panic!("Expected evented transport to fail"),
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn handshake_deadline() {
+        let directory = get_directory("handshake");
+        let (localhost, _) = write_certificate(&directory, "localhost");
+        let (server_config, _) = get_server_config(&get_config(vec![localhost])).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        // A client that sends a byte of its hello at a time is stopped at the deadline
+        let client = thread::spawn(move || {
+            let _ = client.write_all(&[0x16, 0x03, 0x01, 0x02, 0x00]);
+            for _ in 0..30 {
+                thread::sleep(Duration::from_millis(100));
+                if client.write_all(&[0x01]).is_err() {
+                    break;
+                }
+            }
+        });
+        let (socket, _) = listener.accept().unwrap();
+        let mut stream = TlsStream::new(ServerConnection::new(server_config).unwrap(), socket);
+        let started = Instant::now();
+        let error = stream
+            .handshake(Some(Duration::from_millis(500)))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        drop(stream);
+        client.join().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,7 +1,53 @@
 extern crate milstian_internet_framework;
 
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::thread;
+
+use milstian_internet_framework::response::tcp::http::{
+    error, file_not_found, filesystem, ResponderInterface,
+};
+use milstian_internet_framework::{Application, Config};
+
 #[test]
 fn tcp_http() {
-    // TODO Create application in one thread
-    // TODO Make TCP requests to the application for another thread
+    let config = Config::from_env_args(vec![
+        String::from("ignore this"),
+        String::from("127.0.0.1"),
+        String::from("0"),
+        String::from("2"),
+        String::from("index.htm"),
+        String::from("./html/"),
+        String::from("404.htm"),
+        String::from("1024"),
+    ]).unwrap();
+    let responders: Vec<Box<dyn ResponderInterface>> = vec![
+        Box::new(filesystem::Responder::new()),
+        Box::new(file_not_found::Responder::new()),
+        Box::new(error::Responder::new()),
+    ];
+    let server = Application::new(config).tcp_http_bind(responders).unwrap();
+    let address = server.get_local_addr().unwrap();
+    let handle = server.get_handle();
+    let server = thread::spawn(move || server.run());
+
+    let mut client = TcpStream::connect(address).unwrap();
+    client
+        .write_all(b"GET /index.htm HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("<html"));
+
+    let mut client = TcpStream::connect(address).unwrap();
+    client
+        .write_all(b"GET /missing.htm HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 404 File Not Found\r\n"));
+
+    handle.shutdown();
+    assert!(server.join().unwrap().is_ok());
 }